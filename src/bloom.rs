@@ -0,0 +1,81 @@
+use crate::sketch_schemes::hashing::mix;
+use crate::sketch_schemes::ItemHash;
+
+/// A simple Bloom filter for kmers that have already been hashed (so the
+/// bit positions are derived from the kmer hash instead of rehashing the
+/// kmer itself).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    n_hashes: u8,
+}
+
+impl BloomFilter {
+    /// Create an empty filter using (approximately) `size` bytes of memory
+    pub fn new(size: usize, n_hashes: u8) -> Self {
+        BloomFilter {
+            bits: vec![0; usize::max(1, size / 8)],
+            n_hashes: u8::max(1, n_hashes),
+        }
+    }
+
+    /// The memory used by the filter (in bytes)
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    #[inline]
+    fn positions(&self, hash: ItemHash) -> impl Iterator<Item = usize> {
+        // the kmer hashes we get are biased towards small values (e.g. for
+        // scaled sketches) so remix them before using double hashing (see
+        // Kirsch & Mitzenmacher, "Less Hashing, Same Performance", 2006)
+        let h1 = mix(hash);
+        let h2 = mix(h1) | 1;
+        let n_bits = self.bits.len() as u64 * 64;
+        (0..u64::from(self.n_hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % n_bits) as usize)
+    }
+
+    /// Add a hash to the filter, returning whether it was (probably) already
+    /// present.
+    pub fn insert(&mut self, hash: ItemHash) -> bool {
+        let mut present = true;
+        for pos in self.positions(hash) {
+            let (word, bit) = (pos / 64, 1 << (pos % 64));
+            if self.bits[word] & bit == 0 {
+                present = false;
+                self.bits[word] |= bit;
+            }
+        }
+        present
+    }
+
+    /// Check if a hash is (probably) present in the filter
+    pub fn contains(&self, hash: ItemHash) -> bool {
+        self.positions(hash)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom = BloomFilter::new(1 << 16, 4);
+        assert_eq!(bloom.size(), 1 << 16);
+        for hash in 0..5000 {
+            assert!(!bloom.insert(hash * 7));
+        }
+        for hash in 0..5000 {
+            assert!(bloom.contains(hash * 7));
+            assert!(bloom.insert(hash * 7));
+        }
+        // with ~100 bits per item false positives should be very rare
+        let false_positives = (0..5000)
+            .filter(|hash| bloom.contains(hash * 7 + 1))
+            .count();
+        assert!(false_positives < 5);
+    }
+}
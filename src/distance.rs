@@ -1,37 +1,59 @@
 use std::cmp::Ordering;
+use std::mem::discriminant;
 
 use ndarray::Array2;
 
 use crate::serialization::{Sketch, SketchDistance};
-use crate::sketch_schemes::KmerCount;
+use crate::sketch_schemes::{hyperloglog, KmerCount, SketchParams};
 
 pub fn distance(
     query_sketch: &Sketch,
     ref_sketch: &Sketch,
     old_mode: bool,
 ) -> Result<SketchDistance, &'static str> {
-    let distances = if old_mode {
-        old_distance(&query_sketch.hashes, &ref_sketch.hashes)
-    } else {
-        // since we always examine to the lowest of the sketch maxima, a
-        // min_scale of 0 is a noop; otherwise we only set a scale if both of
-        // the sketches are scaled (there may be a slight improvement in
-        // comparing a unscaled "higher range" sketch to a scaled lower range
-        // using the scale, but that makes things more complicated because we
-        // need two scale values, etc)
-        let mut min_scale = 0.;
-        if let Some(scale1) = query_sketch.sketch_params.hash_info().3 {
-            if let Some(scale2) = ref_sketch.sketch_params.hash_info().3 {
-                min_scale = f64::min(scale1, scale2);
+    let distances = match (&query_sketch.sketch_params, &ref_sketch.sketch_params) {
+        (SketchParams::HyperLogLog { .. }, SketchParams::HyperLogLog { precision, .. }) => {
+            if query_sketch
+                .sketch_params
+                .check_compatibility(&ref_sketch.sketch_params)
+                .is_some()
+            {
+                return Err("HyperLogLog sketches have incompatible parameters");
+            }
+            hyperloglog::raw_distance(&query_sketch.hashes, &ref_sketch.hashes, *precision)
+        }
+        (SketchParams::HyperLogLog { .. }, _) | (_, SketchParams::HyperLogLog { .. }) => {
+            return Err("HyperLogLog sketches can only be compared to other HyperLogLog sketches");
+        }
+        (SketchParams::Mash { .. }, SketchParams::Scaled { .. })
+        | (SketchParams::Scaled { .. }, SketchParams::Mash { .. }) => {
+            let max_hash = u64::min(hash_coverage(query_sketch), hash_coverage(ref_sketch));
+            mixed_distance(&query_sketch.hashes, &ref_sketch.hashes, max_hash)
+        }
+        _ if old_mode => old_distance(&query_sketch.hashes, &ref_sketch.hashes),
+        _ => {
+            // since we always examine to the lowest of the sketch maxima, a
+            // min_scale of 0 is a noop; otherwise we only set a scale if both of
+            // the sketches are scaled (there may be a slight improvement in
+            // comparing a unscaled "higher range" sketch to a scaled lower range
+            // using the scale, but that makes things more complicated because we
+            // need two scale values, etc)
+            let mut min_scale = 0.;
+            if let Some(scale1) = query_sketch.sketch_params.hash_info().3 {
+                if let Some(scale2) = ref_sketch.sketch_params.hash_info().3 {
+                    min_scale = f64::min(scale1, scale2);
+                }
             }
+            raw_distance(&query_sketch.hashes, &ref_sketch.hashes, min_scale)
         }
-        raw_distance(&query_sketch.hashes, &ref_sketch.hashes, min_scale)
     };
 
     let containment = distances.0;
     let jaccard = distances.1;
     let common_hashes = distances.2;
     let total_hashes = distances.3;
+    let mixed_types =
+        discriminant(&query_sketch.sketch_params) != discriminant(&ref_sketch.sketch_params);
     let k = query_sketch.sketch_params.k() as f64;
     let mash_distance: f64 = -1.0 * ((2.0 * jaccard) / (1.0 + jaccard)).ln() / k;
     Ok(SketchDistance {
@@ -40,6 +62,7 @@ pub fn distance(
         mash_distance: f64::min(1f64, f64::max(0f64, mash_distance)),
         common_hashes,
         total_hashes,
+        mixed_types,
         query: query_sketch.name.to_string(),
         reference: ref_sketch.name.to_string(),
     })
@@ -124,9 +147,95 @@ pub fn raw_distance(
     (containment, jaccard, common, total)
 }
 
+/// The hash up to which a sketch holds every hash from its input, i.e. the
+/// largest hash for a full Mash sketch or the scale cutoff for a scaled one
+/// (sketches that aren't full hold everything).
+///
+/// A scaled sketch with `kmers_to_sketch` or more hashes may have dropped
+/// hashes above the cutoff (so it can be a bottom-k sample of a small
+/// input), and filtering on counts can drop hashes from anywhere in either
+/// kind of sketch, so these are only trusted up to their last hash.
+fn hash_coverage(sketch: &Sketch) -> u64 {
+    let last_hash = sketch
+        .hashes
+        .last()
+        .map(|kmer_count| kmer_count.hash)
+        .unwrap_or(0);
+    let filtered = sketch.filter_params.filter_on == Some(true);
+    match sketch.sketch_params {
+        SketchParams::Mash { final_size, .. } if filtered || sketch.hashes.len() >= final_size => {
+            last_hash
+        }
+        SketchParams::Scaled {
+            kmers_to_sketch,
+            scale,
+            ..
+        } if filtered || sketch.hashes.len() >= kmers_to_sketch => {
+            u64::min(last_hash, u64::max_value() / scale.recip() as u64)
+        }
+        _ => u64::max_value(),
+    }
+}
+
+/// Estimates set statistics (as in `raw_distance`) between a Mash-style
+/// sketch and a scaled sketch.
+///
+/// Both kinds of sketch hold every hash of their input up to some point (see
+/// `hash_coverage`), so all of the hashes in both sketches up to the lower
+/// of these two points, `max_hash`, are equivalent to a scaled sketch of
+/// each input with that cutoff. The statistics are calculated exactly over
+/// that range (which is then an unbiased sample of both inputs); hashes
+/// above `max_hash` are ignored.
+///
+/// The slices must be sorted by hash (as in `raw_distance`).
+pub fn mixed_distance(
+    query_hashes: &[KmerCount],
+    ref_hashes: &[KmerCount],
+    max_hash: u64,
+) -> (f64, f64, u64, u64) {
+    let in_range = |hashes: &[KmerCount]| {
+        hashes
+            .iter()
+            .take_while(|kmer_count| kmer_count.hash <= max_hash)
+            .count()
+    };
+    let query_hashes = &query_hashes[..in_range(query_hashes)];
+    let ref_hashes = &ref_hashes[..in_range(ref_hashes)];
+
+    let mut i: usize = 0;
+    let mut j: usize = 0;
+    let mut common: u64 = 0;
+    while let (Some(query), Some(refer)) = (query_hashes.get(i), ref_hashes.get(j)) {
+        match query.hash.cmp(&refer.hash) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    let containment = if ref_hashes.is_empty() {
+        0.
+    } else {
+        common as f64 / ref_hashes.len() as f64
+    };
+    let total = (query_hashes.len() + ref_hashes.len()) as u64 - common;
+    let jaccard: f64 = if total == 0 {
+        1.
+    } else {
+        common as f64 / total as f64
+    };
+
+    (containment, jaccard, common, total)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sketch_schemes::HashFunction;
     use proptest::prelude::*;
 
     fn kc(arr: &[u64]) -> Vec<KmerCount> {
@@ -209,6 +318,130 @@ mod tests {
         assert_eq!(total, 3);
     }
 
+    #[test]
+    fn test_mixed_distance() {
+        // everything up to the cutoff counts, even if one sketch runs out
+        let (cont, jac, com, total) = mixed_distance(&kc(&[5, 10, 15]), &kc(&[5]), 18);
+        assert_eq!(cont, 1. / 1.);
+        assert_eq!(jac, 1. / 3.);
+        assert_eq!(com, 1);
+        assert_eq!(total, 3);
+
+        // but nothing past it
+        let (cont, jac, com, total) =
+            mixed_distance(&kc(&[5, 10, 20, 30]), &kc(&[5, 10, 15, 30]), 18);
+        assert_eq!(cont, 2. / 3.);
+        assert_eq!(jac, 2. / 3.);
+        assert_eq!(com, 2);
+        assert_eq!(total, 3);
+
+        assert_eq!((0., 1., 0, 0), mixed_distance(&kc(&[20]), &kc(&[]), 18));
+    }
+
+    #[test]
+    fn test_mixed_type_distance() {
+        let make_sketch = |hashes: &[u64], sketch_params: SketchParams| Sketch {
+            name: "test".to_string(),
+            seq_length: 100,
+            num_valid_kmers: 100,
+            comment: "".to_string(),
+            hashes: kc(hashes),
+            filter_params: Default::default(),
+            sketch_params,
+            provenance: None,
+        };
+        // this covers hashes up to 30 (the last one)...
+        let mash = make_sketch(
+            &[5, 10, 20, 30],
+            SketchParams::Mash {
+                kmers_to_sketch: 4,
+                final_size: 4,
+                no_strict: false,
+                kmer_length: 21,
+                hash_seed: 0,
+                hash_function: HashFunction::MurmurHash3,
+            },
+        );
+        // ...and this to 18
+        let scaled = make_sketch(
+            &[5, 10, 15],
+            SketchParams::Scaled {
+                kmers_to_sketch: 0,
+                kmer_length: 21,
+                scale: 1e-18,
+                hash_seed: 0,
+                hash_function: HashFunction::MurmurHash3,
+            },
+        );
+        let dist = distance(&mash, &scaled, false).unwrap();
+        assert!(dist.mixed_types);
+        assert_eq!(dist.common_hashes, 2);
+        assert_eq!(dist.total_hashes, 3);
+        assert_eq!(dist.containment, 2. / 3.);
+
+        let dist = distance(&mash, &mash, false).unwrap();
+        assert!(!dist.mixed_types);
+        assert_eq!(dist.jaccard, 1.);
+    }
+
+    #[test]
+    fn test_hash_coverage() {
+        let make_sketch = |hashes: &[u64], sketch_params: SketchParams| Sketch {
+            name: "test".to_string(),
+            seq_length: 100,
+            num_valid_kmers: 100,
+            comment: "".to_string(),
+            hashes: kc(hashes),
+            filter_params: Default::default(),
+            sketch_params,
+            provenance: None,
+        };
+        let mash_params = |kmers_to_sketch, final_size| SketchParams::Mash {
+            kmers_to_sketch,
+            final_size,
+            no_strict: true,
+            kmer_length: 21,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        };
+        let scaled_params = |kmers_to_sketch| SketchParams::Scaled {
+            kmers_to_sketch,
+            kmer_length: 21,
+            scale: 1e-18,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        };
+
+        // a full Mash sketch covers up to its last hash
+        assert_eq!(hash_coverage(&make_sketch(&[5, 10], mash_params(2, 2))), 10);
+        // and one that isn't full holds everything...
+        let mut mash = make_sketch(&[5, 10], mash_params(4, 3));
+        assert_eq!(hash_coverage(&mash), u64::max_value());
+        // ...unless filtering could have removed hashes
+        mash.filter_params.filter_on = Some(true);
+        assert_eq!(hash_coverage(&mash), 10);
+
+        // scaled sketches that haven't hit `kmers_to_sketch` hold everything
+        assert_eq!(
+            hash_coverage(&make_sketch(&[5, 10, 25], scaled_params(4))),
+            u64::max_value()
+        );
+        // but once they have they could be a bottom-k sample of a small
+        // input (so they're only complete up to the scale cutoff, 18)...
+        assert_eq!(
+            hash_coverage(&make_sketch(&[5, 10, 25], scaled_params(3))),
+            18
+        );
+        assert_eq!(
+            hash_coverage(&make_sketch(&[5, 10, 15, 17], scaled_params(3))),
+            17
+        );
+        // ...as are filtered ones
+        let mut scaled = make_sketch(&[5, 10, 25], scaled_params(4));
+        scaled.filter_params.filter_on = Some(true);
+        assert_eq!(hash_coverage(&scaled), 18);
+    }
+
     /// This is a straight transcription of the calculation used in Mash
     /// itself; now used for compatibility testing
     fn mash_paper_distance(sketch2: &[KmerCount], sketch1: &[KmerCount]) -> (f64, f64, u64, u64) {
@@ -282,14 +515,14 @@ mod tests {
         use crate::sketch_schemes::scaled::ScaledSketcher;
         use crate::sketch_schemes::SketchScheme;
 
-        let mut queue1 = ScaledSketcher::new(3, 0.001, 2, 42);
+        let mut queue1 = ScaledSketcher::new(3, 0.001, 2, 42, HashFunction::MurmurHash3);
         queue1.push(b"ca", 0);
         queue1.push(b"cc", 1);
         queue1.push(b"ac", 0);
         queue1.push(b"ac", 1);
         let array1 = queue1.to_sketch();
 
-        let mut queue2 = ScaledSketcher::new(3, 0.001, 2, 42);
+        let mut queue2 = ScaledSketcher::new(3, 0.001, 2, 42, HashFunction::MurmurHash3);
         queue2.push(b"ca", 0);
         queue2.push(b"cc", 1);
         queue2.push(b"ac", 0);
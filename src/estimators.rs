@@ -0,0 +1,318 @@
+use std::str::FromStr;
+
+use failure::{bail, format_err};
+
+use crate::serialization::Sketch;
+use crate::sketch_schemes::{hyperloglog, ItemHash, KmerCount, SketchParams};
+use crate::Result;
+
+/// The size of the hash space (2 ** 64) as a float
+pub const HASH_SPACE: f64 = 18_446_744_073_709_551_616.;
+
+/// Number of register index bits used by default for HyperLogLog estimates
+pub const DEFAULT_HLL_PRECISION: u8 = 12;
+
+/// The z-score used for the error bounds on estimates (~95% interval)
+const Z_95: f64 = 1.96;
+
+/// A cardinality estimate with (approximately 95%) error bounds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct CardinalityEstimate {
+    pub estimate: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl CardinalityEstimate {
+    fn exact(count: usize) -> Self {
+        CardinalityEstimate {
+            estimate: count as f64,
+            lower: count as f64,
+            upper: count as f64,
+        }
+    }
+
+    fn from_std_err(estimate: f64, std_err: f64) -> Self {
+        CardinalityEstimate {
+            estimate,
+            lower: f64::max(0., estimate - Z_95 * std_err),
+            upper: estimate + Z_95 * std_err,
+        }
+    }
+}
+
+/// The methods available for estimating the number of unique kmers a sketch
+/// was drawn from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardinalityMethod {
+    Kmv,
+    Scaled,
+    HyperLogLog,
+}
+
+impl CardinalityMethod {
+    /// The most appropriate estimator for sketches made with these parameters
+    pub fn default_for(sketch_params: &SketchParams) -> Self {
+        match sketch_params {
+            SketchParams::Scaled { .. } => CardinalityMethod::Scaled,
+            SketchParams::HyperLogLog { .. } => CardinalityMethod::HyperLogLog,
+            _ => CardinalityMethod::Kmv,
+        }
+    }
+}
+
+impl FromStr for CardinalityMethod {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "kmv" => CardinalityMethod::Kmv,
+            "scaled" => CardinalityMethod::Scaled,
+            "hll" | "hyperloglog" => CardinalityMethod::HyperLogLog,
+            _ => bail!("{} is not a known cardinality estimator", s),
+        })
+    }
+}
+
+/// Estimate the number of unique kmers in the sequence(s) a sketch was made
+/// from using the provided method (or the default one for the sketch's
+/// parameters if `None`).
+pub fn estimate_cardinality(
+    sketch: &Sketch,
+    method: Option<CardinalityMethod>,
+) -> Result<CardinalityEstimate> {
+    // `AllCounts` sketches hold every kmer so the size is exact (and their
+    // "hashes" are kmer indices so none of the estimators below would apply)
+    if let SketchParams::AllCounts { .. } = sketch.sketch_params {
+        return Ok(CardinalityEstimate::exact(sketch.hashes.len()));
+    }
+
+    let method = method.unwrap_or_else(|| CardinalityMethod::default_for(&sketch.sketch_params));
+    // HyperLogLog sketches just hold registers so we can only use those
+    if let SketchParams::HyperLogLog { precision, .. } = sketch.sketch_params {
+        if method != CardinalityMethod::HyperLogLog {
+            bail!(
+                "Sketch {} is a HyperLogLog; try another estimator",
+                sketch.name
+            );
+        }
+        let estimate = hll_estimate(&hyperloglog::to_registers(&sketch.hashes, precision));
+        return Ok(CardinalityEstimate::from_std_err(
+            estimate,
+            estimate * hll_std_err(precision),
+        ));
+    }
+    Ok(match method {
+        CardinalityMethod::Kmv => kmv(&sketch.hashes),
+        CardinalityMethod::Scaled => {
+            let scale = sketch.sketch_params.hash_info().3.ok_or_else(|| {
+                format_err!(
+                    "Sketch {} is not scaled; try another estimator",
+                    sketch.name
+                )
+            })?;
+            scaled(&sketch.hashes, scale)
+        }
+        CardinalityMethod::HyperLogLog => {
+            let max_hash = match sketch.sketch_params.hash_info().3 {
+                Some(scale) => (scale * HASH_SPACE) as ItemHash,
+                None => sketch.hashes.last().map_or(0, |k| k.hash),
+            };
+            hyperloglog(&sketch.hashes, max_hash, DEFAULT_HLL_PRECISION)
+        }
+    })
+}
+
+/// Unbiased k-minimum value estimate of the cardinality.
+///
+/// Uses the `(k - 1) / U(k)` estimator where `U(k)` is the largest of the `k`
+/// sketch hashes normalized into [0, 1); its relative standard error is
+/// `1 / sqrt(k - 2)`. See Beyer et al., "On Synopses for Distinct-Value
+/// Estimation Under Multiset Operations" (2007).
+///
+/// Sketches with fewer than three hashes are too small to extrapolate from
+/// so their size is returned as-is.
+pub fn kmv(sketch: &[KmerCount]) -> CardinalityEstimate {
+    let k = sketch.len();
+    if k < 3 {
+        return CardinalityEstimate::exact(k);
+    }
+    let max_hash = sketch.iter().map(|k| k.hash).max().unwrap_or(0);
+    // the +1 keeps us in the half-open interval and avoids a zero division
+    let u_k = (max_hash as f64 + 1.) / HASH_SPACE;
+    let estimate = (k - 1) as f64 / u_k;
+    CardinalityEstimate::from_std_err(estimate, estimate / ((k - 2) as f64).sqrt())
+}
+
+/// Cardinality estimate for a scaled sketch: the number of hashes under the
+/// scaled maximum divided by the scale.
+///
+/// The number of retained hashes is binomially distributed so the standard
+/// error is `sqrt(n * (1 - scale)) / scale`.
+pub fn scaled(sketch: &[KmerCount], scale: f64) -> CardinalityEstimate {
+    if scale <= 0. || scale >= 1. {
+        return CardinalityEstimate::exact(sketch.len());
+    }
+    let max_hash = (scale * HASH_SPACE) as ItemHash;
+    let n = sketch.iter().filter(|k| k.hash <= max_hash).count() as f64;
+    CardinalityEstimate::from_std_err(n / scale, (n * (1. - scale)).sqrt() / scale)
+}
+
+/// HyperLogLog estimate of the cardinality from a set of hashes sampled
+/// uniformly from `[0, max_hash]`.
+///
+/// The hashes are stretched back over the full hash space before being
+/// loaded into the registers and the resulting estimate is scaled up by the
+/// fraction of the space that was sampled (so for subsampled sketches this
+/// is no more accurate than the `kmv` or `scaled` estimates).
+pub fn hyperloglog(sketch: &[KmerCount], max_hash: ItemHash, precision: u8) -> CardinalityEstimate {
+    if sketch.is_empty() {
+        return CardinalityEstimate::exact(0);
+    }
+    let fraction = f64::min(1., (max_hash as f64 + 1.) / HASH_SPACE);
+    let mut registers = vec![0u8; 1 << precision];
+    for kmer in sketch {
+        let hash = (kmer.hash as f64 / fraction) as ItemHash;
+        hll_update(&mut registers, precision, hash);
+    }
+    let estimate = hll_estimate(&registers) / fraction;
+    // subsampling adds its own (binomial) error on top of the HLL's
+    let sample_err = (1. - fraction) / sketch.len() as f64;
+    let rel_err = (hll_std_err(precision).powi(2) + sample_err).sqrt();
+    CardinalityEstimate::from_std_err(estimate, estimate * rel_err)
+}
+
+/// Add a hash to a set of HyperLogLog registers.
+///
+/// The first `precision` bits of the hash select the register and the
+/// register keeps the largest "rank" (leading zeros + 1) of the remaining
+/// bits seen.
+#[inline]
+pub fn hll_update(registers: &mut [u8], precision: u8, hash: ItemHash) {
+    let index = (hash >> (64 - u32::from(precision))) as usize;
+    let rest = hash << precision;
+    let rank = u32::min(rest.leading_zeros(), 64 - u32::from(precision)) as u8 + 1;
+    if rank > registers[index] {
+        registers[index] = rank;
+    }
+}
+
+/// The raw HyperLogLog estimate from a set of registers with the standard
+/// small-range (linear counting) correction.
+///
+/// See Flajolet et al., "HyperLogLog: the analysis of a near-optimal
+/// cardinality estimation algorithm" (2007).
+pub fn hll_estimate(registers: &[u8]) -> f64 {
+    let m = registers.len() as f64;
+    let alpha = match registers.len() {
+        16 => 0.673,
+        32 => 0.697,
+        64 => 0.709,
+        _ => 0.7213 / (1. + 1.079 / m),
+    };
+    let sum: f64 = registers.iter().map(|r| 2f64.powi(-i32::from(*r))).sum();
+    let raw = alpha * m * m / sum;
+
+    let zeros = registers.iter().filter(|r| **r == 0).count();
+    if raw <= 2.5 * m && zeros > 0 {
+        m * (m / zeros as f64).ln()
+    } else {
+        raw
+    }
+}
+
+/// The relative standard error of a HyperLogLog estimate
+#[inline]
+pub fn hll_std_err(precision: u8) -> f64 {
+    1.04 / f64::from(1u32 << precision).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kc(hashes: impl Iterator<Item = ItemHash>) -> Vec<KmerCount> {
+        hashes
+            .map(|hash| KmerCount {
+                hash,
+                kmer: vec![],
+                count: 1,
+                extra_count: 0,
+                label: None,
+            })
+            .collect()
+    }
+
+    fn random_hashes(n: u64) -> Vec<ItemHash> {
+        // splitmix64 for a cheap set of well-distributed "hashes"
+        let mut hashes: Vec<ItemHash> = (1..=n)
+            .map(|i| {
+                let mut z = i.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^ (z >> 31)
+            })
+            .collect();
+        hashes.sort();
+        hashes
+    }
+
+    #[test]
+    fn test_kmv() {
+        let hashes = random_hashes(100_000);
+        let sketch = kc(hashes.into_iter().take(1000));
+        let est = kmv(&sketch);
+        assert!(est.lower < 100_000. && 100_000. < est.upper);
+        assert!((est.estimate - 100_000.).abs() / 100_000. < 0.1);
+
+        assert_eq!(kmv(&kc(vec![5, 10].into_iter())).estimate, 2.);
+    }
+
+    #[test]
+    fn test_scaled() {
+        let scale = 0.01;
+        let max_hash = (scale * HASH_SPACE) as ItemHash;
+        let hashes = random_hashes(100_000);
+        let sketch = kc(hashes.into_iter().filter(|h| *h <= max_hash));
+        let est = scaled(&sketch, scale);
+        assert!(est.lower < 100_000. && 100_000. < est.upper);
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        // full sets of hashes
+        let hashes = random_hashes(50_000);
+        let est = hyperloglog(&kc(hashes.iter().cloned()), ItemHash::max_value(), 12);
+        assert!((est.estimate - 50_000.).abs() / 50_000. < 0.05);
+
+        // small sets should be (nearly) exact through linear counting
+        let small_hashes = random_hashes(100);
+        let est = hyperloglog(&kc(small_hashes.into_iter()), ItemHash::max_value(), 12);
+        assert!((est.estimate - 100.).abs() < 3.);
+
+        // and subsampled sets should be scaled back up
+        let sketch = kc(hashes.iter().cloned().take(2000));
+        let est = hyperloglog(&sketch, sketch.last().unwrap().hash, 12);
+        assert!((est.estimate - 50_000.).abs() / 50_000. < 0.1);
+    }
+
+    #[test]
+    fn test_estimate_cardinality() {
+        let mut sketch = Sketch {
+            name: "test".to_string(),
+            seq_length: 0,
+            num_valid_kmers: 0,
+            comment: "".to_string(),
+            hashes: kc(vec![1, 2, 3].into_iter()),
+            filter_params: Default::default(),
+            sketch_params: SketchParams::AllCounts { kmer_length: 2 },
+            provenance: None,
+        };
+        let est = estimate_cardinality(&sketch, None).unwrap();
+        assert_eq!(est.estimate, 3.);
+
+        sketch.sketch_params = SketchParams::default();
+        assert!(estimate_cardinality(&sketch, Some(CardinalityMethod::Scaled)).is_err());
+        assert!(estimate_cardinality(&sketch, None).is_ok());
+    }
+}
@@ -1,9 +1,20 @@
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::BuildHasherDefault;
+use std::io::Read;
+use std::iter;
+use std::sync::Arc;
 
+use failure::{bail, format_err};
+use needletail::formats::parse_sequence_reader;
+use needletail::Sequence;
+
+use crate::bloom::BloomFilter;
 use crate::serialization::Sketch;
-use crate::sketch_schemes::KmerCount;
-use crate::statistics::hist;
+use crate::sketch_schemes::hashing::NoHashHasher;
+use crate::sketch_schemes::{HashFunction, ItemHash, KmerCount, KmerHasher, SketchParams};
+use crate::statistics::sparse_hist;
 use crate::Result;
 
 /// Used to pass around filter options for sketching
@@ -13,6 +24,11 @@ pub struct FilterParams {
     pub abun_filter: (Option<u32>, Option<u32>),
     pub err_filter: f64,
     pub strand_filter: f64,
+    pub qual_filter: Option<u8>,
+    pub kmer_err_filter: Option<f64>,
+    pub dust_filter: Option<f64>,
+    pub exclusion_filter: Option<ExclusionSet>,
+    pub bloom_filter_size: Option<usize>,
 }
 
 impl FilterParams {
@@ -66,15 +82,30 @@ impl FilterParams {
     /// If the err filter determined a different low_abundance_filter update
     /// self to that one.
     pub fn filter_counts(&mut self, hashes: &[KmerCount]) -> Vec<KmerCount> {
+        self.filter_counts_with_report(hashes).0
+    }
+
+    /// Returns the filtered kmer counts and a report of what was filtered.
+    pub fn filter_counts_with_report(
+        &mut self,
+        hashes: &[KmerCount],
+    ) -> (Vec<KmerCount>, FilterReport) {
         let filter_on = self.filter_on == Some(true);
         let mut filtered_hashes = hashes.to_vec();
+        let mut report = FilterReport {
+            pre_filter_size: hashes.len(),
+            ..Default::default()
+        };
 
         if filter_on && self.strand_filter > 0f64 {
             filtered_hashes = filter_strands(&filtered_hashes, self.strand_filter);
+            report.strand_filtered = hashes.len() - filtered_hashes.len();
         }
 
         if filter_on && self.err_filter > 0f64 {
-            let cutoff = guess_filter_threshold(&filtered_hashes, self.err_filter);
+            let window = guess_filter_window(&filtered_hashes, self.err_filter);
+            let cutoff = window.cutoff;
+            report.err_cutoff = Some(window);
             if let Some(v) = self.abun_filter.0 {
                 // there's an existing filter so we only use this one if it's stricter
                 if cutoff > v {
@@ -87,15 +118,36 @@ impl FilterParams {
         }
 
         if filter_on && (self.abun_filter.0.is_some() || self.abun_filter.1.is_some()) {
+            let pre_abun_size = filtered_hashes.len();
             filtered_hashes =
                 filter_abundance(&filtered_hashes, self.abun_filter.0, self.abun_filter.1);
+            report.abun_filter = self.abun_filter;
+            report.abun_filtered = pre_abun_size - filtered_hashes.len();
         }
 
-        filtered_hashes
+        report.post_filter_size = filtered_hashes.len();
+        (filtered_hashes, report)
     }
 
     pub fn to_serialized(&self) -> HashMap<String, String> {
         let mut filter_stats: HashMap<String, String> = HashMap::new();
+        // the kmer-level filters are applied while sketching (and
+        // independently of `filter_on`) so they're always recorded
+        if let Some(v) = self.qual_filter {
+            filter_stats.insert(String::from("minQual"), v.to_string());
+        }
+        if let Some(v) = self.kmer_err_filter {
+            filter_stats.insert(String::from("maxKmerErrors"), v.to_string());
+        }
+        if let Some(v) = self.dust_filter {
+            filter_stats.insert(String::from("dustFilter"), v.to_string());
+        }
+        if let Some(v) = &self.exclusion_filter {
+            filter_stats.insert(String::from("exclusionSet"), v.name.clone());
+        }
+        if let Some(v) = self.bloom_filter_size {
+            filter_stats.insert(String::from("bloomFilterSize"), v.to_string());
+        }
         if self.filter_on != Some(true) {
             return filter_stats;
         }
@@ -115,6 +167,47 @@ impl FilterParams {
         filter_stats
     }
 
+    /// Could a sketch with these (stored) filters have been made with the
+    /// `requested` filters? Adaptive cutoffs are only known after filtering
+    /// so with an `err_filter` any stored minimum at least as strict as the
+    /// requested one matches.
+    pub fn matches_requested(&self, requested: &FilterParams) -> bool {
+        let exclusion_name = |f: &FilterParams| f.exclusion_filter.as_ref().map(|e| e.name.clone());
+        if self.qual_filter != requested.qual_filter
+            || self.kmer_err_filter != requested.kmer_err_filter
+            || self.dust_filter != requested.dust_filter
+            || self.bloom_filter_size != requested.bloom_filter_size
+            || exclusion_name(self) != exclusion_name(requested)
+        {
+            return false;
+        }
+
+        // if filtering wasn't specified, it depends on the file type (which
+        // hasn't changed) so any stored value is fine
+        let filter_on = self.filter_on == Some(true);
+        if let Some(requested_on) = requested.filter_on {
+            if requested_on != filter_on {
+                return false;
+            }
+        }
+        if !filter_on {
+            return true;
+        }
+
+        if self.strand_filter != requested.strand_filter
+            || self.err_filter != requested.err_filter
+            || self.abun_filter.1 != requested.abun_filter.1
+        {
+            return false;
+        }
+        match (self.abun_filter.0, requested.abun_filter.0) {
+            (stored, requested_min) if requested.err_filter <= 0. => stored == requested_min,
+            (Some(stored), Some(requested_min)) => stored >= requested_min,
+            (stored, None) => stored.is_some(),
+            (None, Some(_)) => false,
+        }
+    }
+
     pub fn from_serialized(filters: &HashMap<String, String>) -> Result<Self> {
         let low_abun = if let Some(min_copies) = filters.get("minCopies") {
             Some(min_copies.parse()?)
@@ -126,8 +219,31 @@ impl FilterParams {
         } else {
             None
         };
+        let qual_filter = if let Some(min_qual) = filters.get("minQual") {
+            Some(min_qual.parse()?)
+        } else {
+            None
+        };
+        let kmer_err_filter = if let Some(max_errors) = filters.get("maxKmerErrors") {
+            Some(max_errors.parse()?)
+        } else {
+            None
+        };
+        let dust_filter = if let Some(max_score) = filters.get("dustFilter") {
+            Some(max_score.parse()?)
+        } else {
+            None
+        };
+        let bloom_filter_size = if let Some(size) = filters.get("bloomFilterSize") {
+            Some(size.parse()?)
+        } else {
+            None
+        };
+        let filter_on = ["strandFilter", "errFilter", "minCopies", "maxCopies"]
+            .iter()
+            .any(|key| filters.contains_key(*key));
         Ok(FilterParams {
-            filter_on: Some(!filters.is_empty()),
+            filter_on: Some(filter_on),
             abun_filter: (low_abun, high_abun),
             err_filter: filters
                 .get("errFilter")
@@ -137,6 +253,13 @@ impl FilterParams {
                 .get("strandFilter")
                 .unwrap_or(&"0".to_string())
                 .parse()?,
+            qual_filter,
+            kmer_err_filter,
+            dust_filter,
+            exclusion_filter: filters
+                .get("exclusionSet")
+                .map(|name| ExclusionSet::from_name(name)),
+            bloom_filter_size,
         })
     }
 }
@@ -148,10 +271,665 @@ impl Default for FilterParams {
             abun_filter: (None, None),
             err_filter: 0.,
             strand_filter: 0.,
+            qual_filter: None,
+            kmer_err_filter: None,
+            dust_filter: None,
+            exclusion_filter: None,
+            bloom_filter_size: None,
+        }
+    }
+}
+
+/// A summary of what was removed by `FilterParams::filter_counts`
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterReport {
+    pub name: String,
+    /// Number of hashes before any filtering
+    pub pre_filter_size: usize,
+    /// Number of hashes removed by the strand filter
+    pub strand_filtered: usize,
+    /// How the adaptive abundance cutoff was chosen (if it was used)
+    pub err_cutoff: Option<FilterWindow>,
+    /// The final (inclusive) abundance limits applied
+    pub abun_filter: (Option<u32>, Option<u32>),
+    /// Number of hashes removed by the abundance filters
+    pub abun_filtered: usize,
+    /// Number of hashes left after filtering (and any trimming down to the
+    /// final sketch size)
+    pub post_filter_size: usize,
+    /// True if the sketch ended up smaller than requested and was only
+    /// kept because of `no_strict`
+    pub undersized: bool,
+}
+
+/// The number of hash functions used for exclusion set Bloom filters
+const EXCLUSION_BLOOM_HASHES: u8 = 4;
+
+/// The number of hash functions used for singleton-removal Bloom filters
+const SINGLETON_BLOOM_HASHES: u8 = 3;
+
+#[derive(Debug)]
+enum ExcludedKmers {
+    Hashes(HashSet<ItemHash, BuildHasherDefault<NoHashHasher>>),
+    Bloom(BloomFilter),
+}
+
+/// A set of kmers (e.g. from a host genome) that should never be added to a
+/// sketch.
+///
+/// Only the name of the set is saved with sketches (and checkpoints) so sets
+/// read back from sketch files can't be used to exclude anything.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ExclusionSet {
+    pub name: String,
+    #[serde(skip)]
+    kmers: Option<Arc<ExcludedKmers>>,
+    kmer_length: u8,
+    hash_seed: u64,
+    hash_function: HashFunction,
+}
+
+impl ExclusionSet {
+    /// A placeholder for an exclusion set that was used to make a sketch
+    pub fn from_name(name: &str) -> Self {
+        ExclusionSet {
+            name: name.to_string(),
+            kmers: None,
+            kmer_length: 0,
+            hash_seed: 0,
+            hash_function: HashFunction::default(),
+        }
+    }
+
+    /// Build an exclusion set from the hashes in a set of reference sketches.
+    ///
+    /// `AllCounts` sketches store every kmer so they're rehashed to match
+    /// `sketch_params`; otherwise the sketches' hashes are used directly and
+    /// must have been made with the same kmer length and hash function/seed.
+    pub fn from_sketches(
+        name: &str,
+        sketches: &[Sketch],
+        sketch_params: &SketchParams,
+    ) -> Result<Self> {
+        let (_, _, hash_seed, _) = sketch_params.hash_info();
+        let hash_function = sketch_params.hash_function().unwrap_or_default();
+        let mut hashes = HashSet::default();
+        for sketch in sketches {
+            match sketch.sketch_params {
+                SketchParams::AllCounts { kmer_length } if kmer_length == sketch_params.k() => {
+                    if !sketch.has_kmers() && !sketch.is_empty() {
+                        bail!(
+                            "Sketch {} has no kmers to rehash (was it made with --no-kmers?)",
+                            sketch.name
+                        );
+                    }
+                    for kmer in &sketch.hashes {
+                        let rc = kmer.kmer.reverse_complement();
+                        let canonical = cmp::min(&kmer.kmer, &rc);
+                        hashes.insert(hash_function.hash(canonical, hash_seed));
+                    }
+                }
+                SketchParams::HyperLogLog { .. } => {
+                    bail!(
+                        "HyperLogLog sketch {} can't be used to exclude kmers",
+                        sketch.name
+                    )
+                }
+                _ => {
+                    if let Some((param, v1, v2)) =
+                        sketch_params.check_compatibility(&sketch.sketch_params)
+                    {
+                        bail!(
+                            "Exclusion sketch {} has {} {}, but working value is {}",
+                            sketch.name,
+                            param,
+                            v2,
+                            v1,
+                        );
+                    }
+                    hashes.extend(sketch.hashes.iter().map(|k| k.hash));
+                }
+            }
+        }
+        Ok(ExclusionSet {
+            name: name.to_string(),
+            kmers: Some(Arc::new(ExcludedKmers::Hashes(hashes))),
+            kmer_length: sketch_params.k(),
+            hash_seed,
+            hash_function,
+        })
+    }
+
+    /// Build an exclusion set from every kmer in a FASTA/Q file using a
+    /// Bloom filter of approximately `bloom_size` bytes (so a small
+    /// proportion of other kmers will also be excluded if it's too small).
+    pub fn from_sequences<'a>(
+        name: &str,
+        reader: Box<dyn Read + 'a>,
+        sketch_params: &SketchParams,
+        bloom_size: usize,
+    ) -> Result<Self> {
+        let kmer_length = sketch_params.k();
+        let (_, _, hash_seed, _) = sketch_params.hash_info();
+        let hash_function = sketch_params.hash_function().unwrap_or_default();
+        let mut bloom = BloomFilter::new(bloom_size, EXCLUSION_BLOOM_HASHES);
+        parse_sequence_reader(
+            reader,
+            |_| {},
+            |seq| {
+                let rc = seq.reverse_complement();
+                let norm_seq = seq.normalize(false);
+                let mut hasher = KmerHasher::new(hash_function, kmer_length, hash_seed);
+                for (pos, kmer, _) in norm_seq.canonical_kmers(kmer_length, &rc) {
+                    bloom.insert(hasher.hash(&norm_seq, pos, kmer));
+                }
+            },
+        )
+        .map_err(|e| format_err!("{}", e.to_string()))?;
+        Ok(ExclusionSet {
+            name: name.to_string(),
+            kmers: Some(Arc::new(ExcludedKmers::Bloom(bloom))),
+            kmer_length,
+            hash_seed,
+            hash_function,
+        })
+    }
+
+    /// Check that this set can be used for sketching with these parameters
+    pub fn check_params(&self, sketch_params: &SketchParams) -> Result<()> {
+        if self.kmers.is_none() {
+            bail!("Exclusion set {} has no kmers loaded", self.name);
+        }
+        if let SketchParams::AllCounts { .. } = sketch_params {
+            bail!("Exclusion sets can't be used with `none` sketches");
+        }
+        if self.kmer_length != sketch_params.k()
+            || self.hash_seed != sketch_params.hash_info().2
+            || Some(self.hash_function) != sketch_params.hash_function()
+        {
+            bail!(
+                "Exclusion set {} was built with a different kmer length or hash function/seed",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Is this hash in the set? (Placeholder sets contain nothing.)
+    #[inline]
+    pub fn contains(&self, hash: ItemHash) -> bool {
+        let kmers = match &self.kmers {
+            Some(kmers) => kmers,
+            None => return false,
+        };
+        match &**kmers {
+            ExcludedKmers::Hashes(hashes) => hashes.contains(&hash),
+            ExcludedKmers::Bloom(bloom) => bloom.contains(hash),
         }
     }
 }
 
+impl fmt::Debug for ExclusionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExclusionSet({})", self.name)
+    }
+}
+
+// sets are only compared by name because that's all that gets serialized
+impl PartialEq for ExclusionSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// Per-kmer filters that are applied to sequences as they're sketched (as
+/// opposed to `FilterParams::filter_counts` which works on the final counts).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KmerFilter {
+    min_qual: Option<u8>,
+    max_errors: Option<f64>,
+    max_dust: Option<f64>,
+    exclusion: Option<ExclusionSet>,
+    singletons: Option<BloomFilter>,
+}
+
+impl KmerFilter {
+    pub fn new(filters: &FilterParams) -> Self {
+        KmerFilter {
+            min_qual: filters.qual_filter,
+            max_errors: filters.kmer_err_filter,
+            max_dust: filters.dust_filter,
+            exclusion: filters.exclusion_filter.clone(),
+            singletons: filters
+                .bloom_filter_size
+                .map(|size| BloomFilter::new(size, SINGLETON_BLOOM_HASHES)),
+        }
+    }
+
+    /// Put back the kmers of the exclusion set this filter was made with
+    /// (only its name is saved in checkpoints); `exclusion` has to be the
+    /// same set (or `None` if the filter didn't have one)
+    pub(crate) fn restore_exclusion(&mut self, exclusion: Option<&ExclusionSet>) -> Result<()> {
+        match (&self.exclusion, exclusion) {
+            (None, None) => {}
+            (None, Some(given)) => bail!(
+                "Checkpoint was made without an exclusion set, but {} was given",
+                given.name
+            ),
+            (Some(saved), None) => bail!(
+                "Checkpoint was made with exclusion set {} so it has to be given to resume",
+                saved.name
+            ),
+            (Some(saved), Some(given)) => {
+                if given.kmers.is_none() {
+                    bail!("Exclusion set {} has no kmers loaded", given.name);
+                }
+                if saved.name != given.name
+                    || saved.kmer_length != given.kmer_length
+                    || saved.hash_seed != given.hash_seed
+                    || saved.hash_function != given.hash_function
+                {
+                    bail!(
+                        "Checkpoint was made with exclusion set {}, but {} was given",
+                        saved.name,
+                        given.name
+                    );
+                }
+                self.exclusion = Some(given.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the filter has a singleton (Bloom) filter; its results depend
+    /// on every hash seen before so it can't be split across sketchers
+    pub fn has_singleton_filter(&self) -> bool {
+        self.singletons.is_some()
+    }
+
+    /// Should this kmer hash be allowed into the sketch?
+    ///
+    /// If there's a singleton filter, the first time a hash is seen it's
+    /// only added to the Bloom filter (so the counts of kmers in the sketch
+    /// will be one less than the number of times they were seen).
+    #[inline]
+    pub fn admit_hash(&mut self, hash: ItemHash) -> bool {
+        if let Some(exclusion) = &self.exclusion {
+            if exclusion.contains(hash) {
+                return false;
+            }
+        }
+        match &mut self.singletons {
+            Some(bloom) => bloom.insert(hash),
+            None => true,
+        }
+    }
+
+    /// Determine which kmers (by starting position) in a (normalized)
+    /// sequence pass the filters. The quality filters are skipped for
+    /// sequences without quality scores (e.g. FASTAs).
+    pub fn kmer_mask(&self, seq: &[u8], qual: Option<&[u8]>, k: u8) -> KmerMask {
+        // only use the quality scores if there are filters that need them
+        let qual = match qual {
+            Some(q) if q.len() == seq.len() => q,
+            _ => &[],
+        };
+        let use_qual = !qual.is_empty() && (self.min_qual.is_some() || self.max_errors.is_some());
+        if !use_qual && self.max_dust.is_none() {
+            return KmerMask(None);
+        }
+        let k = usize::from(k);
+        if k == 0 || seq.len() < k {
+            return KmerMask(None);
+        }
+
+        let mut mask = vec![true; seq.len() - k + 1];
+        if let (Some(min_qual), true) = (self.min_qual, use_qual) {
+            // kmers starting before `clean_from` contain a low quality base
+            let mut clean_from = 0;
+            for (ix, q) in qual.iter().enumerate() {
+                if q.saturating_sub(PHRED_OFFSET) < min_qual {
+                    clean_from = ix + 1;
+                }
+                if ix + 1 >= k && ix + 1 - k < clean_from {
+                    mask[ix + 1 - k] = false;
+                }
+            }
+        }
+        if let (Some(max_errors), true) = (self.max_errors, use_qual) {
+            // use prefix sums of the error probabilities rather than a
+            // running sum so rounding errors don't accumulate
+            let mut prefix = Vec::with_capacity(qual.len() + 1);
+            prefix.push(0f64);
+            for q in qual {
+                let err_prob = 10f64.powf(-f64::from(q.saturating_sub(PHRED_OFFSET)) / 10.);
+                prefix.push(prefix[prefix.len() - 1] + err_prob);
+            }
+            for (start, keep) in mask.iter_mut().enumerate() {
+                if prefix[start + k] - prefix[start] > max_errors {
+                    *keep = false;
+                }
+            }
+        }
+        if let Some(max_dust) = self.max_dust {
+            for (start, score) in dust_scores(seq, k).into_iter().enumerate() {
+                if score > max_dust {
+                    mask[start] = false;
+                }
+            }
+        }
+        KmerMask(Some(mask))
+    }
+}
+
+/// The DUST low-complexity score of every kmer in a sequence.
+///
+/// Each kmer is broken into its `k - 2` overlapping triplets and, given the
+/// count `c_t` of each distinct triplet, scored as `sum(c_t * (c_t - 1) / 2) /
+/// (k - 3)`; this is 0 for kmers without repeated triplets and `(k - 2) / 2`
+/// for homopolymers. Triplets with non-ACGT bases are ignored. See Morgulis
+/// et al., "A fast and symmetric DUST implementation to mask low-complexity
+/// DNA sequences" (2006).
+pub fn dust_scores(seq: &[u8], k: usize) -> Vec<f64> {
+    // we need at least two triplets for a score
+    if k < 4 || seq.len() < k {
+        return Vec::new();
+    }
+    let n_triplets = k - 2;
+    let triplets: Vec<Option<usize>> = seq.windows(3).map(triplet_index).collect();
+
+    let mut scores = Vec::with_capacity(seq.len() - k + 1);
+    let mut counts = [0u32; 64];
+    let mut score = 0u32;
+    for (ix, triplet) in triplets.iter().enumerate() {
+        // adding a triplet adds a pair for every copy we've already seen
+        if let Some(t) = *triplet {
+            score += counts[t];
+            counts[t] += 1;
+        }
+        if ix + 1 < n_triplets {
+            continue;
+        }
+        let start = ix + 1 - n_triplets;
+        scores.push(f64::from(score) / (n_triplets - 1) as f64);
+        // and removing one drops all the pairs it was part of
+        if let Some(t) = triplets[start] {
+            counts[t] -= 1;
+            score -= counts[t];
+        }
+    }
+    scores
+}
+
+#[inline]
+fn triplet_index(triplet: &[u8]) -> Option<usize> {
+    let mut index = 0;
+    for base in triplet {
+        index = (index << 2)
+            | match base {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => return None,
+            };
+    }
+    Some(index)
+}
+
+/// The offset of ASCII-encoded quality scores (Phred+33, as in Sanger and
+/// Illumina 1.8+ FASTQs)
+const PHRED_OFFSET: u8 = 33;
+
+/// Which kmer start positions in a sequence passed the `KmerFilter`
+#[derive(Clone, Debug)]
+pub struct KmerMask(Option<Vec<bool>>);
+
+impl KmerMask {
+    #[inline]
+    pub fn passes(&self, pos: usize) -> bool {
+        match &self.0 {
+            Some(mask) => mask.get(pos).cloned().unwrap_or(true),
+            None => true,
+        }
+    }
+}
+
+#[test]
+fn test_kmer_filter() {
+    let filters = FilterParams {
+        qual_filter: Some(20),
+        ..Default::default()
+    };
+    let kmer_filter = KmerFilter::new(&filters);
+    // no qualities means no filtering
+    assert!(kmer_filter.kmer_mask(b"ACGTACGTACGT", None, 3).passes(0));
+
+    // `5` is Q20 and `4` is Q19
+    let qual = b"IIIII4IIIII5";
+    let mask = kmer_filter.kmer_mask(b"ACGTACGTACGT", Some(qual), 3);
+    let passed: Vec<bool> = (0..10).map(|pos| mask.passes(pos)).collect();
+    assert_eq!(
+        passed,
+        vec![true, true, true, false, false, false, true, true, true, true]
+    );
+
+    let filters = FilterParams {
+        kmer_err_filter: Some(0.15),
+        ..Default::default()
+    };
+    let kmer_filter = KmerFilter::new(&filters);
+    // `+` is Q10 (0.1 error probability) and `I` is Q40 (0.0001)
+    let qual = b"II+II+I+";
+    let mask = kmer_filter.kmer_mask(b"ACGTACGT", Some(qual), 3);
+    let passed: Vec<bool> = (0..6).map(|pos| mask.passes(pos)).collect();
+    assert_eq!(passed, vec![true, true, true, true, true, false]);
+
+    // a filter that does nothing shouldn't allocate a mask
+    let kmer_filter = KmerFilter::new(&FilterParams::default());
+    assert!(kmer_filter
+        .kmer_mask(b"ACGTACGT", Some(qual), 3)
+        .0
+        .is_none());
+}
+
+#[test]
+fn test_dust_scores() {
+    // homopolymers have the maximum score of (k - 2) / 2
+    assert_eq!(dust_scores(b"AAAAAAAA", 8), vec![3.]);
+    // and kmers without repeated triplets have none
+    assert_eq!(dust_scores(b"ACGGTCAT", 8), vec![0.]);
+    // dinucleotide repeats are in between
+    assert_eq!(dust_scores(b"ACACACAC", 8), vec![1.2]);
+    // triplets with Ns are skipped
+    assert_eq!(dust_scores(b"AAAANAAA", 8), vec![0.6]);
+    // the sliding window should match scoring each kmer separately
+    let seq = b"ACGTTTTTTTTTTGCAACACACACNAGT";
+    let scores = dust_scores(seq, 10);
+    assert_eq!(scores.len(), seq.len() - 9);
+    for (start, score) in scores.iter().enumerate() {
+        assert_eq!(*score, dust_scores(&seq[start..start + 10], 10)[0]);
+    }
+    assert!(dust_scores(b"AAA", 3).is_empty());
+
+    let filters = FilterParams {
+        dust_filter: Some(1.),
+        ..Default::default()
+    };
+    let kmer_filter = KmerFilter::new(&filters);
+    let mask = kmer_filter.kmer_mask(b"AAAAAAAACGGTCAT", None, 8);
+    assert!(!mask.passes(0));
+    assert!(mask.passes(7));
+}
+
+#[test]
+fn test_exclusion_set() {
+    use crate::sketch_schemes::hashing::hash_f;
+
+    let sketch_params = SketchParams::Mash {
+        kmers_to_sketch: 10,
+        final_size: 10,
+        no_strict: false,
+        kmer_length: 3,
+        hash_seed: 42,
+        hash_function: HashFunction::MurmurHash3,
+    };
+    // `AllCounts` kmers get rehashed in their canonical form
+    let reference = Sketch {
+        name: "host".to_string(),
+        seq_length: 0,
+        num_valid_kmers: 0,
+        comment: "".to_string(),
+        hashes: vec![KmerCount {
+            hash: 0,
+            kmer: b"TTT".to_vec(),
+            count: 1,
+            extra_count: 0,
+            label: None,
+        }],
+        filter_params: FilterParams::default(),
+        sketch_params: SketchParams::AllCounts { kmer_length: 3 },
+        provenance: None,
+    };
+    let exclusion = ExclusionSet::from_sketches("host", &[reference], &sketch_params).unwrap();
+    assert!(exclusion.check_params(&sketch_params).is_ok());
+    assert!(exclusion.contains(hash_f(b"AAA", 42)));
+    assert!(!exclusion.contains(hash_f(b"AAC", 42)));
+
+    let filters = FilterParams {
+        exclusion_filter: Some(exclusion),
+        ..Default::default()
+    };
+    let mut kmer_filter = KmerFilter::new(&filters);
+    assert!(!kmer_filter.admit_hash(hash_f(b"AAA", 42)));
+    assert!(kmer_filter.admit_hash(hash_f(b"AAC", 42)));
+
+    // only the name survives serialization
+    let deserialized = FilterParams::from_serialized(&filters.to_serialized()).unwrap();
+    assert_eq!(deserialized, filters);
+    let placeholder = deserialized.exclusion_filter.unwrap();
+    assert!(placeholder.check_params(&sketch_params).is_err());
+
+    // sets built with a different seed can't be used
+    let other_params = SketchParams::Mash {
+        kmers_to_sketch: 10,
+        final_size: 10,
+        no_strict: false,
+        kmer_length: 3,
+        hash_seed: 0,
+        hash_function: HashFunction::MurmurHash3,
+    };
+    let exclusion = filters.exclusion_filter.unwrap();
+    assert!(exclusion.check_params(&other_params).is_err());
+}
+
+#[test]
+fn test_filter_report() {
+    let sketch: Vec<KmerCount> = [1, 1, 1, 2, 20, 20, 20, 20]
+        .iter()
+        .enumerate()
+        .map(|(ix, count)| KmerCount {
+            hash: ix as ItemHash,
+            kmer: vec![],
+            count: *count,
+            // the last kmer is only seen on one strand
+            extra_count: if ix == 7 { 0 } else { count / 2 },
+            label: None,
+        })
+        .collect();
+    let mut filters = FilterParams {
+        filter_on: Some(true),
+        err_filter: 0.1,
+        strand_filter: 0.1,
+        ..Default::default()
+    };
+    let (filtered, report) = filters.filter_counts_with_report(&sketch);
+    assert_eq!(report.pre_filter_size, 8);
+    assert_eq!(report.strand_filtered, 1);
+    let window = report.err_cutoff.unwrap();
+    assert_eq!(window.cutoff, guess_filter_threshold(&sketch[..7], 0.1));
+    assert_eq!(report.abun_filter, filters.abun_filter);
+    assert_eq!(report.abun_filtered, 7 - filtered.len());
+    assert_eq!(report.post_filter_size, filtered.len());
+
+    // nothing should be reported if filtering is off
+    let (_, report) = FilterParams::default().filter_counts_with_report(&sketch);
+    assert_eq!(report.post_filter_size, 8);
+    assert_eq!(report.err_cutoff, None);
+}
+
+#[test]
+fn test_filter_matches_requested() {
+    let requested = FilterParams {
+        filter_on: None,
+        err_filter: 1.,
+        strand_filter: 0.1,
+        ..Default::default()
+    };
+    // the adaptive cutoff is stored as the minimum after filtering
+    let stored = FilterParams {
+        filter_on: Some(true),
+        abun_filter: (Some(3), None),
+        ..requested.clone()
+    };
+    let round_tripped = FilterParams::from_serialized(&stored.to_serialized()).unwrap();
+    assert!(round_tripped.matches_requested(&requested));
+    // unfiltered FASTA sketches also match when filtering isn't specified
+    assert!(FilterParams::default().matches_requested(&requested));
+
+    let stricter = FilterParams {
+        abun_filter: (Some(5), None),
+        ..requested.clone()
+    };
+    assert!(stored.matches_requested(&FilterParams {
+        filter_on: Some(true),
+        abun_filter: (Some(2), None),
+        ..requested.clone()
+    }));
+    assert!(!stored.matches_requested(&stricter));
+    assert!(!stored.matches_requested(&FilterParams {
+        filter_on: Some(false),
+        ..requested.clone()
+    }));
+    assert!(!stored.matches_requested(&FilterParams {
+        qual_filter: Some(20),
+        ..requested.clone()
+    }));
+}
+
+#[test]
+fn test_singleton_filter() {
+    let filters = FilterParams {
+        bloom_filter_size: Some(1024),
+        ..Default::default()
+    };
+    let mut kmer_filter = KmerFilter::new(&filters);
+    assert!(!kmer_filter.admit_hash(1234));
+    assert!(kmer_filter.admit_hash(1234));
+    assert!(kmer_filter.admit_hash(1234));
+    assert!(!kmer_filter.admit_hash(5678));
+
+    // every filter gets its own Bloom filter
+    let mut kmer_filter = KmerFilter::new(&filters);
+    assert!(!kmer_filter.admit_hash(1234));
+}
+
+#[test]
+fn test_quality_filter_serialization() {
+    let filters = FilterParams {
+        qual_filter: Some(20),
+        kmer_err_filter: Some(0.5),
+        dust_filter: Some(2.5),
+        bloom_filter_size: Some(1024),
+        ..Default::default()
+    };
+    let serialized = filters.to_serialized();
+    assert_eq!(serialized.len(), 4);
+    let deserialized = FilterParams::from_serialized(&serialized).unwrap();
+    assert_eq!(deserialized, filters);
+}
+
 /// Determines a dynamic filtering threshold for low abundance kmers. The
 /// cutoff returned is the lowest number of counts that should be included
 /// in any final results.
@@ -160,46 +938,108 @@ impl Default for FilterParams {
 /// errors
 ///
 pub fn guess_filter_threshold(sketch: &[KmerCount], filter_level: f64) -> u32 {
-    let hist_data = hist(sketch);
-    let total_counts = hist_data
+    guess_filter_window(sketch, filter_level).cutoff
+}
+
+/// The details of how `guess_filter_threshold` picked a cutoff
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterWindow {
+    /// The cutoff chosen (the lowest count included in the results)
+    pub cutoff: u32,
+    /// The count below which `filter_level` of the weighted kmers are found
+    /// (the cutoff is searched for below this)
+    pub search_limit: u32,
+    /// The width of the histogram window used to find the minimum
+    pub window_size: u32,
+}
+
+/// Same as `guess_filter_threshold`, but also returns the parameters of the
+/// histogram search that were used to find the cutoff.
+pub fn guess_filter_window(sketch: &[KmerCount], filter_level: f64) -> FilterWindow {
+    // use a sparse histogram to find the cutoff so we don't have to
+    // allocate space for every count up to the highest (which can be huge
+    // for e.g. adapters or rRNA)
+    let sparse_data = sparse_hist(sketch);
+    let total_counts = sparse_data
         .iter()
-        .enumerate()
-        .map(|t| (t.0 as u64 + 1) * t.1)
+        .map(|(level, count)| u64::from(*level) * count)
         .sum::<u64>() as f64;
     let cutoff_amt = filter_level * total_counts;
 
     // calculate the coverage that N% of the weighted data is above
     // note wgt_cutoff is an index now *not* a number of counts
-    let mut wgt_cutoff: usize = 0;
+    let mut wgt_cutoff: usize = sparse_data.keys().last().map_or(0, |l| *l as usize);
     let mut cum_count: u64 = 0;
-    for count in &hist_data {
-        cum_count += wgt_cutoff as u64 * *count as u64;
+    for (level, count) in &sparse_data {
+        let idx = *level as usize - 1;
+        cum_count += idx as u64 * *count as u64;
         if cum_count as f64 > cutoff_amt {
+            wgt_cutoff = idx;
             break;
         }
-        wgt_cutoff += 1;
     }
 
     // special case if the cutoff is the first value
     if wgt_cutoff == 0 {
-        return 1;
+        return FilterWindow {
+            cutoff: 1,
+            search_limit: 0,
+            window_size: 0,
+        };
     }
 
-    // now find the minima within the window to the left
+    // now find the minima within the window to the left; the histogram is
+    // left sparse (indexed by count - 1 like `wgt_cutoff`) since the cutoff
+    // can be as high as the highest count
+    let levels: Vec<(usize, u64)> = sparse_data
+        .range(..=wgt_cutoff as u32)
+        .map(|(level, count)| (*level as usize - 1, *count))
+        .collect();
     let win_size = cmp::max(1, wgt_cutoff / 20);
-    let mut sum: u64 = hist_data[..win_size].iter().sum();
-    let mut lowest_val = sum;
+    // the number of kmers in the window ending at (and including) `end`
+    let n_levels_upto = |max_idx: usize| match levels.binary_search_by(|(idx, _)| idx.cmp(&max_idx))
+    {
+        Ok(pos) => pos + 1,
+        Err(pos) => pos,
+    };
+    let window_sum = |end: usize| -> u64 {
+        let hi = n_levels_upto(end);
+        let lo = if end >= win_size {
+            n_levels_upto(end - win_size)
+        } else {
+            0
+        };
+        levels[lo..hi].iter().map(|(_, count)| count).sum()
+    };
+
+    // the window sum only changes where a level enters or leaves the
+    // window, so only the last window before each change (and the last
+    // window overall) needs checking; the latest lowest window wins ties
+    let window_ends = (win_size - 1)..(wgt_cutoff - 1);
+    let mut ends: Vec<usize> = levels
+        .iter()
+        .flat_map(|(idx, _)| iter::once(idx.wrapping_sub(1)).chain(iter::once(idx + win_size - 1)))
+        .chain(wgt_cutoff.checked_sub(2))
+        .filter(|end| window_ends.contains(end))
+        .collect();
+    ends.sort_unstable();
+    ends.dedup();
+    let mut lowest_val = u64::max_value();
     let mut lowest_idx = win_size - 1;
-    for (i, j) in (0..wgt_cutoff - win_size).zip(win_size..wgt_cutoff) {
+    for end in ends {
+        let sum = window_sum(end);
         if sum <= lowest_val {
             lowest_val = sum;
-            lowest_idx = j;
+            lowest_idx = end + 1;
         }
-        sum -= hist_data[i];
-        sum += hist_data[j];
     }
 
-    lowest_idx as u32 + 1
+    FilterWindow {
+        cutoff: lowest_idx as u32 + 1,
+        search_limit: wgt_cutoff as u32,
+        window_size: win_size as u32,
+    }
 }
 
 #[test]
@@ -332,6 +1172,23 @@ fn test_guess_filter_threshold() {
     }];
     let cutoff = guess_filter_threshold(&sketch, 1.);
     assert_eq!(cutoff, 2);
+
+    // or try to allocate space for every count up to a huge one
+    let sketch: Vec<KmerCount> = [1, 1, 2, 30, 30, u32::max_value() - 1]
+        .iter()
+        .enumerate()
+        .map(|(ix, count)| KmerCount {
+            hash: ix as u64,
+            kmer: vec![],
+            count: *count,
+            extra_count: 0,
+            label: None,
+        })
+        .collect();
+    let window = guess_filter_window(&sketch, 0.2);
+    assert_eq!(window.search_limit, u32::max_value() - 2);
+    assert_eq!(window.window_size, (u32::max_value() - 2) / 20);
+    assert_eq!(window.cutoff, u32::max_value() - 2);
 }
 
 pub fn filter_abundance(
@@ -4,23 +4,33 @@ extern crate capnp;
 #[macro_use]
 extern crate serde_derive;
 
-use std::fs::File;
-use std::io::{stdin, BufReader, Read};
+use std::cell::{Cell, RefCell};
+use std::fs::{canonicalize, File};
+use std::io::{self, stdin, BufReader, Read};
+use std::mem;
 use std::path::Path;
 use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::Mutex;
 
+use crossbeam_utils::thread;
 use failure::{format_err, Error};
 use memmap::MmapOptions;
 use needletail::formats::parse_sequence_reader;
+use needletail::SequenceRecord;
 use rayon::prelude::*;
 
-use crate::filtering::FilterParams;
+use crate::filtering::{FilterParams, FilterReport, KmerFilter};
 use crate::serialization::{
-    read_finch_file, read_mash_file, MultiSketch, Sketch, FINCH_BIN_EXT, FINCH_EXT, MASH_EXT,
+    read_finch_file, read_mash_file, ChecksumReader, MultiSketch, Provenance, Sketch,
+    FINCH_BIN_EXT, FINCH_EXT, MASH_EXT,
 };
-use crate::sketch_schemes::SketchParams;
+use crate::sketch_schemes::{SketchParams, SketchScheme};
 
+pub mod bloom;
 pub mod distance;
+pub mod estimators;
 pub mod filtering;
 pub mod sketch_schemes;
 // it would be nice if there was a `pub(in main)` or something for
@@ -38,21 +48,281 @@ pub fn sketch_files(
     sketch_params: &SketchParams,
     filters: &FilterParams,
 ) -> Result<Vec<Sketch>> {
-    let sketches: Result<Vec<Sketch>> = filenames
+    Ok(
+        sketch_files_with_reports(filenames, sketch_params, filters)?
+            .into_iter()
+            .map(|(sketch, _)| sketch)
+            .collect(),
+    )
+}
+
+/// Same as `sketch_files`, but also returns a report of what was filtered
+/// out of each sketch.
+pub fn sketch_files_with_reports(
+    filenames: &[&str],
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    let samples: Vec<Sample> = filenames.iter().map(|f| Sample::from_file(f)).collect();
+    sketch_samples_with_reports(&samples, &SketchNaming::default(), sketch_params, filters)
+}
+
+/// Sketch every record in each file separately (see
+/// `sketch_records_with_reports`)
+pub fn sketch_file_records_with_reports(
+    filenames: &[&str],
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    sketch_file_records_with_progress(filenames, sketch_params, filters, &|_| {})
+}
+
+/// Same as `sketch_file_records_with_reports`, but calls `progress` as
+/// each record is read and each file is finished.
+pub fn sketch_file_records_with_progress(
+    filenames: &[&str],
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+    progress: &(dyn Fn(Progress) + Sync),
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    let tracker = ProgressTracker::new(filenames.len(), progress);
+    let sketches: Result<Vec<_>> = filenames
         .par_iter()
         .map(|filename| {
-            // open the file with a special case to handle stdin
-            let sin = stdin();
             let reader: Box<dyn Read> = if filename == &"-" {
-                Box::new(sin.lock())
+                Box::new(stdin())
             } else {
-                Box::new(File::open(&Path::new(filename))?)
+                Box::new(
+                    File::open(Path::new(filename))
+                        .map_err(|e| format_err!("Error opening {}: {}", filename, e))?,
+                )
             };
-            // sketch!
-            Ok(sketch_stream(reader, filename, sketch_params, &filters)?)
+            let mut results = sketch_records_tracked(reader, sketch_params, filters, &tracker)?;
+            for (sketch, _) in &mut results {
+                if let Some(provenance) = &mut sketch.provenance {
+                    provenance.input_files = vec![provenance_path(filename)];
+                }
+            }
+            Ok(results)
         })
         .collect();
-    sketches
+    Ok(sketches?.into_iter().flatten().collect())
+}
+
+/// How far along sketching is (see e.g. `sketch_samples_with_progress`)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Progress {
+    /// Records read so far (from every file)
+    pub records: u64,
+    /// Bases read so far (from every file)
+    pub bases: u64,
+    /// Files that have been completely read
+    pub files_done: usize,
+    pub total_files: usize,
+}
+
+/// Keeps track of progress across the threads sketching different files
+struct ProgressTracker<'a> {
+    records: AtomicU64,
+    bases: AtomicU64,
+    files_done: AtomicUsize,
+    total_files: usize,
+    callback: &'a (dyn Fn(Progress) + Sync),
+}
+
+impl<'a> ProgressTracker<'a> {
+    fn new(total_files: usize, callback: &'a (dyn Fn(Progress) + Sync)) -> Self {
+        ProgressTracker {
+            records: AtomicU64::new(0),
+            bases: AtomicU64::new(0),
+            files_done: AtomicUsize::new(0),
+            total_files,
+            callback,
+        }
+    }
+
+    fn add(&self, records: u64, bases: u64, files_done: usize) {
+        self.records.fetch_add(records, Ordering::Relaxed);
+        self.bases.fetch_add(bases, Ordering::Relaxed);
+        self.files_done.fetch_add(files_done, Ordering::Relaxed);
+        (self.callback)(Progress {
+            records: self.records.load(Ordering::Relaxed),
+            bases: self.bases.load(Ordering::Relaxed),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            total_files: self.total_files,
+        });
+    }
+
+    /// An observer for a `StreamSketcher` that adds its progress to the total
+    fn observer(&self) -> impl FnMut(&SketchEvent) -> bool + '_ {
+        let mut last = SketchEvent::default();
+        move |event| {
+            self.add(
+                event.records - last.records,
+                event.bases - last.bases,
+                event.streams_done - last.streams_done,
+            );
+            last = *event;
+            true
+        }
+    }
+}
+
+/// A named group of sequence files (e.g. paired-end reads or several
+/// sequencing lanes) that should be sketched together into one sketch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub filenames: Vec<String>,
+}
+
+impl Sample {
+    /// A sample consisting of a single file (and named after it)
+    pub fn from_file(filename: &str) -> Self {
+        Sample {
+            name: filename.to_string(),
+            filenames: vec![filename.to_string()],
+        }
+    }
+}
+
+/// How to name the sketch made from each sample
+#[derive(Clone, Debug, PartialEq)]
+pub enum SketchNaming {
+    /// The sample's name (for single files this is the filename)
+    Filename,
+    /// The sample's name without any leading directories or file extensions
+    Stem,
+    /// The id of the first record in the sample
+    FirstId,
+    /// A template with `{filename}`, `{stem}`, `{id}` and `{desc}`
+    /// placeholders (`{id}` and `{desc}` are from the first record)
+    Template(String),
+}
+
+impl Default for SketchNaming {
+    fn default() -> Self {
+        SketchNaming::Filename
+    }
+}
+
+impl SketchNaming {
+    pub fn name(&self, sample_name: &str, first_id: &str, first_desc: &str) -> String {
+        match self {
+            SketchNaming::Filename => sample_name.to_string(),
+            SketchNaming::Stem => file_stem(sample_name),
+            // fall back to the sample name for empty files
+            SketchNaming::FirstId if first_id.is_empty() => sample_name.to_string(),
+            SketchNaming::FirstId => first_id.to_string(),
+            SketchNaming::Template(template) => template
+                .replace("{filename}", sample_name)
+                .replace("{stem}", &file_stem(sample_name))
+                .replace("{id}", first_id)
+                .replace("{desc}", first_desc),
+        }
+    }
+}
+
+/// Strip the directories and extension (including any compression
+/// extension) from a filename, e.g. `refseq/GCF_1.1.fna.gz` to `GCF_1.1`
+fn file_stem(filename: &str) -> String {
+    let mut name = Path::new(filename)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| filename.to_string());
+    for ext in &[".gz", ".bz2", ".xz", ".zst"] {
+        if name.ends_with(ext) {
+            name.truncate(name.len() - ext.len());
+            break;
+        }
+    }
+    match name.rfind('.') {
+        Some(ix) if ix > 0 => name[..ix].to_string(),
+        _ => name,
+    }
+}
+
+/// The path to record for an input file in a sketch's provenance; this is
+/// absolute so `finch validate` can find the file from any directory (stdin
+/// is left as `-`)
+fn provenance_path(filename: &str) -> String {
+    if filename == "-" {
+        return filename.to_string();
+    }
+    canonicalize(filename)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| filename.to_string())
+}
+
+/// Split a record's header into its id and description
+fn split_header(header: &[u8]) -> (String, String) {
+    let header = String::from_utf8_lossy(header);
+    let mut header_parts = header.trim().splitn(2, char::is_whitespace);
+    let id = header_parts.next().unwrap_or("").to_string();
+    let desc = header_parts.next().unwrap_or("").trim().to_string();
+    (id, desc)
+}
+
+/// Sketch each sample (all of its files are fed through the same sketcher
+/// so filtering thresholds and counts reflect the whole sample) and report
+/// what was filtered out of each sketch.
+pub fn sketch_samples_with_reports(
+    samples: &[Sample],
+    naming: &SketchNaming,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    sketch_samples_with_progress(samples, naming, sketch_params, filters, &|_| {})
+}
+
+/// Same as `sketch_samples_with_reports`, but calls `progress` as each
+/// record is read and each file is finished (from whichever thread is
+/// reading it, so `progress` should be quick).
+pub fn sketch_samples_with_progress(
+    samples: &[Sample],
+    naming: &SketchNaming,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+    progress: &(dyn Fn(Progress) + Sync),
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    let total_files = samples.iter().map(|s| s.filenames.len()).sum();
+    let tracker = ProgressTracker::new(total_files, progress);
+    // when there are fewer samples than threads, use the spare threads to
+    // sketch each sample in parallel
+    let n_threads = usize::max(
+        1,
+        rayon::current_num_threads() / usize::max(1, samples.len()),
+    );
+    samples
+        .par_iter()
+        .map(|sample| {
+            let mut readers: Vec<Box<dyn Read>> = Vec::with_capacity(sample.filenames.len());
+            for filename in &sample.filenames {
+                // open the file with a special case to handle stdin
+                if filename == "-" {
+                    readers.push(Box::new(stdin()));
+                } else {
+                    let file = File::open(&Path::new(filename))
+                        .map_err(|e| format_err!("Error opening {}: {}", filename, e))?;
+                    readers.push(Box::new(file));
+                }
+            }
+            // sketch!
+            let (mut sketch, report) = StreamSketcher::new(&sample.name, sketch_params, filters)
+                .naming(naming.clone())
+                .threads(n_threads)
+                .observer(tracker.observer())
+                .sketch_streams_with_report(readers)?;
+            if let Some(provenance) = &mut sketch.provenance {
+                provenance.input_files = sample
+                    .filenames
+                    .iter()
+                    .map(|f| provenance_path(f))
+                    .collect();
+            }
+            Ok((sketch, report))
+        })
+        .collect()
 }
 
 pub fn sketch_stream<'a>(
@@ -61,47 +331,473 @@ pub fn sketch_stream<'a>(
     sketch_params: &SketchParams,
     filters: &FilterParams,
 ) -> Result<Sketch> {
-    let mut filter_params = filters.clone();
-    let mut sketcher = sketch_params.create_sketcher();
+    StreamSketcher::new(name, sketch_params, filters).sketch(reader)
+}
+
+/// Same as `sketch_stream`, but also returns a report of what was filtered
+/// out of the sketch.
+pub fn sketch_stream_with_report<'a>(
+    reader: Box<dyn Read + 'a>,
+    name: &str,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<(Sketch, FilterReport)> {
+    StreamSketcher::new(name, sketch_params, filters).sketch_with_report(reader)
+}
+
+/// Sketch several streams of sequences together into a single sketch. The
+/// sketch is named according to `naming` (with `name` used as the filename)
+/// and the description of the first record is used as its comment.
+///
+/// Records are sketched in parallel (using as many threads as rayon's
+/// thread pool has) but this gives the same sketch as sketching serially.
+pub fn sketch_streams_with_report<'a>(
+    readers: Vec<Box<dyn Read + 'a>>,
+    name: &str,
+    naming: &SketchNaming,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<(Sketch, FilterReport)> {
+    StreamSketcher::new(name, sketch_params, filters)
+        .naming(naming.clone())
+        .sketch_streams_with_report(readers)
+}
+
+/// What's happened so far while sketching streams with a `StreamSketcher`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SketchEvent {
+    /// Bytes read from the streams so far (streams are read in chunks so
+    /// this can be a little ahead of the records that have been sketched)
+    pub bytes: u64,
+    /// Records sketched so far
+    pub records: u64,
+    /// Bases in the records sketched so far
+    pub bases: u64,
+    /// Streams that have been completely read
+    pub streams_done: usize,
+    /// Hashes in the sketch so far (before filtering); this isn't known
+    /// while records are being sketched on several threads, only once
+    /// each stream is finished
+    pub sketch_size: Option<usize>,
+}
+
+/// Sketches streams of sequences into a single sketch; this is the same as
+/// `sketch_stream` and friends, but the number of threads used can be set
+/// and an observer can follow along (and cancel sketching) as each record
+/// is sketched.
+pub struct StreamSketcher<'a> {
+    name: String,
+    naming: SketchNaming,
+    sketch_params: SketchParams,
+    filters: FilterParams,
+    n_threads: usize,
+    observer: Option<SketchObserver<'a>>,
+}
+
+type SketchObserver<'a> = Box<dyn FnMut(&SketchEvent) -> bool + 'a>;
+
+impl<'a> StreamSketcher<'a> {
+    /// A sketcher for streams that'll make a sketch named `name` (as its
+    /// filename; see `naming`)
+    pub fn new(name: &str, sketch_params: &SketchParams, filters: &FilterParams) -> Self {
+        StreamSketcher {
+            name: name.to_string(),
+            naming: SketchNaming::default(),
+            sketch_params: sketch_params.clone(),
+            filters: filters.clone(),
+            n_threads: rayon::current_num_threads(),
+            observer: None,
+        }
+    }
+
+    /// How to name the sketch (by default it's named `name`)
+    pub fn naming(mut self, naming: SketchNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// How many threads to sketch records on, including the one reading
+    /// them (by default as many as rayon's thread pool has); this gives the
+    /// same sketch for any number of threads
+    pub fn threads(mut self, n_threads: usize) -> Self {
+        self.n_threads = n_threads;
+        self
+    }
+
+    /// Call `observer` after each record is sketched and each stream is
+    /// finished; sketching is cancelled (with an error) if it returns
+    /// `false`
+    pub fn observer(mut self, observer: impl FnMut(&SketchEvent) -> bool + 'a) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    pub fn sketch<'r>(&mut self, reader: Box<dyn Read + 'r>) -> Result<Sketch> {
+        Ok(self.sketch_with_report(reader)?.0)
+    }
+
+    /// Same as `sketch`, but also returns a report of what was filtered out
+    /// of the sketch.
+    pub fn sketch_with_report<'r>(
+        &mut self,
+        reader: Box<dyn Read + 'r>,
+    ) -> Result<(Sketch, FilterReport)> {
+        self.sketch_streams_with_report(vec![reader])
+    }
+
+    /// Sketch several streams of sequences together into a single sketch
+    /// (see `sketch_streams_with_report`)
+    pub fn sketch_streams_with_report<'r>(
+        &mut self,
+        readers: Vec<Box<dyn Read + 'r>>,
+    ) -> Result<(Sketch, FilterReport)> {
+        let sketch_params = &self.sketch_params;
+        let mut filter_params = self.filters.clone();
+        if let Some(exclusion) = &filter_params.exclusion_filter {
+            exclusion.check_params(sketch_params)?;
+        }
+        let kmer_filter = KmerFilter::new(&filter_params);
+        let mut sketcher = sketch_params.create_sketcher();
+        sketcher.set_kmer_filter(kmer_filter.clone());
+        let mut first_header = None;
+        let mut checksums = Vec::with_capacity(readers.len());
+        let mut event = SketchEvent::default();
+        let observer = &mut self.observer;
+        for reader in readers {
+            let mut reader = ChecksumReader::new(reader);
+            let stream_start = event.bytes;
+            let bytes_read = process_stream(
+                &mut reader,
+                &mut *sketcher,
+                &kmer_filter,
+                self.n_threads,
+                |seq_type| {
+                    // disable filtering for FASTA files unless it was explicitly specified
+                    if filter_params.filter_on.is_none() {
+                        filter_params.filter_on = default_filtering(seq_type);
+                    }
+                },
+                |record| {
+                    if first_header.is_none() {
+                        first_header = Some(split_header(record.id));
+                    }
+                    event.records += 1;
+                    event.bases += record.bases as u64;
+                    event.bytes = stream_start + record.bytes_read;
+                    match observer {
+                        Some(observer) => {
+                            event.sketch_size = record.sketcher.map(|s| s.sketch_size());
+                            observer(&event)
+                        }
+                        None => true,
+                    }
+                },
+            )?;
+            let finished = match (bytes_read, observer.as_mut()) {
+                (None, _) => false,
+                (Some(bytes_read), Some(observer)) => {
+                    event.bytes = stream_start + bytes_read;
+                    event.streams_done += 1;
+                    event.sketch_size = Some(sketcher.sketch_size());
+                    observer(&event)
+                }
+                (Some(_), None) => true,
+            };
+            if !finished {
+                return Err(format_err!("Sketching {} was cancelled", self.name));
+            }
+            checksums.push(reader.hex_digest());
+        }
+
+        let (first_id, first_desc) = first_header.unwrap_or_default();
+        let name = self.naming.name(&self.name, &first_id, &first_desc);
+        let (mut sketch, report) =
+            finish_sketch(&*sketcher, &name, &first_desc, sketch_params, filter_params)?;
+        sketch.provenance = Some(Provenance::new(checksums));
+        Ok((sketch, report))
+    }
+}
+
+/// How many bases of sequence to hand to a sketching thread at once
+const BATCH_BASES: usize = 1 << 20;
+
+/// What `process_stream` tells its caller after each record
+struct RecordRead<'r> {
+    id: &'r [u8],
+    bases: usize,
+    /// Bytes read from the stream so far
+    bytes_read: u64,
+    /// The sketcher, if it's up to date with this record (i.e. unless
+    /// records are being sketched on other threads)
+    sketcher: Option<&'r dyn SketchScheme>,
+}
+
+/// Counts the bytes read through it and stops reading once `cancelled` is
+/// set (so the parser doesn't read the rest of the stream)
+struct StreamReader<'c, R> {
+    inner: R,
+    bytes_read: &'c Cell<u64>,
+    cancelled: &'c Cell<bool>,
+}
+
+impl<R: Read> Read for StreamReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.get() {
+            return Err(io::Error::new(io::ErrorKind::Other, "sketching cancelled"));
+        }
+        let n_read = self.inner.read(buf)?;
+        self.bytes_read.set(self.bytes_read.get() + n_read as u64);
+        Ok(n_read)
+    }
+}
+
+/// Parse a stream of sequences and feed every record through `sketcher`
+/// (`record_callback` is called after each record, in order, and can stop
+/// processing by returning `false`). Returns the number of bytes read from
+/// the stream or `None` if processing was stopped.
+///
+/// With more than one thread, records are parsed on this thread and handed
+/// out in batches to `n_threads - 1` worker threads (so `n_threads` are
+/// busy in all) that each have their own sketcher; these are merged into
+/// `sketcher` at the end, which gives the same result as sketching every
+/// record serially. Singleton filters need to see every
+/// kmer in order so they're always run serially.
+fn process_stream<R: Read>(
+    reader: R,
+    sketcher: &mut dyn SketchScheme,
+    kmer_filter: &KmerFilter,
+    n_threads: usize,
+    type_callback: impl FnMut(&'static str),
+    mut record_callback: impl FnMut(RecordRead) -> bool,
+) -> Result<Option<u64>> {
+    let bytes_read = Cell::new(0);
+    let cancelled = Cell::new(false);
+    let reader = StreamReader {
+        inner: reader,
+        bytes_read: &bytes_read,
+        cancelled: &cancelled,
+    };
+
+    if n_threads <= 1 || kmer_filter.has_singleton_filter() {
+        let parsed = parse_sequence_reader(reader, type_callback, |seq| {
+            if cancelled.get() {
+                return;
+            }
+            let (id, bases) = (seq.id.clone(), seq.seq.len());
+            sketcher.process(seq);
+            let keep_going = record_callback(RecordRead {
+                id: &id,
+                bases,
+                bytes_read: bytes_read.get(),
+                sketcher: Some(&*sketcher),
+            });
+            cancelled.set(!keep_going);
+        });
+        // (the parser fails when the reader stops after a cancellation)
+        if cancelled.get() {
+            return Ok(None);
+        }
+        parsed.map_err(|e| format_err!("{}", e.to_string()))?;
+        return Ok(Some(bytes_read.get()));
+    }
+
+    let sketch_params = sketcher.parameters();
+    // the workers run outside of rayon's pool so count this (parsing)
+    // thread as one of the threads we're allowed
+    let n_workers = n_threads - 1;
+    let (sender, receiver) = sync_channel::<Vec<SequenceRecord<'static>>>(2 * n_workers);
+    let receiver = Mutex::new(receiver);
+    let scoped = thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_workers)
+            .map(|_| {
+                scope.spawn(|_| {
+                    let mut worker = sketch_params.create_sketcher();
+                    worker.set_kmer_filter(kmer_filter.clone());
+                    loop {
+                        // (the lock is released before the batch is sketched)
+                        let batch = receiver.lock().unwrap().recv();
+                        match batch {
+                            Ok(batch) => batch.into_iter().for_each(|seq| worker.process(seq)),
+                            Err(_) => return worker,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut batch = Vec::new();
+        let mut batch_bases = 0;
+        let parsed = parse_sequence_reader(reader, type_callback, |seq| {
+            if cancelled.get() {
+                return;
+            }
+            let keep_going = record_callback(RecordRead {
+                id: &seq.id,
+                bases: seq.seq.len(),
+                bytes_read: bytes_read.get(),
+                sketcher: None,
+            });
+            cancelled.set(!keep_going);
+            batch_bases += seq.seq.len();
+            batch.push(SequenceRecord::new(
+                seq.id.into_owned().into(),
+                seq.seq.into_owned().into(),
+                seq.qual.map(|qual| qual.into_owned().into()),
+            ));
+            if batch_bases >= BATCH_BASES {
+                // (sending only fails if the workers have all panicked)
+                let capacity = batch.len();
+                let _ = sender.send(mem::replace(&mut batch, Vec::with_capacity(capacity)));
+                batch_bases = 0;
+            }
+        });
+        if !batch.is_empty() && !cancelled.get() {
+            let _ = sender.send(batch);
+        }
+        // let the workers know there's nothing else coming
+        drop(sender);
+        let workers: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("sketching thread panicked"))
+            .collect();
+        (parsed, workers)
+    });
+    let (parsed, workers) = scoped.expect("sketching thread panicked");
+    if cancelled.get() {
+        return Ok(None);
+    }
+    parsed.map_err(|e| format_err!("{}", e.to_string()))?;
+    for worker in workers {
+        sketcher.merge_from(&*worker)?;
+    }
+    Ok(Some(bytes_read.get()))
+}
+
+/// Sketch every record in a stream separately (e.g. for the contigs of an
+/// assembly or a collection of plasmids). Each sketch is named after its
+/// record's id and the rest of the record's header is used as the comment.
+///
+/// The singleton (Bloom) filter can't be used here since every record would
+/// need a Bloom filter of its own.
+pub fn sketch_records_with_reports<'a>(
+    reader: Box<dyn Read + 'a>,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    let tracker = ProgressTracker::new(1, &|_| {});
+    sketch_records_tracked(reader, sketch_params, filters, &tracker)
+}
+
+fn sketch_records_tracked<'a>(
+    reader: Box<dyn Read + 'a>,
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+    tracker: &ProgressTracker,
+) -> Result<Vec<(Sketch, FilterReport)>> {
+    if filters.bloom_filter_size.is_some() {
+        return Err(format_err!(
+            "The singleton (Bloom) filter can't be used when sketching records individually"
+        ));
+    }
+    if let Some(exclusion) = &filters.exclusion_filter {
+        exclusion.check_params(sketch_params)?;
+    }
+    let kmer_filter = KmerFilter::new(filters);
+    // the record callback needs to know what the file type callback decided
+    let filter_params = RefCell::new(filters.clone());
+    let mut results = Vec::new();
+    let mut error = None;
+    let mut reader = ChecksumReader::new(reader);
     parse_sequence_reader(
-        reader,
+        &mut reader,
         |seq_type| {
-            // disable filtering for FASTA files unless it was explicitly specified
+            let mut filter_params = filter_params.borrow_mut();
             if filter_params.filter_on.is_none() {
-                filter_params.filter_on = match seq_type {
-                    "FASTA" => Some(false),
-                    "FASTQ" => Some(true),
-                    _ => panic!("Unknown sequence type"),
-                };
+                filter_params.filter_on = default_filtering(seq_type);
             }
         },
         |seq| {
+            if error.is_some() {
+                return;
+            }
+            tracker.add(1, seq.seq.len() as u64, 0);
+            let (name, comment) = split_header(&seq.id);
+
+            let mut sketcher = sketch_params.create_sketcher();
+            sketcher.set_kmer_filter(kmer_filter.clone());
             sketcher.process(seq);
+            let filter_params = filter_params.borrow().clone();
+            match finish_sketch(&*sketcher, &name, &comment, sketch_params, filter_params) {
+                Ok(result) => results.push(result),
+                Err(e) => error = Some(e),
+            }
         },
     )
     .map_err(|e| format_err!("{}", e.to_string()))?;
+    if let Some(e) = error {
+        return Err(e);
+    }
+    tracker.add(0, 0, 1);
+    // the checksum's only known once the whole stream has been read
+    let provenance = Provenance::new(vec![reader.hex_digest()]);
+    for (sketch, _) in &mut results {
+        sketch.provenance = Some(provenance.clone());
+    }
+    Ok(results)
+}
 
+fn default_filtering(seq_type: &str) -> Option<bool> {
+    match seq_type {
+        "FASTA" => Some(false),
+        "FASTQ" => Some(true),
+        _ => panic!("Unknown sequence type"),
+    }
+}
+
+/// Filter the sketcher's hashes and turn them into a `Sketch`
+pub(crate) fn finish_sketch<S: SketchScheme + ?Sized>(
+    sketcher: &S,
+    name: &str,
+    comment: &str,
+    sketch_params: &SketchParams,
+    mut filter_params: FilterParams,
+) -> Result<(Sketch, FilterReport)> {
     let (seq_length, num_valid_kmers) = sketcher.total_bases_and_kmers();
     let hashes = sketcher.to_vec();
 
+    // HyperLogLog registers aren't kmer counts so they can't be filtered
+    if let SketchParams::HyperLogLog { .. } = sketch_params {
+        filter_params.filter_on = Some(false);
+    }
+
     // do filtering
-    let mut filtered_hashes = filter_params.filter_counts(&hashes);
+    let (mut filtered_hashes, mut report) = filter_params.filter_counts_with_report(&hashes);
     sketch_params.process_post_filter(&mut filtered_hashes, name)?;
+    report.name = name.to_string();
+    report.post_filter_size = filtered_hashes.len();
+    // (`process_post_filter` would have failed if this wasn't `no_strict`)
+    report.undersized = match sketch_params {
+        SketchParams::Mash { final_size, .. } => filtered_hashes.len() < *final_size,
+        _ => false,
+    };
 
-    Ok(Sketch {
+    let sketch = Sketch {
         name: name.to_string(),
         seq_length,
         num_valid_kmers,
-        comment: "".to_string(),
+        comment: comment.to_string(),
         hashes: filtered_hashes,
         filter_params,
         sketch_params: sketch_params.clone(),
-    })
+        provenance: None,
+    };
+    Ok((sketch, report))
 }
 
 pub fn open_sketch_file(filename: &str) -> Result<Vec<Sketch>> {
-    let file = File::open(filename).map_err(|_| format_err!("Error opening {}", &filename))?;
-    if filename.ends_with(MASH_EXT) {
+    let file =
+        File::open(filename).map_err(|e| format_err!("Error opening {}: {}", &filename, e))?;
+    let sketches = if filename.ends_with(MASH_EXT) {
         let mut buf_reader = BufReader::new(file);
         read_mash_file(&mut buf_reader)
     } else if filename.ends_with(FINCH_BIN_EXT) {
@@ -109,10 +805,171 @@ pub fn open_sketch_file(filename: &str) -> Result<Vec<Sketch>> {
         read_finch_file(&mut buf_reader)
     } else if filename.ends_with(FINCH_EXT) || filename.ends_with(".json") {
         let mapped = unsafe { MmapOptions::new().map(&file)? };
-        let multisketch: MultiSketch = serde_json::from_slice(&mapped)
-            .map_err(|_| format_err!("Error parsing {}", &filename))?;
-        multisketch.to_sketches()
+        serde_json::from_slice::<MultiSketch>(&mapped)
+            .map_err(|e| e.into())
+            .and_then(|multisketch| multisketch.to_sketches())
     } else {
-        Err(format_err!("File suffix is not *.bsk, *.msh, or *.sk"))
+        return Err(format_err!("File suffix is not *.bsk, *.msh, or *.sk"));
+    };
+    sketches.map_err(|e| format_err!("Error parsing {}: {}", &filename, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_sketching() {
+        // enough random reads for more than one batch
+        let mut state = 42u64;
+        let mut fastq = Vec::new();
+        for ix in 0..3 * BATCH_BASES / 200 {
+            let seq: Vec<u8> = (0..100)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6_364_136_223_846_793_005)
+                        .wrapping_add(1);
+                    b"ACGT"[(state >> 62) as usize]
+                })
+                .collect();
+            fastq.extend_from_slice(format!("@read{}\n", ix).as_bytes());
+            fastq.extend_from_slice(&seq);
+            fastq.extend_from_slice(b"\n+\n");
+            fastq.extend(seq.iter().map(|_| b'I'));
+            fastq.push(b'\n');
+        }
+        let filters = FilterParams {
+            filter_on: Some(false),
+            ..Default::default()
+        };
+        let sketch = |sketch_params: &SketchParams, n_threads: usize| {
+            StreamSketcher::new("test", sketch_params, &filters)
+                .naming(SketchNaming::FirstId)
+                .threads(n_threads)
+                .sketch(Box::new(&fastq[..]))
+                .unwrap()
+        };
+
+        let mut sketch_params = SketchParams::default();
+        let serial = sketch(&sketch_params, 1);
+        let parallel = sketch(&sketch_params, 4);
+        assert_eq!(parallel.name, "read0");
+        assert_eq!(parallel.hashes, serial.hashes);
+        assert_eq!(parallel.seq_length, serial.seq_length);
+        assert_eq!(parallel.num_valid_kmers, serial.num_valid_kmers);
+
+        if let SketchParams::Mash {
+            kmers_to_sketch, ..
+        } = &mut sketch_params
+        {
+            *kmers_to_sketch = 100_000;
+        }
+        let serial = sketch(&sketch_params, 1);
+        assert_eq!(sketch(&sketch_params, 3).hashes, serial.hashes);
+    }
+
+    fn small_sketch_params() -> SketchParams {
+        let mut sketch_params = SketchParams::default();
+        if let SketchParams::Mash {
+            kmer_length,
+            no_strict,
+            ..
+        } = &mut sketch_params
+        {
+            *kmer_length = 3;
+            *no_strict = true;
+        }
+        sketch_params
+    }
+
+    #[test]
+    fn test_progress() {
+        let fasta: &[u8] = b">seq1\nACGTACGTAC\n>seq2\nACGTAC\n";
+        let readers: Vec<Box<dyn Read>> = vec![Box::new(fasta), Box::new(fasta)];
+        let events = Mutex::new(Vec::new());
+        let record_event = |p| events.lock().unwrap().push(p);
+        let tracker = ProgressTracker::new(readers.len(), &record_event);
+        StreamSketcher::new("test", &small_sketch_params(), &FilterParams::default())
+            .threads(1)
+            .observer(tracker.observer())
+            .sketch_streams_with_report(readers)
+            .unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            events[1],
+            Progress {
+                records: 2,
+                bases: 16,
+                files_done: 0,
+                total_files: 2,
+            }
+        );
+        assert_eq!(events[2].files_done, 1);
+        assert_eq!(
+            events[5],
+            Progress {
+                records: 4,
+                bases: 32,
+                files_done: 2,
+                total_files: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_observer() {
+        let fasta: &[u8] = b">seq1\nACGTACGTAC\n>seq2\nACGTAC\n>seq3\nTTTTTGGG\n";
+        let sketch_params = small_sketch_params();
+        let filters = FilterParams::default();
+        for n_threads in &[1, 2] {
+            let mut events = Vec::new();
+            let sketch = StreamSketcher::new("test", &sketch_params, &filters)
+                .threads(*n_threads)
+                .observer(|event| {
+                    events.push(*event);
+                    true
+                })
+                .sketch(Box::new(fasta))
+                .unwrap();
+            assert_eq!(events.len(), 4);
+            assert_eq!(events[0].records, 1);
+            assert_eq!(events[0].bases, 10);
+            assert_eq!(events[2].records, 3);
+            assert_eq!(events[2].streams_done, 0);
+            assert_eq!(
+                events[3],
+                SketchEvent {
+                    bytes: fasta.len() as u64,
+                    records: 3,
+                    bases: 24,
+                    streams_done: 1,
+                    sketch_size: Some(sketch.hashes.len()),
+                }
+            );
+            if *n_threads == 1 {
+                // CGT/ACG, GTA/TAC and ACG/CGT (again)
+                assert_eq!(events[0].sketch_size, Some(2));
+                assert_eq!(events[1].sketch_size, Some(2));
+            } else {
+                assert_eq!(events[0].sketch_size, None);
+            }
+        }
+
+        // cancel after the first record
+        let mut n_events = 0;
+        let result = StreamSketcher::new("test", &sketch_params, &filters)
+            .threads(1)
+            .observer(|_| {
+                n_events += 1;
+                false
+            })
+            .sketch(Box::new(fasta));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Sketching test was cancelled"
+        );
+        assert_eq!(n_events, 1);
     }
 }
@@ -5,24 +5,39 @@ extern crate failure;
 extern crate finch;
 extern crate serde_json;
 
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{create_dir_all, metadata, File};
 use std::io::{stderr, stdout, Write};
+use std::mem::discriminant;
+use std::path::Path;
 use std::process::exit;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rayon::prelude::*;
 
 use finch::distance::distance;
+use finch::estimators::estimate_cardinality;
+use finch::filtering::{FilterParams, FilterReport};
 use finch::serialization::{
-    write_finch_file, write_mash_file, MultiSketch, Sketch, SketchDistance, FINCH_BIN_EXT,
-    FINCH_EXT, MASH_EXT,
+    checksum_reader, format_timestamp, harmonize_sketches, write_finch_file, write_mash_file,
+    Downsample, MultiSketch, Sketch, SketchDistance, FINCH_BIN_EXT, FINCH_EXT, MASH_EXT,
+};
+use finch::sketch_schemes::SketchParams;
+use finch::statistics::{binned_hist, sparse_hist, HistBin, HistBins};
+use finch::{
+    open_sketch_file, sketch_file_records_with_progress, sketch_samples_with_progress, Progress,
+    Result, Sample,
 };
-use finch::statistics::{cardinality, hist};
-use finch::{open_sketch_file, sketch_files, Result};
 
 use finch::main_parsing::{
-    add_filter_options, add_sketch_options, get_float_arg, get_int_arg, parse_filter_options,
-    parse_sketch_options, update_sketch_params,
+    add_filter_options, add_input_options, add_sample_options, add_sketch_options, get_float_arg,
+    get_int_arg, is_sketch_file, parse_exclusion_options, parse_filter_options,
+    parse_input_options, parse_naming_options, parse_sample_options, parse_sketch_options,
+    update_sketch_params,
 };
 
 fn add_output_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
@@ -96,7 +111,7 @@ fn run() -> Result<()> {
             Arg::with_name("INPUT")
                 .help("The file(s) to sketch")
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&["sample_sheet", "input_list"]),
         )
         .arg(
             Arg::with_name("binary_format")
@@ -110,9 +125,35 @@ fn run() -> Result<()> {
                 .long("mash-binary-format")
                 .conflicts_with("binary_format")
                 .help("Outputs sketch in a binary format compatible with `mash`"),
+        )
+        .arg(
+            Arg::with_name("out_dir")
+                .long("out-dir")
+                .help("When sketching in place, write the sketches into this directory instead of next to their inputs")
+                .conflicts_with_all(&["output_file", "std_out"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("incremental")
+                .long("incremental")
+                .help("When sketching in place, skip inputs whose sketch is newer than them and was made with the same parameters")
+                .conflicts_with_all(&["output_file", "std_out"]),
+        )
+        .arg(
+            Arg::with_name("no_kmers")
+                .long("no-kmers")
+                .help("Don't save the kmers themselves in the sketch (only their hashes and counts)"),
+        )
+        .arg(
+            Arg::with_name("filter_report")
+                .long("filter-report")
+                .help("Write a JSON report of how many kmers were filtered from each sketch (and why) to this file")
+                .takes_value(true),
         );
     sketch_command = add_output_options(sketch_command);
     sketch_command = add_filter_options(sketch_command);
+    sketch_command = add_input_options(sketch_command);
+    sketch_command = add_sample_options(sketch_command);
     sketch_command = add_sketch_options(sketch_command);
 
     let mut dist_command = SubCommand::with_name("dist")
@@ -121,7 +162,7 @@ fn run() -> Result<()> {
             Arg::with_name("INPUT")
                 .help("Sketchfile(s) to make comparisons for")
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&["sample_sheet", "input_list"]),
         )
         .arg(
             Arg::with_name("pairwise")
@@ -154,6 +195,8 @@ fn run() -> Result<()> {
         );
     dist_command = add_output_options(dist_command);
     dist_command = add_filter_options(dist_command);
+    dist_command = add_input_options(dist_command);
+    dist_command = add_sample_options(dist_command);
     dist_command = add_sketch_options(dist_command);
 
     let mut hist_command = SubCommand::with_name("hist")
@@ -162,10 +205,24 @@ fn run() -> Result<()> {
             Arg::with_name("INPUT")
                 .help("Generate histograms from these file(s)")
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&["sample_sheet", "input_list"]),
+        )
+        .arg(
+            Arg::with_name("bins")
+                .long("bins")
+                .help("Group coverage levels into bins; either `log` for bins that double in width or a number for fixed-width bins. Binned histograms are output as a list of {lower, upper, count} objects with only non-empty bins included.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sparse")
+                .long("sparse")
+                .help("Output each histogram as a {depth: count} object with only the depths that have kmers (instead of a list with every depth up to the highest)")
+                .conflicts_with("bins"),
         );
     hist_command = add_output_options(hist_command);
     hist_command = add_filter_options(hist_command);
+    hist_command = add_input_options(hist_command);
+    hist_command = add_sample_options(hist_command);
     hist_command = add_sketch_options(hist_command);
 
     let mut info_command = SubCommand::with_name("info")
@@ -174,48 +231,142 @@ fn run() -> Result<()> {
             Arg::with_name("INPUT")
                 .help("Return stats on these file(s)")
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&["sample_sheet", "input_list"]),
+        )
+        .arg(
+            Arg::with_name("cardinality_method")
+                .long("cardinality-method")
+                .help("Estimator to use for the number of unique kmers (defaults to `scaled` for scaled sketches and `kmv` otherwise)")
+                .possible_values(&["kmv", "scaled", "hll"])
+                .takes_value(true),
         );
     info_command = add_output_options(info_command);
     info_command = add_filter_options(info_command);
+    info_command = add_input_options(info_command);
+    info_command = add_sample_options(info_command);
     info_command = add_sketch_options(info_command);
 
+    let validate_command = SubCommand::with_name("validate")
+        .about("Check that sketches are well-formed and that their inputs haven't changed")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sketch file(s) to validate")
+                .multiple(true)
+                .required(true),
+        );
+
+    let mut downsample_command = SubCommand::with_name("downsample")
+        .about("Reduce sketches to a smaller size or scale")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sketch file(s) to downsample")
+                .multiple(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("n_hashes")
+                .short("n")
+                .long("n-hashes")
+                .help("Keep this many of the lowest hashes from each sketch")
+                .required_unless("scale")
+                .conflicts_with("scale")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .help("Keep only hashes below this fraction of the hash space (making scaled sketches)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("binary_format")
+                .short("b")
+                .long("finch-binary-format")
+                .help("Outputs sketch to a finch-native binary format"),
+        )
+        .arg(
+            Arg::with_name("mash_binary_format")
+                .short("B")
+                .long("mash-binary-format")
+                .conflicts_with("binary_format")
+                .help("Outputs sketch in a binary format compatible with `mash`"),
+        );
+    downsample_command = add_output_options(downsample_command);
+
+    let mut strip_command = SubCommand::with_name("strip")
+        .about("Remove the kmers from sketches (leaving their hashes and counts)")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Sketch file(s) to strip")
+                .multiple(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("binary_format")
+                .short("b")
+                .long("finch-binary-format")
+                .help("Outputs sketch to a finch-native binary format"),
+        )
+        .arg(
+            Arg::with_name("mash_binary_format")
+                .short("B")
+                .long("mash-binary-format")
+                .conflicts_with("binary_format")
+                .help("Outputs sketch in a binary format compatible with `mash`"),
+        );
+    strip_command = add_output_options(strip_command);
+
     let matches = App::new("finch")
         .version(crate_version!())
         .author(crate_authors!())
         .about("Tool for working with genomic MinHash sketches")
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .help("Number of threads to use (defaults to the number of CPUs)")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .help("Don't show progress (only shown when stderr is a terminal)")
+                .global(true),
+        )
         .subcommand(sketch_command)
         .subcommand(dist_command)
         .subcommand(hist_command)
         .subcommand(info_command)
+        .subcommand(validate_command)
+        .subcommand(downsample_command)
+        .subcommand(strip_command)
         .get_matches();
 
+    if let (_, Some(matches)) = matches.subcommand() {
+        if matches.is_present("threads") {
+            let n_threads: usize = get_int_arg(matches, "threads")?;
+            if n_threads == 0 {
+                bail!("threads must be a positive integer");
+            }
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build_global()
+                .map_err(|e| format_err!("Could not start threads: {}", e))?;
+        }
+    }
+
     if let Some(matches) = matches.subcommand_matches("sketch") {
-        let file_ext = if matches.is_present("binary_format") {
-            FINCH_BIN_EXT
-        } else if matches.is_present("mash_binary_format") {
-            MASH_EXT
-        } else {
-            FINCH_EXT
-        };
+        let file_ext = sketch_file_ext(matches);
         if matches.is_present("output_file") || matches.is_present("std_out") {
-            let sketches = parse_mash_files(matches)?;
+            let mut sketches = parse_mash_files(matches)?;
+            strip_kmers_if_requested(matches, &mut sketches);
             let output = matches.value_of("output_file");
 
             output_to(
-                |writer| {
-                    if matches.is_present("binary_format") {
-                        write_finch_file(writer, &sketches)?;
-                    } else if matches.is_present("mash_binary_format") {
-                        write_mash_file(writer, &sketches)?;
-                    } else {
-                        let multisketch = MultiSketch::from_sketches(&sketches)?;
-                        serde_json::to_writer(writer, &multisketch)?;
-                    }
-                    Ok(())
-                },
+                |writer| write_sketches(matches, writer, &sketches),
                 output,
                 &file_ext,
             )?;
@@ -227,7 +378,14 @@ fn run() -> Result<()> {
         let old_mode = matches.is_present("old_dist_mode");
 
         let max_dist = get_float_arg(matches, "max_distance", 1f64)?;
-        let all_sketches = parse_mash_files(matches)?;
+        let mut all_sketches = parse_mash_files(matches)?;
+        // malformed sketches (e.g. with unsorted hashes) give wrong distances
+        // instead of failing, so catch them up front
+        for sketch in &all_sketches {
+            sketch.validate()?;
+        }
+        // and compare everything at the same resolution
+        harmonize_sketches(&mut all_sketches)?;
 
         let mut query_sketches = Vec::new();
         if matches.is_present("pairwise") {
@@ -265,43 +423,110 @@ fn run() -> Result<()> {
             ".json",
         )?;
     } else if let Some(matches) = matches.subcommand_matches("hist") {
-        let mut hist_map: HashMap<String, Vec<u64>> = HashMap::new();
+        let bins: Option<HistBins> = match matches.value_of("bins") {
+            Some(bins) => Some(bins.parse()?),
+            None => None,
+        };
         let multisketch = parse_mash_files(matches)?;
 
-        for sketch in multisketch {
-            hist_map.insert(sketch.name.to_string(), hist(&sketch.hashes));
-        }
+        if let Some(bins) = bins {
+            let mut hist_map: HashMap<String, Vec<HistBin>> = HashMap::new();
+            for sketch in multisketch {
+                hist_map.insert(sketch.name.to_string(), binned_hist(&sketch.hashes, bins));
+            }
 
-        output_to(
-            |writer| {
-                serde_json::to_writer(writer, &hist_map)
-                    .map_err(|_| format_err!("Could not serialize JSON to file"))?;
-                Ok(())
-            },
-            matches.value_of("output_file"),
-            ".json",
-        )?;
+            output_to(
+                |writer| {
+                    serde_json::to_writer(writer, &hist_map)
+                        .map_err(|_| format_err!("Could not serialize JSON to file"))?;
+                    Ok(())
+                },
+                matches.value_of("output_file"),
+                ".json",
+            )?;
+        } else if matches.is_present("sparse") {
+            let mut hist_map: HashMap<String, BTreeMap<u32, u64>> = HashMap::new();
+            for sketch in multisketch {
+                hist_map.insert(sketch.name.to_string(), sparse_hist(&sketch.hashes));
+            }
+
+            output_to(
+                |writer| {
+                    serde_json::to_writer(writer, &hist_map)
+                        .map_err(|_| format_err!("Could not serialize JSON to file"))?;
+                    Ok(())
+                },
+                matches.value_of("output_file"),
+                ".json",
+            )?;
+        } else {
+            let mut hist_map: HashMap<String, Vec<u64>> = HashMap::new();
+            for sketch in multisketch {
+                hist_map.insert(sketch.name.to_string(), dense_hist(&sketch)?);
+            }
+
+            output_to(
+                |writer| {
+                    serde_json::to_writer(writer, &hist_map)
+                        .map_err(|_| format_err!("Could not serialize JSON to file"))?;
+                    Ok(())
+                },
+                matches.value_of("output_file"),
+                ".json",
+            )?;
+        }
     } else if let Some(matches) = matches.subcommand_matches("info") {
         // TODO: this should probably output JSON
         let multisketch = parse_mash_files(matches)?;
+        let cardinality_method = match matches.value_of("cardinality_method") {
+            Some(method) => Some(method.parse()?),
+            None => None,
+        };
 
         for sketch in multisketch {
             print!("{}", &sketch.name);
             println!(" (from {}bp)", sketch.seq_length);
+            if let Some(provenance) = &sketch.provenance {
+                println!(
+                    "  Sketched by finch {} at {}",
+                    provenance.finch_version,
+                    format_timestamp(provenance.timestamp)
+                );
+                for (filename, checksum) in provenance
+                    .input_files
+                    .iter()
+                    .zip(&provenance.input_checksums)
+                {
+                    println!("  Input: {} (SHA-256 {})", filename, checksum);
+                }
+                if !provenance.command_line.is_empty() {
+                    println!("  Command: {}", provenance.command_line);
+                }
+            }
             let kmers = &sketch.hashes;
-            if let Ok(c) = cardinality(kmers) {
-                println!("  Estimated # of Unique Kmers: {}", c);
+            let c = estimate_cardinality(&sketch, cardinality_method)?;
+            println!(
+                "  Estimated # of Unique Kmers: {:.0} (95% CI {:.0}-{:.0})",
+                c.estimate, c.lower, c.upper
+            );
+            // HyperLogLog registers don't have kmers or counts to report on
+            if let SketchParams::HyperLogLog { .. } = sketch.sketch_params {
+                continue;
             }
 
-            let histogram = hist(kmers);
-            let mean = histogram
+            let mean = kmers
                 .iter()
-                .enumerate()
-                .map(|(i, v)| ((i as f32 + 1f32) * *v as f32, *v as f32))
+                .map(|k| (k.count as f32, 1f32))
                 .fold((0f32, 0f32), |e, s| (e.0 + s.0, e.1 + s.1));
             println!("  Estimated Average Depth: {}x", mean.0 / mean.1);
 
+            // (sketches made with `--no-kmers` don't have anything to count)
+            if !sketch.has_kmers() {
+                println!("  Estimated % GC: unknown (no kmers in sketch)");
+                continue;
+            }
             let mut total_gc: u64 = 0;
+            let mut total_bases: u64 = 0;
             for kmer in kmers {
                 total_gc += kmer
                     .kmer
@@ -311,84 +536,548 @@ fn run() -> Result<()> {
                         _ => 0,
                     })
                     .sum::<u64>();
+                total_bases += kmer.kmer.len() as u64 * u64::from(kmer.count);
             }
-            let total_bases = if kmers.is_empty() {
-                0f32
-            } else {
-                mean.0 * kmers[0].kmer.len() as f32
-            };
             println!(
                 "  Estimated % GC: {}%",
-                100f32 * total_gc as f32 / total_bases
+                100f32 * total_gc as f32 / total_bases as f32
             );
         }
+    } else if let Some(matches) = matches.subcommand_matches("validate") {
+        let mut n_failed = 0;
+        for filename in matches.values_of("INPUT").unwrap() {
+            let sketches = match open_sketch_file(filename) {
+                Ok(sketches) => sketches,
+                Err(e) => {
+                    println!("{}: UNREADABLE ({})", filename, e);
+                    n_failed += 1;
+                    continue;
+                }
+            };
+            // don't bother checking the inputs of sketches that are broken
+            let (valid, invalid): (Vec<Sketch>, Vec<Sketch>) = sketches
+                .into_iter()
+                .partition(|sketch| sketch.find_problems().is_empty());
+            for sketch in &invalid {
+                for problem in sketch.find_problems() {
+                    println!("{}: INVALID ({})", sketch.name, problem);
+                }
+            }
+            n_failed += invalid.len();
+            n_failed += validate_provenance(&valid);
+        }
+        if n_failed > 0 {
+            bail!("{} sketch(es) failed validation", n_failed);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("downsample") {
+        let target = if matches.is_present("n_hashes") {
+            Downsample::Size(get_int_arg(matches, "n_hashes")?)
+        } else {
+            Downsample::Scale(get_float_arg(matches, "scale", 1f64)?)
+        };
+        let mut sketches = Vec::new();
+        for filename in matches.values_of("INPUT").unwrap() {
+            for sketch in open_sketch_file(filename)? {
+                sketches.push(sketch.downsample(target)?);
+            }
+        }
+
+        output_to(
+            |writer| write_sketches(matches, writer, &sketches),
+            matches.value_of("output_file"),
+            sketch_file_ext(matches),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("strip") {
+        let mut sketches = Vec::new();
+        for filename in matches.values_of("INPUT").unwrap() {
+            sketches.extend(open_sketch_file(filename)?);
+        }
+        sketches.iter_mut().for_each(Sketch::strip_kmers);
+
+        output_to(
+            |writer| write_sketches(matches, writer, &sketches),
+            matches.value_of("output_file"),
+            sketch_file_ext(matches),
+        )?;
     }
     Ok(())
 }
 
 fn generate_sketch_files(matches: &ArgMatches, file_ext: &str) -> Result<()> {
-    let filenames: Vec<_> = matches
-        .values_of("INPUT")
-        .ok_or_else(|| format_err!("Bad INPUT"))?
-        .collect();
+    let inputs = parse_input_options(matches, true)?;
 
     let kmer_length: u8 = get_int_arg(matches, "kmer_length")?;
-    let filters = parse_filter_options(matches, kmer_length)?;
+    let mut filters = parse_filter_options(matches, kmer_length)?;
     let sketch_params = parse_sketch_options(matches, kmer_length, filters.filter_on)?;
+    filters.exclusion_filter = parse_exclusion_options(matches, &sketch_params)?;
+
+    for input in &inputs {
+        if is_sketch_file(&input.filenames[0]) {
+            bail!("Filename {} is not a sequence file?", input.filenames[0]);
+        }
+    }
+
+    if let Some(out_dir) = matches.value_of("out_dir") {
+        create_dir_all(out_dir)
+            .map_err(|e| format_err!("Could not create directory {}: {}", out_dir, e))?;
+    }
+
+    let naming = parse_naming_options(matches);
+    let incremental = matches.is_present("incremental");
+    if matches.is_present("individual") {
+        // each file is read on a single thread so sketch (and write out)
+        // several at once
+        let out_filenames = sketch_file_paths(matches, &inputs, file_ext)?;
+        let progress = ProgressPrinter::new(matches, inputs.len());
+        let reports: Vec<Vec<FilterReport>> = inputs
+            .par_iter()
+            .zip(&out_filenames)
+            .map(|(input, out_filename)| {
+                if incremental
+                    && is_up_to_date(
+                        matches,
+                        out_filename,
+                        &input.filenames,
+                        &sketch_params,
+                        &filters,
+                    )
+                {
+                    progress.skip_files(1);
+                    return Ok(Vec::new());
+                }
+                let (mut sketches, reports): (Vec<_>, Vec<_>) = sketch_file_records_with_progress(
+                    &[&input.filenames[0]],
+                    &sketch_params,
+                    &filters,
+                    &progress.batch(),
+                )?
+                .into_iter()
+                .unzip();
+                record_command_line(&mut sketches);
+                strip_kmers_if_requested(matches, &mut sketches);
+                write_sketch_file(matches, out_filename, &sketches)?;
+                Ok(reports)
+            })
+            .collect::<Result<_>>()?;
+        let reports: Vec<_> = reports.into_iter().flatten().collect();
+        return write_filter_report(matches, &reports);
+    }
 
-    for filename in filenames {
-        if filename.ends_with(".json")
-            || filename.ends_with(FINCH_EXT)
-            || filename.ends_with(FINCH_BIN_EXT)
-            || filename.ends_with(MASH_EXT)
+    let samples = parse_sample_options(matches, &inputs)?;
+    let progress = ProgressPrinter::new(matches, samples.iter().map(|s| s.filenames.len()).sum());
+    let mut out_filenames = Vec::with_capacity(samples.len());
+    let mut to_sketch = Vec::with_capacity(samples.len());
+    for (sample, out_filename) in samples
+        .iter()
+        .zip(sketch_file_paths(matches, &samples, file_ext)?)
+    {
+        if incremental
+            && is_up_to_date(
+                matches,
+                &out_filename,
+                &sample.filenames,
+                &sketch_params,
+                &filters,
+            )
         {
-            bail!("Filename {} is not a sequence file?", filename);
+            progress.skip_files(sample.filenames.len());
+            continue;
         }
+        out_filenames.push(out_filename);
+        to_sketch.push(sample.clone());
+    }
+    // sketch all the samples together so the library can share the threads
+    // out between them
+    let (mut sketches, reports): (Vec<_>, Vec<_>) = sketch_samples_with_progress(
+        &to_sketch,
+        &naming,
+        &sketch_params,
+        &filters,
+        &progress.batch(),
+    )?
+    .into_iter()
+    .unzip();
+    record_command_line(&mut sketches);
+    strip_kmers_if_requested(matches, &mut sketches);
+    out_filenames
+        .par_iter()
+        .zip(&sketches)
+        .map(|(out_filename, sketch)| {
+            write_sketch_file(matches, out_filename, std::slice::from_ref(sketch))
+        })
+        .collect::<Result<()>>()?;
+    write_filter_report(matches, &reports)
+}
 
-        let sketches = sketch_files(&[filename], &sketch_params, &filters)?;
+/// The deepest histogram that `finch hist` will write out as a list (deeper
+/// ones need `--sparse` or `--bins`)
+const MAX_DENSE_HIST_DEPTH: u32 = 1_000_000;
 
-        let out_filename = filename.to_string() + file_ext;
-        let mut out = File::create(&out_filename)
-            .map_err(|_| format_err!("Could not open {}", out_filename))?;
-        if matches.is_present("binary_format") {
-            write_finch_file(&mut out, &sketches)?;
-        } else if matches.is_present("mash_binary_format") {
-            write_mash_file(&mut out, &sketches)?;
-        } else {
-            let multisketch: MultiSketch = MultiSketch::from_sketches(&sketches)?;
-            serde_json::to_writer(&mut out, &multisketch)?;
+/// A list of the number of kmers at each depth (starting from 1)
+fn dense_hist(sketch: &Sketch) -> Result<Vec<u64>> {
+    let sparse = sparse_hist(&sketch.hashes);
+    let max_depth = sparse.keys().next_back().cloned().unwrap_or(0);
+    if max_depth > MAX_DENSE_HIST_DEPTH {
+        bail!(
+            "{} has kmers with a depth of {}; use `--sparse` or `--bins` for its histogram",
+            sketch.name,
+            max_depth
+        );
+    }
+    let mut counts = vec![0; max_depth as usize];
+    for (depth, count) in sparse {
+        counts[depth as usize - 1] = count;
+    }
+    Ok(counts)
+}
+
+/// Where to write the sketches for each of the samples when sketching in
+/// place, failing if any two would be written to the same file (e.g. files
+/// with the same name from different directories with `--out-dir`)
+fn sketch_file_paths(
+    matches: &ArgMatches,
+    samples: &[Sample],
+    file_ext: &str,
+) -> Result<Vec<String>> {
+    let mut seen: HashMap<String, &str> = HashMap::with_capacity(samples.len());
+    let mut paths = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let path = sketch_file_path(matches, &sample.name, file_ext);
+        if let Some(other) = seen.insert(path.clone(), &sample.name) {
+            bail!(
+                "The sketches for {} and {} would both be written to {}",
+                other,
+                sample.name,
+                path
+            );
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Where to write the sketch for a sample when sketching in place
+fn sketch_file_path(matches: &ArgMatches, name: &str, file_ext: &str) -> String {
+    match matches.value_of("out_dir") {
+        Some(out_dir) => {
+            let file_name = Path::new(name)
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_else(|| name.to_string());
+            Path::new(out_dir)
+                .join(file_name + file_ext)
+                .to_string_lossy()
+                .into_owned()
         }
+        None => name.to_string() + file_ext,
+    }
+}
+
+/// Check if an existing sketch file is newer than all of its inputs and
+/// was made with the same sketching and filtering parameters (so it doesn't
+/// need to be remade)
+fn is_up_to_date(
+    matches: &ArgMatches,
+    sketch_filename: &str,
+    input_filenames: &[String],
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> bool {
+    let modified = |filename: &str| metadata(filename).and_then(|m| m.modified()).ok();
+    let sketch_modified = match modified(sketch_filename) {
+        Some(m) => m,
+        None => return false,
+    };
+    for filename in input_filenames {
+        match modified(filename) {
+            Some(m) if m <= sketch_modified => {}
+            _ => return false,
+        }
+    }
+
+    let sketches = match open_sketch_file(sketch_filename) {
+        Ok(sketches) => sketches,
+        Err(_) => return false,
+    };
+    !sketches.is_empty()
+        && sketches.iter().all(|sketch| {
+            sketch_params
+                .check_compatibility(&sketch.sketch_params)
+                .is_none()
+                && discriminant(sketch_params) == discriminant(&sketch.sketch_params)
+                && sketch_params.hash_info().3 == sketch.sketch_params.hash_info().3
+                && sketch_params.expected_size() == sketch.sketch_params.expected_size()
+                && sketch.filter_params.matches_requested(filters)
+                // empty sketches look the same with or without kmers
+                && (sketch.is_empty() || sketch.has_kmers() != matches.is_present("no_kmers"))
+        })
+}
+
+/// Remove the kmers from the sketches if `--no-kmers` was given
+fn strip_kmers_if_requested(matches: &ArgMatches, sketches: &mut [Sketch]) {
+    if matches.is_present("no_kmers") {
+        sketches.iter_mut().for_each(Sketch::strip_kmers);
+    }
+}
+
+/// The extension for sketch files in the requested output format
+fn sketch_file_ext(matches: &ArgMatches) -> &'static str {
+    if matches.is_present("binary_format") {
+        FINCH_BIN_EXT
+    } else if matches.is_present("mash_binary_format") {
+        MASH_EXT
+    } else {
+        FINCH_EXT
+    }
+}
+
+/// Write sketches out in the requested output format
+fn write_sketches(matches: &ArgMatches, writer: &mut dyn Write, sketches: &[Sketch]) -> Result<()> {
+    if matches.is_present("binary_format") {
+        write_finch_file(writer, sketches)?;
+    } else if matches.is_present("mash_binary_format") {
+        write_mash_file(writer, sketches)?;
+    } else {
+        let multisketch: MultiSketch = MultiSketch::from_sketches(sketches)?;
+        serde_json::to_writer(writer, &multisketch)?;
     }
     Ok(())
 }
 
-fn parse_mash_files(matches: &ArgMatches) -> Result<Vec<Sketch>> {
-    let filenames: Vec<_> = matches
-        .values_of("INPUT")
-        .ok_or_else(|| format_err!("Bad INPUT"))?
-        .collect();
-
-    let mut sketch_filenames = Vec::new();
-    let mut seq_filenames = Vec::new();
-    for filename in filenames {
-        if filename.ends_with(".json")
-            || filename.ends_with(FINCH_EXT)
-            || filename.ends_with(FINCH_BIN_EXT)
-            || filename.ends_with(MASH_EXT)
+fn write_sketch_file(matches: &ArgMatches, out_filename: &str, sketches: &[Sketch]) -> Result<()> {
+    let mut out =
+        File::create(out_filename).map_err(|_| format_err!("Could not open {}", out_filename))?;
+    write_sketches(matches, &mut out, sketches)
+}
+
+fn write_filter_report(matches: &ArgMatches, reports: &[FilterReport]) -> Result<()> {
+    if let Some(filename) = matches.value_of("filter_report") {
+        let mut out =
+            File::create(filename).map_err(|_| format_err!("Could not open {}", filename))?;
+        serde_json::to_writer(&mut out, reports)?;
+    }
+    Ok(())
+}
+
+/// Sketch sequence files (and write out a filter report if requested)
+fn sketch_seq_files(
+    matches: &ArgMatches,
+    seq_files: &[Sample],
+    sketch_params: &SketchParams,
+    filters: &FilterParams,
+) -> Result<Vec<Sketch>> {
+    let results = if matches.is_present("individual") {
+        let filenames: Vec<&str> = seq_files.iter().map(|s| &s.filenames[0][..]).collect();
+        let progress = ProgressPrinter::new(matches, filenames.len());
+        let update = progress.batch();
+        sketch_file_records_with_progress(&filenames, sketch_params, filters, &update)?
+    } else {
+        let samples = parse_sample_options(matches, seq_files)?;
+        let naming = parse_naming_options(matches);
+        let progress =
+            ProgressPrinter::new(matches, samples.iter().map(|s| s.filenames.len()).sum());
+        let update = progress.batch();
+        sketch_samples_with_progress(&samples, &naming, sketch_params, filters, &update)?
+    };
+    let (mut sketches, reports): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+    write_filter_report(matches, &reports)?;
+    record_command_line(&mut sketches);
+    Ok(sketches)
+}
+
+/// Shows how far along sketching is on stderr (unless it's not a terminal,
+/// e.g. in a pipeline, or `--quiet` was passed)
+struct ProgressPrinter {
+    enabled: bool,
+    total_files: usize,
+    records: AtomicU64,
+    bases: AtomicU64,
+    files_done: AtomicUsize,
+    last_printed: Mutex<Option<Instant>>,
+}
+
+impl ProgressPrinter {
+    fn new(matches: &ArgMatches, total_files: usize) -> Self {
+        ProgressPrinter {
+            enabled: !matches.is_present("quiet") && atty::is(atty::Stream::Stderr),
+            total_files,
+            records: AtomicU64::new(0),
+            bases: AtomicU64::new(0),
+            files_done: AtomicUsize::new(0),
+            last_printed: Mutex::new(None),
+        }
+    }
+
+    /// A progress callback for one call into the library (several of which
+    /// can be running at once); it's called from any thread as records are
+    /// read with the running totals for that call.
+    fn batch(&self) -> impl Fn(Progress) + Sync + '_ {
+        let seen = Mutex::new(Progress::default());
+        move |progress| {
+            if !self.enabled {
+                return;
+            }
+            // updates from different threads can arrive out of order, so
+            // only add on whatever's new since the largest totals so far
+            {
+                let mut seen = seen.lock().unwrap();
+                self.records.fetch_add(
+                    progress.records.saturating_sub(seen.records),
+                    Ordering::Relaxed,
+                );
+                self.bases
+                    .fetch_add(progress.bases.saturating_sub(seen.bases), Ordering::Relaxed);
+                self.files_done.fetch_add(
+                    progress.files_done.saturating_sub(seen.files_done),
+                    Ordering::Relaxed,
+                );
+                seen.records = u64::max(seen.records, progress.records);
+                seen.bases = u64::max(seen.bases, progress.bases);
+                seen.files_done = usize::max(seen.files_done, progress.files_done);
+            }
+            self.print_if_due();
+        }
+    }
+
+    fn print_if_due(&self) {
+        // don't hold up the sketching threads to redraw the line
+        if let Ok(mut last_printed) = self.last_printed.try_lock() {
+            let now = Instant::now();
+            let due = match *last_printed {
+                Some(t) => now - t >= Duration::from_millis(200),
+                None => true,
+            };
+            if due {
+                *last_printed = Some(now);
+                self.print();
+            }
+        }
+    }
+
+    fn print(&self) {
+        eprint!(
+            "\r{} of {} files sketched ({} records, {})\x1b[K",
+            self.files_done.load(Ordering::Relaxed),
+            self.total_files,
+            self.records.load(Ordering::Relaxed),
+            format_bases(self.bases.load(Ordering::Relaxed)),
+        );
+    }
+
+    /// Count files that didn't need sketching as done
+    fn skip_files(&self, n_files: usize) {
+        self.files_done.fetch_add(n_files, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ProgressPrinter {
+    fn drop(&mut self) {
+        // finish the line (even if there was an error so it's not printed
+        // on the end of the progress line)
+        if self.enabled && self.last_printed.lock().unwrap().is_some() {
+            self.print();
+            eprintln!();
+        }
+    }
+}
+
+fn format_bases(bases: u64) -> String {
+    match bases {
+        0..=999 => format!("{} bp", bases),
+        1_000..=999_999 => format!("{:.1} kbp", bases as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.1} Mbp", bases as f64 / 1e6),
+        _ => format!("{:.1} Gbp", bases as f64 / 1e9),
+    }
+}
+
+/// Add the command used to make these sketches to their provenance
+fn record_command_line(sketches: &mut [Sketch]) {
+    let command_line = env::args()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    for sketch in sketches {
+        if let Some(provenance) = &mut sketch.provenance {
+            provenance.command_line = command_line.clone();
+        }
+    }
+}
+
+/// Check that the input files recorded in each sketch's provenance (if
+/// they're still around) haven't changed, returning how many sketches
+/// didn't match.
+fn validate_provenance(sketches: &[Sketch]) -> usize {
+    let mut n_failed = 0;
+    for sketch in sketches {
+        let provenance = match &sketch.provenance {
+            Some(p) => p,
+            None => {
+                println!("{}: no provenance recorded", sketch.name);
+                continue;
+            }
+        };
+        let mut failed = false;
+        for (filename, checksum) in provenance
+            .input_files
+            .iter()
+            .zip(&provenance.input_checksums)
         {
-            sketch_filenames.push(filename);
-        } else {
-            seq_filenames.push(filename);
+            if filename == "-" {
+                println!(
+                    "{}: {} was read from stdin; skipping",
+                    sketch.name, filename
+                );
+                continue;
+            }
+            match File::open(filename).map(checksum_reader) {
+                Ok(Ok(ref current)) if current == checksum => {
+                    println!("{}: {} OK", sketch.name, filename);
+                }
+                Ok(Ok(current)) => {
+                    println!(
+                        "{}: {} CHANGED (checksum {} but sketched from {})",
+                        sketch.name, filename, current, checksum
+                    );
+                    failed = true;
+                }
+                _ => {
+                    println!("{}: {} MISSING", sketch.name, filename);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            n_failed += 1;
         }
     }
+    n_failed
+}
+
+fn parse_mash_files(matches: &ArgMatches) -> Result<Vec<Sketch>> {
+    let (sketch_files, seq_files): (Vec<Sample>, Vec<Sample>) =
+        parse_input_options(matches, false)?
+            .into_iter()
+            .partition(|input| is_sketch_file(&input.filenames[0]));
 
     let kmer_length: u8 = get_int_arg(matches, "kmer_length")?;
     let mut filters = parse_filter_options(matches, kmer_length)?;
     let mut sketch_params = parse_sketch_options(matches, kmer_length, filters.filter_on)?;
 
-    let mut filename_iter = sketch_filenames.iter();
-    if let Some(first_filename) = filename_iter.next() {
-        let mut sketches = open_sketch_file(first_filename)?;
+    // read all the sketch files up front (in parallel)
+    let sketch_filenames: Vec<&str> = sketch_files.iter().map(|s| &s.filenames[0][..]).collect();
+    let mut opened_sketches = sketch_filenames
+        .par_iter()
+        .map(|filename| open_sketch_file(filename))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    if let Some(mut sketches) = opened_sketches.next() {
+        let first_filename = sketch_filenames[0];
 
         update_sketch_params(matches, &mut sketch_params, &sketches[0], first_filename)?;
         // we also have to handle updating filter options separately because
@@ -405,8 +1094,7 @@ fn parse_mash_files(matches: &ArgMatches) -> Result<Vec<Sketch>> {
         }
 
         // and then handle the rest of the sketch files
-        for filename in filename_iter {
-            let extra_sketches = open_sketch_file(filename)?;
+        for extra_sketches in opened_sketches {
             // check new sketches are compatible with original file
             for sketch in &extra_sketches {
                 if let Some((name, v1, v2)) =
@@ -430,12 +1118,16 @@ fn parse_mash_files(matches: &ArgMatches) -> Result<Vec<Sketch>> {
         }
 
         // now handle the sequences
-        let extra_sketches = sketch_files(&seq_filenames, &sketch_params, &filters)?;
+        if !seq_files.is_empty() || matches.is_present("sample_sheet") {
+            filters.exclusion_filter = parse_exclusion_options(matches, &sketch_params)?;
+        }
+        let extra_sketches = sketch_seq_files(matches, &seq_files, &sketch_params, &filters)?;
         sketches.extend(extra_sketches);
         Ok(sketches)
     } else {
         // now handle the sequences
-        sketch_files(&seq_filenames, &sketch_params, &filters)
+        filters.exclusion_filter = parse_exclusion_options(matches, &sketch_params)?;
+        sketch_seq_files(matches, &seq_files, &sketch_params, &filters)
     }
 }
 
@@ -1,13 +1,18 @@
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader};
 use std::mem::{discriminant, size_of};
+use std::path::Path;
+use std::result::Result as StdResult;
 use std::str::FromStr;
 
 use clap::{App, Arg, ArgMatches};
 use failure::{bail, format_err};
 
-use crate::filtering::FilterParams;
-use crate::serialization::Sketch;
-use crate::sketch_schemes::SketchParams;
+use crate::filtering::{ExclusionSet, FilterParams};
+use crate::serialization::{Sketch, FINCH_BIN_EXT, FINCH_EXT, MASH_EXT};
+use crate::sketch_schemes::{hyperloglog, HashFunction, SketchParams};
 use crate::Result;
+use crate::{open_sketch_file, Sample, SketchNaming};
 
 pub fn get_int_arg<T: FromStr>(matches: &ArgMatches, key: &str) -> Result<T> {
     let display_key = key.replace("_", "-");
@@ -70,6 +75,31 @@ pub fn add_filter_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
          .help("The assumed error rate (as a percentage) used to dynamically determine the minimum coverage threshold from the kmer count histogram. This threshold is then used in place of the min-abun-filter if it's more stringent.")
          .takes_value(true)
          .default_value("1"))
+    .arg(Arg::with_name("min_qual")
+         .long("min-qual")
+         .help("Skip kmers containing any base with a (Phred+33) quality score below this [FASTQ only]")
+         .takes_value(true))
+    .arg(Arg::with_name("max_kmer_errors")
+         .long("max-kmer-errors")
+         .help("Skip kmers whose expected number of errors (from the base quality scores) is above this [FASTQ only]")
+         .takes_value(true))
+    .arg(Arg::with_name("dust_filter")
+         .long("dust-filter")
+         .help("Skip low-complexity kmers with a DUST score above this (from 0 for kmers without repeated trinucleotides to (k - 2) / 2 for homopolymers)")
+         .takes_value(true))
+    .arg(Arg::with_name("exclude")
+         .long("exclude")
+         .help("Never sketch kmers found in this sketch or FASTA/Q file (e.g. a host genome)")
+         .takes_value(true))
+    .arg(Arg::with_name("exclude_bloom_size")
+         .long("exclude-bloom-size")
+         .help("Memory (in MB) for the Bloom filter used to hold the kmers of an `--exclude` FASTA/Q file")
+         .takes_value(true)
+         .default_value("512"))
+    .arg(Arg::with_name("bloom_filter")
+         .long("bloom-filter")
+         .help("Only sketch kmers seen at least twice, tracking kmers seen once in a Bloom filter of this size (in MB, per input file)")
+         .takes_value(true))
 }
 
 pub fn parse_filter_options(matches: &ArgMatches, kmer_length: u8) -> Result<FilterParams> {
@@ -101,14 +131,331 @@ pub fn parse_filter_options(matches: &ArgMatches, kmer_length: u8) -> Result<Fil
 
     let strand_filter = get_float_arg::<f64>(matches, "strand_filter", 1f64)?;
 
+    let qual_filter = if matches.is_present("min_qual") {
+        Some(get_int_arg::<u8>(matches, "min_qual")?)
+    } else {
+        None
+    };
+    let kmer_err_filter = if matches.is_present("max_kmer_errors") {
+        Some(get_float_arg::<f64>(
+            matches,
+            "max_kmer_errors",
+            f64::from(kmer_length),
+        )?)
+    } else {
+        None
+    };
+
+    let dust_filter = if matches.is_present("dust_filter") {
+        Some(get_float_arg::<f64>(
+            matches,
+            "dust_filter",
+            f64::from(kmer_length) / 2.,
+        )?)
+    } else {
+        None
+    };
+
+    let bloom_filter_size = if matches.is_present("bloom_filter") {
+        Some(get_int_arg::<usize>(matches, "bloom_filter")? * 1024 * 1024)
+    } else {
+        None
+    };
+
     Ok(FilterParams {
         filter_on,
         abun_filter: (min_abun_filter, max_abun_filter),
         err_filter,
         strand_filter,
+        qual_filter,
+        kmer_err_filter,
+        dust_filter,
+        exclusion_filter: None,
+        bloom_filter_size,
     })
 }
 
+/// Load the `--exclude` kmers (this has to happen after the final
+/// `SketchParams` are known so the kmers are hashed the same way).
+pub fn parse_exclusion_options(
+    matches: &ArgMatches,
+    sketch_params: &SketchParams,
+) -> Result<Option<ExclusionSet>> {
+    let filename = match matches.value_of("exclude") {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let exclusion_set = if is_sketch_file(filename) {
+        let sketches = open_sketch_file(filename)?;
+        ExclusionSet::from_sketches(filename, &sketches, sketch_params)?
+    } else {
+        let bloom_size = get_int_arg::<usize>(matches, "exclude_bloom_size")?;
+        let file = File::open(filename).map_err(|_| format_err!("Error opening {}", filename))?;
+        ExclusionSet::from_sequences(
+            filename,
+            Box::new(file),
+            sketch_params,
+            bloom_size * 1024 * 1024,
+        )?
+    };
+    Ok(Some(exclusion_set))
+}
+
+pub fn add_sample_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(Arg::with_name("pair")
+         .long("pair")
+         .help("Treat consecutive sequence file inputs as pairs (e.g. R1 and R2) and sketch each pair together (named after the first file)"))
+    .arg(Arg::with_name("sample_sheet")
+         .long("sample-sheet")
+         .conflicts_with("pair")
+         .help("Sketch the files listed in this tab-separated file together as one sample per line (formatted `name<TAB>file1<TAB>file2...`)")
+         .takes_value(true))
+    .arg(Arg::with_name("individual")
+         .short("i")
+         .long("individual")
+         .conflicts_with_all(&["pair", "sample_sheet"])
+         .help("Make a separate sketch for every record in each sequence file (named after the record's id)"))
+    .arg(Arg::with_name("name_by")
+         .long("name-by")
+         .help("Name each sketch after its file/sample name (`filename`), the name without directories or extensions (`stem`) or the id of its first record (`id`)")
+         .possible_values(&["filename", "stem", "id"])
+         .default_value("filename"))
+    .arg(Arg::with_name("name_template")
+         .long("name-template")
+         .help("Name each sketch with a template containing `{filename}`, `{stem}`, `{id}` and/or `{desc}` (the last two from the first record)")
+         .takes_value(true))
+}
+
+/// Determine how sketches should be named from the `--name-by` and
+/// `--name-template` options
+pub fn parse_naming_options(matches: &ArgMatches) -> SketchNaming {
+    if let Some(template) = matches.value_of("name_template") {
+        return SketchNaming::Template(template.to_string());
+    }
+    match matches.value_of("name_by") {
+        Some("stem") => SketchNaming::Stem,
+        Some("id") => SketchNaming::FirstId,
+        _ => SketchNaming::Filename,
+    }
+}
+
+/// Group the sequence files into samples according to the `--pair` and
+/// `--sample-sheet` options (by default every file is its own sample).
+pub fn parse_sample_options(matches: &ArgMatches, seq_files: &[Sample]) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    if matches.is_present("pair") {
+        let pairs = seq_files.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            bail!("--pair needs an even number of sequence files");
+        }
+        for pair in pairs {
+            samples.push(Sample {
+                name: pair[0].name.clone(),
+                filenames: pair.iter().flat_map(|s| s.filenames.clone()).collect(),
+            });
+        }
+    } else {
+        samples.extend(seq_files.iter().cloned());
+    }
+    if let Some(sheet_filename) = matches.value_of("sample_sheet") {
+        let file = File::open(sheet_filename)
+            .map_err(|_| format_err!("Error opening {}", sheet_filename))?;
+        samples.extend(parse_sample_sheet(BufReader::new(file))?);
+    }
+    Ok(samples)
+}
+
+/// Parse a tab-separated sample sheet with a sample name followed by one or
+/// more filenames on each line (blank lines and lines starting with `#` are
+/// skipped).
+pub fn parse_sample_sheet<R: BufRead>(reader: R) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for (ix, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t').map(|f| f.trim()).filter(|f| !f.is_empty());
+        let name = fields.next().unwrap_or("");
+        let filenames: Vec<String> = fields.map(|f| f.to_string()).collect();
+        if filenames.is_empty() {
+            bail!(
+                "Sample sheet line {} has no files for sample {}",
+                ix + 1,
+                name
+            );
+        }
+        samples.push(Sample {
+            name: name.to_string(),
+            filenames,
+        });
+    }
+    Ok(samples)
+}
+
+pub fn add_input_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(Arg::with_name("input_list")
+         .long("input-list")
+         .help("Also read inputs from this file (one path per line, optionally followed by a tab and the name to use for the sketch)")
+         .takes_value(true))
+    .arg(Arg::with_name("glob")
+         .long("glob")
+         .help("Only use files with names matching this pattern (e.g. `*.fna.gz`) when reading directory inputs; by default all sequence and sketch files are used (sketch files are always skipped by `sketch`)")
+         .multiple(true)
+         .number_of_values(1)
+         .takes_value(true))
+}
+
+/// Collect all the inputs from the positional arguments and `--input-list`,
+/// expanding any directories into the files they contain (recursively).
+/// Every input is returned as its own (single file) `Sample`.
+///
+/// With `sequences_only` set, sketch files are skipped when expanding
+/// directories (so sketching a directory in place can be rerun).
+pub fn parse_input_options(matches: &ArgMatches, sequences_only: bool) -> Result<Vec<Sample>> {
+    let globs: Vec<&str> = matches
+        .values_of("glob")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+
+    let mut inputs: Vec<(String, Option<String>)> = matches
+        .values_of("INPUT")
+        .map(|v| v.map(|f| (f.to_string(), None)).collect())
+        .unwrap_or_default();
+    if let Some(list_filename) = matches.value_of("input_list") {
+        let file = File::open(list_filename)
+            .map_err(|_| format_err!("Error opening {}", list_filename))?;
+        inputs.extend(parse_input_list(BufReader::new(file))?);
+    }
+
+    let mut samples = Vec::new();
+    for (path, name) in inputs {
+        if Path::new(&path).is_dir() {
+            if name.is_some() {
+                bail!("Directory {} can't be given a name", path);
+            }
+            let mut filenames = Vec::new();
+            find_input_files(Path::new(&path), &globs, sequences_only, &mut filenames)?;
+            samples.extend(filenames.iter().map(|f| Sample::from_file(f)));
+        } else {
+            let mut sample = Sample::from_file(&path);
+            if let Some(name) = name {
+                sample.name = name;
+            }
+            samples.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+/// Parse a list of input paths with one path per line and an optional
+/// tab-separated name after it (blank lines and lines starting with `#` are
+/// skipped).
+pub fn parse_input_list<R: BufRead>(reader: R) -> Result<Vec<(String, Option<String>)>> {
+    let mut inputs = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(2, '\t').map(|f| f.trim());
+        let path = fields.next().unwrap_or("").to_string();
+        let name = fields
+            .next()
+            .filter(|n| !n.is_empty())
+            .map(|n| n.to_string());
+        inputs.push((path, name));
+    }
+    Ok(inputs)
+}
+
+fn find_input_files(
+    dir: &Path,
+    globs: &[&str],
+    sequences_only: bool,
+    filenames: &mut Vec<String>,
+) -> Result<()> {
+    let mut entries = read_dir(dir)
+        .map_err(|e| format_err!("Error reading directory {}: {}", dir.display(), e))?
+        .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?))))
+        .collect::<StdResult<Vec<_>, _>>()?;
+    // sort so the sketches come out in a consistent order
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, file_type) in entries {
+        // `file_type` doesn't follow symlinks so symlinked directories (which
+        // could loop back on themselves) are skipped
+        if file_type.is_dir() {
+            find_input_files(&path, globs, sequences_only, filenames)?;
+            continue;
+        } else if path.is_dir() {
+            continue;
+        }
+        let file_name = match path.file_name() {
+            Some(f) => f.to_string_lossy(),
+            None => continue,
+        };
+        let wanted = if sequences_only && is_sketch_file(&file_name) {
+            false
+        } else if globs.is_empty() {
+            is_sketch_file(&file_name) || is_sequence_file(&file_name)
+        } else {
+            globs.iter().any(|g| glob_match(g, &file_name))
+        };
+        if wanted {
+            filenames.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Does this filename have one of the sketch extensions?
+pub fn is_sketch_file(filename: &str) -> bool {
+    filename.ends_with(".json")
+        || filename.ends_with(FINCH_EXT)
+        || filename.ends_with(FINCH_BIN_EXT)
+        || filename.ends_with(MASH_EXT)
+}
+
+fn is_sequence_file(filename: &str) -> bool {
+    let mut filename = filename;
+    for ext in &[".gz", ".bz2", ".xz", ".zst"] {
+        if filename.ends_with(ext) {
+            filename = &filename[..filename.len() - ext.len()];
+            break;
+        }
+    }
+    [".fa", ".fasta", ".fna", ".ffn", ".fas", ".fq", ".fastq"]
+        .iter()
+        .any(|ext| filename.ends_with(ext))
+}
+
+/// Match a filename against a shell-style pattern (`*` matches any run of
+/// characters and `?` matches any single character)
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where to resume from if the current attempt at matching a `*` fails
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // let the `*` absorb one more character and try again
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 pub fn add_sketch_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
     // note we're defining groups for the arguments depending on which
     // sketch_type they're used with, but clap doesn't allow us to flag
@@ -117,7 +464,7 @@ pub fn add_sketch_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
          .short("s")
          .long("sketch-type")
          .takes_value(true)
-         .possible_values(&["mash", "scaled", "none"])
+         .possible_values(&["mash", "scaled", "none", "hyperloglog"])
          .default_value("mash")
          .help("What type of sketching to perform"))
     .arg(Arg::with_name("kmer_length")
@@ -145,7 +492,13 @@ pub fn add_sketch_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
          .takes_value(true)
          // .groups(&["mash", "scaled"])
          .default_value("0")
-         .help("Seed murmurhash with this value [`sketch-type=mash` and `sketch-type=scaled`]"))
+         .help("Seed the hash function with this value [`sketch-type=mash` and `sketch-type=scaled`]"))
+    .arg(Arg::with_name("hash_function")
+         .long("hash")
+         .takes_value(true)
+         .possible_values(&["murmurhash3", "xxhash3", "nthash"])
+         .default_value("murmurhash3")
+         .help("The function used to hash kmers; `nthash` is a rolling hash so it's the fastest, but only `murmurhash3` sketches are compatible with Mash [`sketch-type=mash`, `sketch-type=scaled` and `sketch-type=hyperloglog`]"))
     .arg(Arg::with_name("oversketch")
          .long("oversketch")
          .takes_value(true)
@@ -157,6 +510,12 @@ pub fn add_sketch_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
          .long("no-strict")
          // .group("mash")
          .help("Allow sketching files with fewer kmers than `n_hashes` [`sketch-type=mash` only]"))
+    .arg(Arg::with_name("hll_precision")
+         .long("hll-precision")
+         .takes_value(true)
+         // .group("hyperloglog")
+         .default_value("12")
+         .help("Number of bits used to index the HyperLogLog registers; the sketch has 2^precision registers and a relative error of ~1.04/sqrt(2^precision) [`sketch-type=hyperloglog` only]"))
 }
 
 pub fn parse_sketch_options(
@@ -169,6 +528,9 @@ pub fn parse_sketch_options(
             if matches.occurrences_of("scale") != 0 {
                 bail!("`scale` can not be specified for `mash` sketch types")
             }
+            if matches.occurrences_of("hll_precision") != 0 {
+                bail!("`hll_precision` can not be specified for `mash` sketch types")
+            }
             let final_size: usize = get_int_arg(matches, "n_hashes")?;
             let oversketch: usize = get_int_arg(matches, "oversketch")?;
             let sketch_size = final_size * oversketch;
@@ -184,6 +546,7 @@ pub fn parse_sketch_options(
                 no_strict: matches.is_present("no_strict"),
                 kmer_length,
                 hash_seed: get_int_arg(matches, "seed")?,
+                hash_function: parse_hash_function(matches),
             }
         }
         "scaled" => {
@@ -193,6 +556,9 @@ pub fn parse_sketch_options(
             if matches.occurrences_of("no_strict") != 0 {
                 bail!("`no_strict` can not be specified for `scaled` sketch types")
             }
+            if matches.occurrences_of("hll_precision") != 0 {
+                bail!("`hll_precision` can not be specified for `scaled` sketch types")
+            }
             let kmers_to_sketch: usize = get_int_arg(matches, "n_hashes")?;
             let scale: f64 = get_float_arg(matches, "scale", 1.)?;
             SketchParams::Scaled {
@@ -200,6 +566,7 @@ pub fn parse_sketch_options(
                 kmer_length,
                 scale,
                 hash_seed: get_int_arg(matches, "seed")?,
+                hash_function: parse_hash_function(matches),
             }
         }
         "none" => {
@@ -209,6 +576,9 @@ pub fn parse_sketch_options(
             if matches.occurrences_of("seed") != 0 {
                 bail!("`seed` can not be specified for `none` sketch types")
             }
+            if matches.occurrences_of("hash_function") != 0 {
+                bail!("`hash` can not be specified for `none` sketch types")
+            }
             if matches.occurrences_of("oversketch") != 0 {
                 bail!("`oversketch` can not be specified for `none` sketch types")
             }
@@ -218,12 +588,72 @@ pub fn parse_sketch_options(
             if matches.occurrences_of("scale") != 0 {
                 bail!("`scale` can not be specified for `none` sketch types")
             }
+            if matches.occurrences_of("hll_precision") != 0 {
+                bail!("`hll_precision` can not be specified for `none` sketch types")
+            }
             SketchParams::AllCounts { kmer_length }
         }
+        "hyperloglog" => {
+            if matches.occurrences_of("n_hashes") != 0 {
+                bail!("`n_hashes` can not be specified for `hyperloglog` sketch types")
+            }
+            if matches.occurrences_of("oversketch") != 0 {
+                bail!("`oversketch` can not be specified for `hyperloglog` sketch types")
+            }
+            if matches.occurrences_of("no_strict") != 0 {
+                bail!("`no_strict` can not be specified for `hyperloglog` sketch types")
+            }
+            if matches.occurrences_of("scale") != 0 {
+                bail!("`scale` can not be specified for `hyperloglog` sketch types")
+            }
+            let precision: u8 = get_int_arg(matches, "hll_precision")?;
+            if !hyperloglog::PRECISIONS.contains(&precision) {
+                bail!(
+                    "hll-precision must be between {} and {}",
+                    hyperloglog::PRECISIONS.start(),
+                    hyperloglog::PRECISIONS.end()
+                );
+            }
+            SketchParams::HyperLogLog {
+                kmer_length,
+                precision,
+                hash_seed: get_int_arg(matches, "seed")?,
+                hash_function: parse_hash_function(matches),
+            }
+        }
         _ => panic!("A unknown sketch type was selected"),
     })
 }
 
+fn parse_hash_function(matches: &ArgMatches) -> HashFunction {
+    match matches.value_of("hash_function").unwrap_or("murmurhash3") {
+        "xxhash3" => HashFunction::XxHash3,
+        "nthash" => HashFunction::NtHash,
+        _ => HashFunction::MurmurHash3,
+    }
+}
+
+fn update_hash_function(
+    matches: &ArgMatches,
+    hash_function: &mut HashFunction,
+    new_sketch_params: &SketchParams,
+    name: &str,
+) -> Result<()> {
+    if let Some(new_hash_function) = new_sketch_params.hash_function() {
+        if matches.occurrences_of("hash_function") == 0 {
+            *hash_function = new_hash_function;
+        } else if *hash_function != new_hash_function {
+            bail!(
+                "Specified hash function {} does not match {} from sketch {}",
+                hash_function.name(),
+                new_hash_function.name(),
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn update_sketch_params(
     matches: &ArgMatches,
     sketch_params: &mut SketchParams,
@@ -232,10 +662,19 @@ pub fn update_sketch_params(
 ) -> Result<()> {
     let new_sketch_params = &sketch.sketch_params;
 
-    // check that the sketching type is the same; we may want to remove
-    // this check at some point?
+    // check that the sketching type is the same (although Mash and scaled
+    // sketches can be compared with each other so if there wasn't a specific
+    // type requested, we use whichever the sketch has)
     if discriminant(sketch_params) != discriminant(new_sketch_params) {
-        bail!("Sketch types are not the same")
+        match (&sketch_params, new_sketch_params) {
+            (SketchParams::Mash { .. }, SketchParams::Scaled { .. })
+            | (SketchParams::Scaled { .. }, SketchParams::Mash { .. }) => {
+                if matches.occurrences_of("sketch_type") == 0 {
+                    *sketch_params = new_sketch_params.clone();
+                }
+            }
+            _ => bail!("Sketch types are not the same"),
+        }
     }
 
     // if arguments weren't provided use the ones from the multisketch
@@ -244,10 +683,18 @@ pub fn update_sketch_params(
             final_size,
             kmer_length,
             hash_seed,
+            hash_function,
             ..
         } => {
-            if matches.occurrences_of("n_hashes") == 0 {
-                *final_size = new_sketch_params.expected_size();
+            update_hash_function(matches, hash_function, new_sketch_params, name)?;
+            if let SketchParams::Mash {
+                final_size: new_final_size,
+                ..
+            } = new_sketch_params
+            {
+                if matches.occurrences_of("n_hashes") == 0 {
+                    *final_size = *new_final_size;
+                }
             }
             if matches.occurrences_of("kmer_length") == 0 {
                 *kmer_length = new_sketch_params.k();
@@ -276,8 +723,10 @@ pub fn update_sketch_params(
             kmer_length,
             hash_seed,
             scale,
+            hash_function,
             ..
         } => {
+            update_hash_function(matches, hash_function, new_sketch_params, name)?;
             // TODO: these two are identical to above so some DRY might be good?
             if matches.occurrences_of("kmer_length") == 0 {
                 *kmer_length = new_sketch_params.k();
@@ -327,6 +776,51 @@ pub fn update_sketch_params(
                 );
             }
         }
+        SketchParams::HyperLogLog {
+            kmer_length,
+            precision,
+            hash_seed,
+            hash_function,
+        } => {
+            update_hash_function(matches, hash_function, new_sketch_params, name)?;
+            if matches.occurrences_of("kmer_length") == 0 {
+                *kmer_length = new_sketch_params.k();
+            } else if *kmer_length != new_sketch_params.k() {
+                bail!(
+                    "Specified kmer length {} does not match {} from sketch {}",
+                    kmer_length,
+                    new_sketch_params.k(),
+                    name
+                );
+            }
+            let (_, _, new_hash_seed, _) = new_sketch_params.hash_info();
+            if matches.occurrences_of("seed") == 0 {
+                *hash_seed = new_hash_seed;
+            } else if *hash_seed != new_hash_seed {
+                bail!(
+                    "Specified hash seed {} does not match {} from sketch {}",
+                    hash_seed,
+                    new_hash_seed,
+                    name
+                );
+            }
+            if let SketchParams::HyperLogLog {
+                precision: new_precision,
+                ..
+            } = new_sketch_params
+            {
+                if matches.occurrences_of("hll_precision") == 0 {
+                    *precision = *new_precision;
+                } else if precision != new_precision {
+                    bail!(
+                        "Specified HyperLogLog precision {} does not match {} from sketch {}",
+                        precision,
+                        new_precision,
+                        name
+                    );
+                }
+            }
+        }
     }
     Ok(())
 }
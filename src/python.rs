@@ -2,7 +2,6 @@ use std::collections::{HashSet, VecDeque};
 use std::fmt::Display;
 use std::fs::File;
 
-use failure::bail;
 use numpy::{PyArray, PyArray1, PyArray2};
 use pyo3::class::*;
 use pyo3::exceptions::{IndexError, KeyError};
@@ -11,10 +10,11 @@ use pyo3::types::{PyAny, PyBytes, PyDict, PyList, PyTuple, PyType};
 use pyo3::{create_exception, wrap_pyfunction};
 
 use crate::distance::{distance, minmer_matrix};
+use crate::estimators::{estimate_cardinality, CardinalityMethod};
 use crate::filtering::FilterParams;
 use crate::serialization::{write_finch_file, Sketch as SType};
-use crate::sketch_schemes::{KmerCount, SketchParams};
-use crate::{open_sketch_file, sketch_files as rs_sketch_files, Result as FinchResult};
+use crate::sketch_schemes::{HashFunction, KmerCount, SketchParams};
+use crate::{open_sketch_file, sketch_files as rs_sketch_files};
 
 create_exception!(finch, FinchError, pyo3::exceptions::Exception);
 
@@ -22,84 +22,6 @@ fn to_pyerr(e: impl Display) -> PyErr {
     PyErr::new::<FinchError, _>(format!("{}", e))
 }
 
-fn merge_sketches(sketch: &mut SType, other: &SType, size: Option<usize>) -> FinchResult<()> {
-    // update my parameters from the remote's
-    sketch.seq_length += other.seq_length;
-    sketch.num_valid_kmers += other.num_valid_kmers;
-
-    // TODO: do something with filters?
-    if let Some((name, v1, v2)) = sketch
-        .sketch_params
-        .check_compatibility(&other.sketch_params)
-    {
-        bail!(
-            "First sketch has {} {}, but second sketch has {0} {}",
-            name,
-            v1,
-            v2,
-        );
-    }
-
-    // now merge the hashes together; someday it would be nice to use something idiomatic like:
-    // https://users.rust-lang.org/t/solved-merge-multiple-sorted-vectors-using-iterators/6543
-    let sketch1 = &sketch.hashes;
-    let sketch2 = &other.hashes;
-
-    let mut new_hashes = Vec::with_capacity(sketch1.len() + sketch2.len());
-    let (mut i, mut j) = (0, 0);
-    while (i < sketch1.len()) && (j < sketch2.len()) {
-        if sketch1[i].hash < sketch2[j].hash {
-            new_hashes.push(sketch1[i].clone());
-            i += 1;
-        } else if sketch2[j].hash < sketch1[i].hash {
-            new_hashes.push(sketch2[j].clone());
-            j += 1;
-        } else {
-            new_hashes.push(KmerCount {
-                hash: sketch1[i].hash,
-                kmer: sketch1[i].kmer.clone(),
-                count: sketch1[i].count + sketch2[j].count,
-                extra_count: sketch1[i].extra_count + sketch2[j].extra_count,
-                label: sketch1[i].label.clone(),
-            });
-            i += 1;
-            j += 1;
-        }
-    }
-
-    // now clip to the appropriate size
-    let scale = sketch.sketch_params.hash_info().3;
-    match (size, scale) {
-        (Some(s), Some(sc)) => {
-            let max_hash = u64::max_value() / (1. / sc) as u64;
-            // truncate to hashes <= max/sc (or) s whichever is higher
-            new_hashes = new_hashes
-                .into_iter()
-                .enumerate()
-                .take_while(|(ix, h)| (h.hash <= max_hash) || (*ix < s))
-                .map(|(_, h)| h)
-                .collect();
-        }
-        (None, Some(sc)) => {
-            let max_hash = u64::max_value() / (1. / sc) as u64;
-            // truncate to hashes <= max/sc
-            new_hashes = new_hashes
-                .into_iter()
-                .take_while(|h| h.hash <= max_hash)
-                .collect();
-        }
-        (Some(s), None) => {
-            // truncate to size
-            new_hashes.truncate(s);
-        }
-        (None, None) => {
-            // no filtering
-        }
-    }
-    sketch.hashes = new_hashes;
-    Ok(())
-}
-
 #[pyclass]
 /// A Multisketch is a collection of Sketchs with information about their
 /// generation parameters (to make sure they're consistant for distance
@@ -219,7 +141,7 @@ impl Multisketch {
     //         sketch_size = None;
     //     }
     //     for sketch in sketch_iter {
-    //         merge_sketches(&mut s, &sketch, sketch_size).map_err(to_pyerr)?;
+    //         s.merge(&sketch, sketch_size).map_err(to_pyerr)?;
     //     }
     //     Ok(s.into())
     // }
@@ -338,6 +260,7 @@ impl Sketch {
             no_strict: true,
             kmer_length: 21,
             hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
         };
         let s = SType {
             name: name.to_string(),
@@ -347,6 +270,7 @@ impl Sketch {
             hashes: Vec::new(),
             sketch_params,
             filter_params: FilterParams::default(),
+            provenance: None,
         };
         obj.init(Sketch { s });
         Ok(())
@@ -437,6 +361,7 @@ impl Sketch {
                 no_strict,
                 kmer_length,
                 hash_seed,
+                hash_function,
             } => {
                 ret.set_item("sketch_type", "mash")?;
                 ret.set_item("kmers_to_sketch", kmers_to_sketch)?;
@@ -444,27 +369,66 @@ impl Sketch {
                 ret.set_item("no_strict", no_strict)?;
                 ret.set_item("kmer_length", kmer_length)?;
                 ret.set_item("hash_seed", hash_seed)?;
+                ret.set_item("hash_function", hash_function.name())?;
             }
             SketchParams::Scaled {
                 kmers_to_sketch,
                 kmer_length,
                 scale,
                 hash_seed,
+                hash_function,
             } => {
                 ret.set_item("sketch_type", "scaled")?;
                 ret.set_item("kmers_to_sketch", kmers_to_sketch)?;
                 ret.set_item("kmer_length", kmer_length)?;
                 ret.set_item("scale", scale)?;
                 ret.set_item("hash_seed", hash_seed)?;
+                ret.set_item("hash_function", hash_function.name())?;
             }
             SketchParams::AllCounts { kmer_length } => {
                 ret.set_item("sketch_type", "none")?;
                 ret.set_item("kmer_length", kmer_length)?;
             }
+            SketchParams::HyperLogLog {
+                kmer_length,
+                precision,
+                hash_seed,
+                hash_function,
+            } => {
+                ret.set_item("sketch_type", "hyperloglog")?;
+                ret.set_item("kmer_length", kmer_length)?;
+                ret.set_item("precision", precision)?;
+                ret.set_item("hash_seed", hash_seed)?;
+                ret.set_item("hash_function", hash_function.name())?;
+            }
         }
         Ok(ret.to_object(py))
     }
 
+    /// cardinality(self, method: str = None) -> (float, float, float)
+    ///
+    /// Estimate the number of unique kmers in the sequences this sketch was
+    /// made from. Returns the estimate and its lower and upper (~95%)
+    /// bounds. `method` can be "kmv", "scaled" or "hll"; by default scaled
+    /// sketches use "scaled" and all others use "kmv".
+    #[args(method = "None")]
+    pub fn cardinality(&self, method: Option<&str>) -> PyResult<(f64, f64, f64)> {
+        let method = match method {
+            Some(m) => Some(m.parse::<CardinalityMethod>().map_err(to_pyerr)?),
+            None => None,
+        };
+        let est = estimate_cardinality(&self.s, method).map_err(to_pyerr)?;
+        Ok((est.estimate, est.lower, est.upper))
+    }
+
+    /// add_sequence(self, seq: str)
+    ///
+    /// Sketch a sequence (e.g. from a BAM file or a database) and add its
+    /// kmers to this sketch (which keeps the same size).
+    pub fn add_sequence(&mut self, seq: &str) -> PyResult<()> {
+        Ok(self.s.add_sequence(seq.as_bytes()).map_err(to_pyerr)?)
+    }
+
     // TODO: filtering method
 
     // TODO: clip to n kmers/hashes method
@@ -477,7 +441,7 @@ impl Sketch {
     /// that have 'high' hashes because they're under `size`, this will
     /// potentially remove those hashes if the new sketch is large enough).
     pub fn merge(&mut self, sketch: &Sketch, size: Option<usize>) -> PyResult<()> {
-        Ok(merge_sketches(&mut self.s, &sketch.s, size).map_err(to_pyerr)?)
+        Ok(self.s.merge(&sketch.s, size).map_err(to_pyerr)?)
     }
 
     /// compare(self, sketch: Sketch, old_mode: bool = False) -> (float, float)
@@ -649,9 +613,8 @@ impl From<SType> for Sketch {
 // set difference calculations for sketches?
 // see https://github.com/PyO3/pyo3/blob/master/tests/test_arithmetics.rs for details
 
-// TODO: also it would be sweet to add a `str` to the Sketch to kmerize it and
-// add the kmers; this might be better done with a new "Sketch scheme" that
-// allows non-nucleic acid bases?
+// TODO: `Sketch.add_sequence` only handles nucleic acid sequences; other
+// alphabets might be better done with a new "Sketch scheme"?
 
 /// sketch_file(
 ///     filename: str,
@@ -681,12 +644,18 @@ pub fn sketch_file(
         no_strict: false,
         kmer_length,
         hash_seed: seed,
+        hash_function: HashFunction::MurmurHash3,
     };
     let filters = FilterParams {
         filter_on: Some(filter),
         abun_filter: (None, None),
         err_filter: 1.,
         strand_filter: 0.1,
+        qual_filter: None,
+        kmer_err_filter: None,
+        dust_filter: None,
+        exclusion_filter: None,
+        bloom_filter_size: None,
     };
     let sketches = rs_sketch_files(&[filename], &sketch_params, &filters);
     Ok(Sketch {
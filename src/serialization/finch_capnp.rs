@@ -10,6 +10,7 @@ pub enum SketchMethod {
     MurmurHash3 = 0,
     MurmurHash3Scaled = 1,
     None = 2,
+    MurmurHash3HyperLogLog = 3,
 }
 impl ::capnp::traits::FromU16 for SketchMethod {
     #[inline]
@@ -18,6 +19,7 @@ impl ::capnp::traits::FromU16 for SketchMethod {
             0 => ::std::result::Result::Ok(SketchMethod::MurmurHash3),
             1 => ::std::result::Result::Ok(SketchMethod::MurmurHash3Scaled),
             2 => ::std::result::Result::Ok(SketchMethod::None),
+            3 => ::std::result::Result::Ok(SketchMethod::MurmurHash3HyperLogLog),
             n => ::std::result::Result::Err(::capnp::NotInSchema(n)),
         }
     }
@@ -35,6 +37,37 @@ impl ::capnp::traits::HasTypeId for SketchMethod {
     }
 }
 
+#[repr(u16)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum HashFunction {
+    MurmurHash3 = 0,
+    XxHash3 = 1,
+    NtHash = 2,
+}
+impl ::capnp::traits::FromU16 for HashFunction {
+    #[inline]
+    fn from_u16(value: u16) -> ::std::result::Result<HashFunction, ::capnp::NotInSchema> {
+        match value {
+            0 => ::std::result::Result::Ok(HashFunction::MurmurHash3),
+            1 => ::std::result::Result::Ok(HashFunction::XxHash3),
+            2 => ::std::result::Result::Ok(HashFunction::NtHash),
+            n => ::std::result::Result::Err(::capnp::NotInSchema(n)),
+        }
+    }
+}
+impl ::capnp::traits::ToU16 for HashFunction {
+    #[inline]
+    fn to_u16(self) -> u16 {
+        self as u16
+    }
+}
+impl ::capnp::traits::HasTypeId for HashFunction {
+    #[inline]
+    fn type_id() -> u64 {
+        0xf3b8_6cba_8772_d2c5u64
+    }
+}
+
 pub mod filter_params {
     #[derive(Copy, Clone)]
     pub struct Owned;
@@ -118,6 +151,32 @@ pub mod filter_params {
         pub fn get_strand_filter(self) -> f64 {
             self.reader.get_data_field::<f64>(3)
         }
+        #[inline]
+        pub fn get_min_qual(self) -> u8 {
+            self.reader.get_data_field::<u8>(1)
+        }
+        #[inline]
+        pub fn get_max_kmer_errors(self) -> f64 {
+            self.reader.get_data_field::<f64>(4)
+        }
+        #[inline]
+        pub fn get_dust_filter(self) -> f64 {
+            self.reader
+                .get_data_field_mask::<f64>(5, 0xbff0_0000_0000_0000u64)
+        }
+        #[inline]
+        pub fn get_exclusion_set(self) -> ::capnp::Result<::capnp::text::Reader<'a>> {
+            self.reader
+                .get_pointer_field(0)
+                .get_text(::std::ptr::null(), 0)
+        }
+        pub fn has_exclusion_set(&self) -> bool {
+            !self.reader.get_pointer_field(0).is_null()
+        }
+        #[inline]
+        pub fn get_bloom_filter_size(self) -> u64 {
+            self.reader.get_data_field::<u64>(6)
+        }
     }
 
     pub struct Builder<'a> {
@@ -232,6 +291,57 @@ pub mod filter_params {
         pub fn set_strand_filter(&mut self, value: f64) {
             self.builder.set_data_field::<f64>(3, value);
         }
+        #[inline]
+        pub fn get_min_qual(self) -> u8 {
+            self.builder.get_data_field::<u8>(1)
+        }
+        #[inline]
+        pub fn set_min_qual(&mut self, value: u8) {
+            self.builder.set_data_field::<u8>(1, value);
+        }
+        #[inline]
+        pub fn get_max_kmer_errors(self) -> f64 {
+            self.builder.get_data_field::<f64>(4)
+        }
+        #[inline]
+        pub fn set_max_kmer_errors(&mut self, value: f64) {
+            self.builder.set_data_field::<f64>(4, value);
+        }
+        #[inline]
+        pub fn get_dust_filter(self) -> f64 {
+            self.builder
+                .get_data_field_mask::<f64>(5, 0xbff0_0000_0000_0000u64)
+        }
+        #[inline]
+        pub fn set_dust_filter(&mut self, value: f64) {
+            self.builder
+                .set_data_field_mask::<f64>(5, value, 0xbff0_0000_0000_0000u64);
+        }
+        #[inline]
+        pub fn get_exclusion_set(self) -> ::capnp::Result<::capnp::text::Builder<'a>> {
+            self.builder
+                .get_pointer_field(0)
+                .get_text(::std::ptr::null(), 0)
+        }
+        #[inline]
+        pub fn set_exclusion_set(&mut self, value: ::capnp::text::Reader) {
+            self.builder.get_pointer_field(0).set_text(value);
+        }
+        #[inline]
+        pub fn init_exclusion_set(self, size: u32) -> ::capnp::text::Builder<'a> {
+            self.builder.get_pointer_field(0).init_text(size)
+        }
+        pub fn has_exclusion_set(&self) -> bool {
+            !self.builder.get_pointer_field(0).is_null()
+        }
+        #[inline]
+        pub fn get_bloom_filter_size(self) -> u64 {
+            self.builder.get_data_field::<u64>(6)
+        }
+        #[inline]
+        pub fn set_bloom_filter_size(&mut self, value: u64) {
+            self.builder.set_data_field::<u64>(6, value);
+        }
     }
 
     pub struct Pipeline {
@@ -248,8 +358,8 @@ pub mod filter_params {
     mod _private {
         use capnp::private::layout;
         pub const STRUCT_SIZE: layout::StructSize = layout::StructSize {
-            data: 4,
-            pointers: 0,
+            data: 7,
+            pointers: 1,
         };
         pub const TYPE_ID: u64 = 0x8dff_64f8_81c9_51eb;
     }
@@ -348,6 +458,16 @@ pub mod sketch_params {
         pub fn get_scale(self) -> f64 {
             self.reader.get_data_field::<f64>(4)
         }
+        #[inline]
+        pub fn get_hll_precision(self) -> u8 {
+            self.reader.get_data_field::<u8>(4)
+        }
+        #[inline]
+        pub fn get_hash_function(
+            self,
+        ) -> ::std::result::Result<super::HashFunction, ::capnp::NotInSchema> {
+            ::capnp::traits::FromU16::from_u16(self.reader.get_data_field::<u16>(3))
+        }
     }
 
     pub struct Builder<'a> {
@@ -480,6 +600,24 @@ pub mod sketch_params {
         pub fn set_scale(&mut self, value: f64) {
             self.builder.set_data_field::<f64>(4, value);
         }
+        #[inline]
+        pub fn get_hll_precision(self) -> u8 {
+            self.builder.get_data_field::<u8>(4)
+        }
+        #[inline]
+        pub fn set_hll_precision(&mut self, value: u8) {
+            self.builder.set_data_field::<u8>(4, value);
+        }
+        #[inline]
+        pub fn get_hash_function(
+            self,
+        ) -> ::std::result::Result<super::HashFunction, ::capnp::NotInSchema> {
+            ::capnp::traits::FromU16::from_u16(self.builder.get_data_field::<u16>(3))
+        }
+        #[inline]
+        pub fn set_hash_function(&mut self, value: super::HashFunction) {
+            self.builder.set_data_field::<u16>(3, value as u16)
+        }
     }
 
     pub struct Pipeline {
@@ -751,6 +889,294 @@ pub mod kmer_count {
     }
 }
 
+pub mod provenance {
+    #[derive(Copy, Clone)]
+    pub struct Owned;
+    impl<'a> ::capnp::traits::Owned<'a> for Owned {
+        type Reader = Reader<'a>;
+        type Builder = Builder<'a>;
+    }
+    impl<'a> ::capnp::traits::OwnedStruct<'a> for Owned {
+        type Reader = Reader<'a>;
+        type Builder = Builder<'a>;
+    }
+    impl ::capnp::traits::Pipelined for Owned {
+        type Pipeline = Pipeline;
+    }
+
+    #[derive(Clone, Copy)]
+    pub struct Reader<'a> {
+        reader: ::capnp::private::layout::StructReader<'a>,
+    }
+
+    impl<'a> ::capnp::traits::HasTypeId for Reader<'a> {
+        #[inline]
+        fn type_id() -> u64 {
+            _private::TYPE_ID
+        }
+    }
+    impl<'a> ::capnp::traits::FromStructReader<'a> for Reader<'a> {
+        fn new(reader: ::capnp::private::layout::StructReader<'a>) -> Reader<'a> {
+            Reader { reader: reader }
+        }
+    }
+
+    impl<'a> ::capnp::traits::FromPointerReader<'a> for Reader<'a> {
+        fn get_from_pointer(
+            reader: &::capnp::private::layout::PointerReader<'a>,
+        ) -> ::capnp::Result<Reader<'a>> {
+            ::std::result::Result::Ok(::capnp::traits::FromStructReader::new(
+                reader.get_struct(::std::ptr::null())?,
+            ))
+        }
+    }
+
+    impl<'a> ::capnp::traits::IntoInternalStructReader<'a> for Reader<'a> {
+        fn into_internal_struct_reader(self) -> ::capnp::private::layout::StructReader<'a> {
+            self.reader
+        }
+    }
+
+    impl<'a> ::capnp::traits::Imbue<'a> for Reader<'a> {
+        fn imbue(&mut self, cap_table: &'a ::capnp::private::layout::CapTable) {
+            self.reader
+                .imbue(::capnp::private::layout::CapTableReader::Plain(cap_table))
+        }
+    }
+
+    impl<'a> Reader<'a> {
+        pub fn reborrow(&self) -> Reader<'_> {
+            Reader { ..*self }
+        }
+
+        pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+            self.reader.total_size()
+        }
+        #[inline]
+        pub fn get_finch_version(self) -> ::capnp::Result<::capnp::text::Reader<'a>> {
+            self.reader
+                .get_pointer_field(0)
+                .get_text(::std::ptr::null(), 0)
+        }
+        pub fn has_finch_version(&self) -> bool {
+            !self.reader.get_pointer_field(0).is_null()
+        }
+        #[inline]
+        pub fn get_input_files(self) -> ::capnp::Result<::capnp::text_list::Reader<'a>> {
+            ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(1))
+        }
+        pub fn has_input_files(&self) -> bool {
+            !self.reader.get_pointer_field(1).is_null()
+        }
+        #[inline]
+        pub fn get_input_checksums(self) -> ::capnp::Result<::capnp::text_list::Reader<'a>> {
+            ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(2))
+        }
+        pub fn has_input_checksums(&self) -> bool {
+            !self.reader.get_pointer_field(2).is_null()
+        }
+        #[inline]
+        pub fn get_timestamp(self) -> u64 {
+            self.reader.get_data_field::<u64>(0)
+        }
+        #[inline]
+        pub fn get_command_line(self) -> ::capnp::Result<::capnp::text::Reader<'a>> {
+            self.reader
+                .get_pointer_field(3)
+                .get_text(::std::ptr::null(), 0)
+        }
+        pub fn has_command_line(&self) -> bool {
+            !self.reader.get_pointer_field(3).is_null()
+        }
+    }
+
+    pub struct Builder<'a> {
+        builder: ::capnp::private::layout::StructBuilder<'a>,
+    }
+    impl<'a> ::capnp::traits::HasStructSize for Builder<'a> {
+        #[inline]
+        fn struct_size() -> ::capnp::private::layout::StructSize {
+            _private::STRUCT_SIZE
+        }
+    }
+    impl<'a> ::capnp::traits::HasTypeId for Builder<'a> {
+        #[inline]
+        fn type_id() -> u64 {
+            _private::TYPE_ID
+        }
+    }
+    impl<'a> ::capnp::traits::FromStructBuilder<'a> for Builder<'a> {
+        fn new(builder: ::capnp::private::layout::StructBuilder<'a>) -> Builder<'a> {
+            Builder { builder: builder }
+        }
+    }
+
+    impl<'a> ::capnp::traits::ImbueMut<'a> for Builder<'a> {
+        fn imbue_mut(&mut self, cap_table: &'a mut ::capnp::private::layout::CapTable) {
+            self.builder
+                .imbue(::capnp::private::layout::CapTableBuilder::Plain(cap_table))
+        }
+    }
+
+    impl<'a> ::capnp::traits::FromPointerBuilder<'a> for Builder<'a> {
+        fn init_pointer(
+            builder: ::capnp::private::layout::PointerBuilder<'a>,
+            _size: u32,
+        ) -> Builder<'a> {
+            ::capnp::traits::FromStructBuilder::new(builder.init_struct(_private::STRUCT_SIZE))
+        }
+        fn get_from_pointer(
+            builder: ::capnp::private::layout::PointerBuilder<'a>,
+        ) -> ::capnp::Result<Builder<'a>> {
+            ::std::result::Result::Ok(::capnp::traits::FromStructBuilder::new(
+                builder.get_struct(_private::STRUCT_SIZE, ::std::ptr::null())?,
+            ))
+        }
+    }
+
+    impl<'a> ::capnp::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
+        fn set_pointer_builder<'b>(
+            pointer: ::capnp::private::layout::PointerBuilder<'b>,
+            value: Reader<'a>,
+            canonicalize: bool,
+        ) -> ::capnp::Result<()> {
+            pointer.set_struct(&value.reader, canonicalize)
+        }
+    }
+
+    impl<'a> Builder<'a> {
+        #[deprecated(since = "0.9.2", note = "use into_reader()")]
+        pub fn as_reader(self) -> Reader<'a> {
+            self.into_reader()
+        }
+        pub fn into_reader(self) -> Reader<'a> {
+            ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+        }
+        pub fn reborrow(&mut self) -> Builder<'_> {
+            Builder { ..*self }
+        }
+        pub fn reborrow_as_reader(&self) -> Reader<'_> {
+            ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+        }
+
+        pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+            self.builder.into_reader().total_size()
+        }
+        #[inline]
+        pub fn get_finch_version(self) -> ::capnp::Result<::capnp::text::Builder<'a>> {
+            self.builder
+                .get_pointer_field(0)
+                .get_text(::std::ptr::null(), 0)
+        }
+        #[inline]
+        pub fn set_finch_version(&mut self, value: ::capnp::text::Reader) {
+            self.builder.get_pointer_field(0).set_text(value);
+        }
+        #[inline]
+        pub fn init_finch_version(self, size: u32) -> ::capnp::text::Builder<'a> {
+            self.builder.get_pointer_field(0).init_text(size)
+        }
+        pub fn has_finch_version(&self) -> bool {
+            !self.builder.get_pointer_field(0).is_null()
+        }
+        #[inline]
+        pub fn get_input_files(self) -> ::capnp::Result<::capnp::text_list::Builder<'a>> {
+            ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(1))
+        }
+        #[inline]
+        pub fn set_input_files(
+            &mut self,
+            value: ::capnp::text_list::Reader<'a>,
+        ) -> ::capnp::Result<()> {
+            ::capnp::traits::SetPointerBuilder::set_pointer_builder(
+                self.builder.get_pointer_field(1),
+                value,
+                false,
+            )
+        }
+        #[inline]
+        pub fn init_input_files(self, size: u32) -> ::capnp::text_list::Builder<'a> {
+            ::capnp::traits::FromPointerBuilder::init_pointer(
+                self.builder.get_pointer_field(1),
+                size,
+            )
+        }
+        pub fn has_input_files(&self) -> bool {
+            !self.builder.get_pointer_field(1).is_null()
+        }
+        #[inline]
+        pub fn get_input_checksums(self) -> ::capnp::Result<::capnp::text_list::Builder<'a>> {
+            ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(2))
+        }
+        #[inline]
+        pub fn set_input_checksums(
+            &mut self,
+            value: ::capnp::text_list::Reader<'a>,
+        ) -> ::capnp::Result<()> {
+            ::capnp::traits::SetPointerBuilder::set_pointer_builder(
+                self.builder.get_pointer_field(2),
+                value,
+                false,
+            )
+        }
+        #[inline]
+        pub fn init_input_checksums(self, size: u32) -> ::capnp::text_list::Builder<'a> {
+            ::capnp::traits::FromPointerBuilder::init_pointer(
+                self.builder.get_pointer_field(2),
+                size,
+            )
+        }
+        pub fn has_input_checksums(&self) -> bool {
+            !self.builder.get_pointer_field(2).is_null()
+        }
+        #[inline]
+        pub fn get_timestamp(self) -> u64 {
+            self.builder.get_data_field::<u64>(0)
+        }
+        #[inline]
+        pub fn set_timestamp(&mut self, value: u64) {
+            self.builder.set_data_field::<u64>(0, value);
+        }
+        #[inline]
+        pub fn get_command_line(self) -> ::capnp::Result<::capnp::text::Builder<'a>> {
+            self.builder
+                .get_pointer_field(3)
+                .get_text(::std::ptr::null(), 0)
+        }
+        #[inline]
+        pub fn set_command_line(&mut self, value: ::capnp::text::Reader) {
+            self.builder.get_pointer_field(3).set_text(value);
+        }
+        #[inline]
+        pub fn init_command_line(self, size: u32) -> ::capnp::text::Builder<'a> {
+            self.builder.get_pointer_field(3).init_text(size)
+        }
+        pub fn has_command_line(&self) -> bool {
+            !self.builder.get_pointer_field(3).is_null()
+        }
+    }
+
+    pub struct Pipeline {
+        _typeless: ::capnp::any_pointer::Pipeline,
+    }
+    impl ::capnp::capability::FromTypelessPipeline for Pipeline {
+        fn new(typeless: ::capnp::any_pointer::Pipeline) -> Pipeline {
+            Pipeline {
+                _typeless: typeless,
+            }
+        }
+    }
+    impl Pipeline {}
+    mod _private {
+        use capnp::private::layout;
+        pub const STRUCT_SIZE: layout::StructSize = layout::StructSize {
+            data: 1,
+            pointers: 4,
+        };
+        pub const TYPE_ID: u64 = 0xe040_e92a_e93f_6d41;
+    }
+}
+
 pub mod sketch {
     #[derive(Copy, Clone)]
     pub struct Owned;
@@ -863,6 +1289,13 @@ pub mod sketch {
         pub fn has_sketch_params(&self) -> bool {
             !self.reader.get_pointer_field(4).is_null()
         }
+        #[inline]
+        pub fn get_provenance(self) -> ::capnp::Result<super::provenance::Reader<'a>> {
+            ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(5))
+        }
+        pub fn has_provenance(&self) -> bool {
+            !self.reader.get_pointer_field(5).is_null()
+        }
     }
 
     pub struct Builder<'a> {
@@ -1061,6 +1494,28 @@ pub mod sketch {
         pub fn has_sketch_params(&self) -> bool {
             !self.builder.get_pointer_field(4).is_null()
         }
+        #[inline]
+        pub fn get_provenance(self) -> ::capnp::Result<super::provenance::Builder<'a>> {
+            ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(5))
+        }
+        #[inline]
+        pub fn set_provenance<'b>(
+            &mut self,
+            value: super::provenance::Reader<'b>,
+        ) -> ::capnp::Result<()> {
+            ::capnp::traits::SetPointerBuilder::set_pointer_builder(
+                self.builder.get_pointer_field(5),
+                value,
+                false,
+            )
+        }
+        #[inline]
+        pub fn init_provenance(self) -> super::provenance::Builder<'a> {
+            ::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field(5), 0)
+        }
+        pub fn has_provenance(&self) -> bool {
+            !self.builder.get_pointer_field(5).is_null()
+        }
     }
 
     pub struct Pipeline {
@@ -1080,12 +1535,15 @@ pub mod sketch {
         pub fn get_sketch_params(&self) -> super::sketch_params::Pipeline {
             ::capnp::capability::FromTypelessPipeline::new(self._typeless.get_pointer_field(4))
         }
+        pub fn get_provenance(&self) -> super::provenance::Pipeline {
+            ::capnp::capability::FromTypelessPipeline::new(self._typeless.get_pointer_field(5))
+        }
     }
     mod _private {
         use capnp::private::layout;
         pub const STRUCT_SIZE: layout::StructSize = layout::StructSize {
             data: 2,
-            pointers: 5,
+            pointers: 6,
         };
         pub const TYPE_ID: u64 = 0xb602_150b_6621_a020;
     }
@@ -8,8 +8,8 @@ use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 use crate::filtering::FilterParams;
 pub use crate::serialization::mash::{read_mash_file, write_mash_file};
-use crate::serialization::Sketch;
-use crate::sketch_schemes::{KmerCount, SketchParams};
+use crate::serialization::{Provenance, Sketch};
+use crate::sketch_schemes::{hyperloglog, HashFunction, KmerCount, SketchParams};
 use crate::Result as FinchResult;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -20,6 +20,7 @@ pub struct JsonSketch {
     pub comment: Option<String>,
     pub filters: Option<HashMap<String, String>>,
     pub hashes: Vec<KmerCount>,
+    pub provenance: Option<Provenance>,
 }
 
 impl JsonSketch {
@@ -37,6 +38,7 @@ impl JsonSketch {
             comment: Some(String::from("")),
             filters: Some(filters.clone()),
             hashes: kmercounts,
+            provenance: None,
         }
     }
 
@@ -63,15 +65,25 @@ impl Serialize for JsonSketch {
             count_list.push(hash.count);
         }
 
-        let mut state = serializer.serialize_struct("Sketch", 8)?;
+        // sketches without kmers (e.g. from `--no-kmers`) leave them out
+        // entirely instead of writing a list of empty strings
+        let has_kmers = kmer_list.iter().any(|kmer| !kmer.is_empty());
+
+        let n_fields = 7 + has_kmers as usize + self.provenance.is_some() as usize;
+        let mut state = serializer.serialize_struct("Sketch", n_fields)?;
         state.serialize_field("name", &self.name)?;
         state.serialize_field("seqLength", &self.seq_length)?;
         state.serialize_field("numValidKmers", &self.num_valid_kmers)?;
         state.serialize_field("comment", &self.comment)?;
         state.serialize_field("filters", &self.filters)?;
         state.serialize_field("hashes", &hash_list)?;
-        state.serialize_field("kmers", &kmer_list)?;
+        if has_kmers {
+            state.serialize_field("kmers", &kmer_list)?;
+        }
         state.serialize_field("counts", &count_list)?;
+        if let Some(provenance) = &self.provenance {
+            state.serialize_field("provenance", provenance)?;
+        }
         state.end()
     }
 }
@@ -92,9 +104,24 @@ impl<'de> Deserialize<'de> for JsonSketch {
             hashes: Vec<QuotedU64>,
             kmers: Option<Vec<String>>,
             counts: Option<Vec<u32>>,
+            provenance: Option<Provenance>,
         }
 
         let mut jsketch = BaseJsonSketch::deserialize(deserializer)?;
+        let n_hashes = jsketch.hashes.len();
+        for (field, len) in &[
+            ("kmers", jsketch.kmers.as_ref().map(Vec::len)),
+            ("counts", jsketch.counts.as_ref().map(Vec::len)),
+        ] {
+            if let Some(len) = len {
+                if *len != n_hashes {
+                    return Err(de::Error::custom(format!(
+                        "sketch {} has {} hashes, but {} {}",
+                        jsketch.name, n_hashes, len, field
+                    )));
+                }
+            }
+        }
 
         let mut kmercount_list = Vec::with_capacity(jsketch.hashes.len());
         for i in 0..jsketch.hashes.len() {
@@ -122,6 +149,7 @@ impl<'de> Deserialize<'de> for JsonSketch {
             comment: jsketch.comment,
             filters: jsketch.filters,
             hashes: kmercount_list,
+            provenance: jsketch.provenance,
         })
     }
 }
@@ -147,40 +175,66 @@ pub struct MultiSketch {
 
 impl MultiSketch {
     pub fn get_params(&self) -> FinchResult<SketchParams> {
-        Ok(match (&*self.hash_type, self.scale) {
-            ("MurmurHash3_x64_128", None) => {
-                if self.hash_bits != 64 {
-                    bail!(
-                        "Multisketch has incompatible hash size ({} != 64)",
-                        self.hash_bits
-                    );
-                }
-                SketchParams::Mash {
-                    kmers_to_sketch: self.sketch_size as usize,
-                    final_size: self.sketch_size as usize,
-                    no_strict: true,
-                    kmer_length: self.kmer,
-                    hash_seed: self.hash_seed,
-                }
+        if self.hash_type == "None" {
+            return Ok(SketchParams::AllCounts {
+                kmer_length: self.kmer,
+            });
+        }
+        let hll_suffix = "_HyperLogLog";
+        let hll_hash_function = if self.hash_type.ends_with(hll_suffix) {
+            HashFunction::from_name(&self.hash_type[..self.hash_type.len() - hll_suffix.len()])
+        } else {
+            None
+        };
+        if let Some(hash_function) = hll_hash_function {
+            // the number of registers is stored as the sketch size
+            if !self.sketch_size.is_power_of_two() {
+                bail!(
+                    "Multisketch has an invalid number of HyperLogLog registers ({})",
+                    self.sketch_size
+                );
             }
-            ("MurmurHash3_x64_128", Some(scale)) => {
-                if self.hash_bits != 64 {
-                    bail!(
-                        "Multisketch has incompatible hash size ({} != 64)",
-                        self.hash_bits
-                    );
-                }
-                SketchParams::Scaled {
-                    kmers_to_sketch: self.sketch_size as usize,
-                    kmer_length: self.kmer,
-                    scale,
-                    hash_seed: self.hash_seed,
-                }
+            let precision = self.sketch_size.trailing_zeros() as u8;
+            if !hyperloglog::PRECISIONS.contains(&precision) {
+                bail!(
+                    "Multisketch has an unsupported number of HyperLogLog registers ({})",
+                    self.sketch_size
+                );
             }
-            ("None", _) => SketchParams::AllCounts {
+            return Ok(SketchParams::HyperLogLog {
+                kmer_length: self.kmer,
+                precision,
+                hash_seed: self.hash_seed,
+                hash_function,
+            });
+        }
+
+        let hash_function = match HashFunction::from_name(&self.hash_type) {
+            Some(hash_function) => hash_function,
+            None => bail!("{} sketch type is not supported", self.hash_type),
+        };
+        if self.hash_bits != 64 {
+            bail!(
+                "Multisketch has incompatible hash size ({} != 64)",
+                self.hash_bits
+            );
+        }
+        Ok(match self.scale {
+            None => SketchParams::Mash {
+                kmers_to_sketch: self.sketch_size as usize,
+                final_size: self.sketch_size as usize,
+                no_strict: true,
+                kmer_length: self.kmer,
+                hash_seed: self.hash_seed,
+                hash_function,
+            },
+            Some(scale) => SketchParams::Scaled {
+                kmers_to_sketch: self.sketch_size as usize,
                 kmer_length: self.kmer,
+                scale,
+                hash_seed: self.hash_seed,
+                hash_function,
             },
-            (x, _) => bail!("{} sketch type is not supported", x),
         })
     }
 
@@ -220,6 +274,7 @@ impl MultiSketch {
                 hashes: sketch.hashes.clone(),
                 filter_params,
                 sketch_params: sketch_params.clone(),
+                provenance: sketch.provenance.clone(),
             });
         }
         Ok(sketches)
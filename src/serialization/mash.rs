@@ -3,16 +3,27 @@ use std::io::{BufRead, Write};
 
 use capnp::message;
 use capnp::serialize as capnp_serialize;
-use failure::format_err;
+use failure::{bail, format_err};
 
 use crate::filtering::FilterParams;
 use crate::serialization::mash_capnp::min_hash;
 use crate::serialization::Sketch;
-use crate::sketch_schemes::{ItemHash, KmerCount, SketchParams};
+use crate::sketch_schemes::{HashFunction, ItemHash, KmerCount, SketchParams};
 use crate::Result as FinchResult;
 
 pub fn write_mash_file(mut file: &mut dyn Write, sketches: &[Sketch]) -> FinchResult<()> {
     let params = SketchParams::from_sketches(&sketches)?;
+    if let SketchParams::HyperLogLog { .. } = params {
+        bail!("HyperLogLog sketches can not be saved in mash format");
+    }
+    if let Some(hash_function) = params.hash_function() {
+        if hash_function != HashFunction::MurmurHash3 {
+            bail!(
+                "Only MurmurHash3 sketches can be saved in mash format (not {})",
+                hash_function.name()
+            );
+        }
+    }
 
     let mut message = message::Builder::new_default();
     {
@@ -77,6 +88,7 @@ pub fn read_mash_file(mut file: &mut dyn BufRead) -> FinchResult<Vec<Sketch>> {
         no_strict: true,
         hash_seed: u64::from(mash_data.get_hash_seed()),
         kmer_length: mash_data.get_kmer_size() as u8,
+        hash_function: HashFunction::MurmurHash3,
     };
 
     /*
@@ -132,6 +144,7 @@ pub fn read_mash_file(mut file: &mut dyn BufRead) -> FinchResult<Vec<Sketch>> {
             hashes: kmercounts,
             sketch_params: sketch_params.clone(),
             filter_params: FilterParams::default(),
+            provenance: None,
         });
     }
 
@@ -11,17 +11,25 @@ mod finch_capnp;
 mod json;
 mod mash;
 mod mash_capnp;
+mod provenance;
 
 use std::io::{BufRead, Write};
+use std::result::Result as StdResult;
 
 use capnp::message;
 use capnp::serialize as capnp_serialize;
+use failure::bail;
 
-use crate::filtering::FilterParams;
-use crate::serialization::finch_capnp::{multisketch, sketch_params, SketchMethod};
+use crate::filtering::{ExclusionSet, FilterParams};
+use crate::serialization::finch_capnp::{
+    multisketch, sketch_params, HashFunction as CapHashFunction, SketchMethod,
+};
 pub use crate::serialization::json::{JsonSketch, MultiSketch};
 pub use crate::serialization::mash::{read_mash_file, write_mash_file};
-use crate::sketch_schemes::{KmerCount, SketchParams};
+pub use crate::serialization::provenance::{
+    checksum_reader, format_timestamp, ChecksumReader, Provenance,
+};
+use crate::sketch_schemes::{hyperloglog, HashFunction, KmerCount, SketchParams};
 use crate::Result;
 
 pub const FINCH_EXT: &str = ".sk";
@@ -38,6 +46,10 @@ pub struct SketchDistance {
     pub common_hashes: u64,
     #[serde(rename = "totalHashes")]
     pub total_hashes: u64,
+    /// If the distance was between a Mash-style and a scaled sketch (see
+    /// `distance::mixed_distance`)
+    #[serde(rename = "mixedTypes", default, skip_serializing_if = "is_false")]
+    pub mixed_types: bool,
     pub query: String,
     pub reference: String,
 }
@@ -52,6 +64,27 @@ pub struct Sketch {
     pub hashes: Vec<KmerCount>,
     pub filter_params: FilterParams,
     pub sketch_params: SketchParams,
+    pub provenance: Option<Provenance>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// The resolution to reduce a sketch to (see `Sketch::downsample`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Downsample {
+    /// Keep only the `n` lowest hashes (making a Mash-style sketch)
+    Size(usize),
+    /// Keep only the hashes below this fraction of the hash space (making a
+    /// scaled sketch)
+    Scale(f64),
+}
+
+/// The largest hash a scaled sketch includes (this matches the cutoff used
+/// in the scaled sketcher and distance calculations)
+fn scaled_max_hash(scale: f64) -> u64 {
+    u64::max_value() / scale.recip() as u64
 }
 
 impl Sketch {
@@ -62,17 +95,403 @@ impl Sketch {
     pub fn is_empty(&self) -> bool {
         self.hashes.is_empty()
     }
+
+    /// Whether any of the sketch's hashes still have their kmer sequences
+    pub fn has_kmers(&self) -> bool {
+        self.hashes.iter().any(|hash| !hash.kmer.is_empty())
+    }
+
+    /// Drop the kmer sequences from the sketch (they aren't needed for
+    /// distances and otherwise make up much of a sketch's size)
+    pub fn strip_kmers(&mut self) {
+        for hash in &mut self.hashes {
+            hash.kmer = Vec::new();
+        }
+    }
+
+    /// Check that the sketch is internally consistent (e.g. that its
+    /// hashes are sorted and unique, as the distance calculations assume),
+    /// failing with a description of every problem found.
+    pub fn validate(&self) -> Result<()> {
+        let problems = self.find_problems();
+        if !problems.is_empty() {
+            bail!("Sketch {} is invalid: {}", self.name, problems.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Find everything that's inconsistent in the sketch (see `validate`)
+    pub fn find_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // only describe the first instance of each kind of problem
+        let mut report = |description: &str, positions: Vec<String>| {
+            if let Some(first) = positions.first() {
+                problems.push(format!(
+                    "{} {} (first at {})",
+                    positions.len(),
+                    description,
+                    first
+                ));
+            }
+        };
+
+        let mut unsorted = Vec::new();
+        let mut duplicated = Vec::new();
+        for (ix, pair) in self.hashes.windows(2).enumerate() {
+            if pair[0].hash > pair[1].hash {
+                unsorted.push(format!("position {}", ix + 1));
+            } else if pair[0].hash == pair[1].hash {
+                duplicated.push(format!("position {}, hash {}", ix + 1, pair[1].hash));
+            }
+        }
+        report("hash(es) out of order", unsorted);
+        report("duplicate hash(es)", duplicated);
+
+        let zero_counts = self
+            .hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| h.count == 0)
+            .map(|(ix, h)| format!("position {}, hash {}", ix, h.hash))
+            .collect();
+        report("hash(es) with a count of 0", zero_counts);
+        let bad_strand_counts = self
+            .hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| h.extra_count > h.count)
+            .map(|(ix, h)| format!("position {}, hash {}", ix, h.hash))
+            .collect();
+        report(
+            "hash(es) with more reverse strand counts than counts",
+            bad_strand_counts,
+        );
+
+        let k = usize::from(self.sketch_params.k());
+        let bad_kmers = self
+            .hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| !h.kmer.is_empty() && h.kmer.len() != k)
+            .map(|(ix, h)| format!("position {}, hash {}", ix, h.hash))
+            .collect();
+        report(&format!("kmer(s) not of length {}", k), bad_kmers);
+
+        match self.sketch_params {
+            SketchParams::Mash { final_size, .. } if self.hashes.len() > final_size => {
+                problems.push(format!(
+                    "{} hashes is more than the sketch size of {}",
+                    self.hashes.len(),
+                    final_size
+                ));
+            }
+            SketchParams::Scaled {
+                kmers_to_sketch,
+                scale,
+                ..
+            } if self.hashes.len() > kmers_to_sketch => {
+                // scaled sketches only keep hashes over the maximum to fill
+                // out the sketch to `kmers_to_sketch`
+                let max_hash = scaled_max_hash(scale);
+                let over_max = self
+                    .hashes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, h)| h.hash > max_hash)
+                    .map(|(ix, h)| format!("position {}, hash {}", ix, h.hash))
+                    .collect();
+                report(
+                    &format!("hash(es) above the scaled maximum of {}", max_hash),
+                    over_max,
+                );
+            }
+            SketchParams::HyperLogLog { precision, .. } => {
+                let bad_registers = self
+                    .hashes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, h)| {
+                        h.hash >= 1 << precision || h.count > 65 - u32::from(precision)
+                    })
+                    .map(|(ix, h)| format!("position {}, register {}", ix, h.hash))
+                    .collect();
+                report("invalid HyperLogLog register(s)", bad_registers);
+            }
+            _ => {}
+        }
+        problems
+    }
+
+    /// Merge another sketch's hashes into this one, optionally clipping
+    /// the result to `size` hashes (scaled sketches also keep any hashes
+    /// under their scaled maximum).
+    pub fn merge(&mut self, other: &Sketch, size: Option<usize>) -> Result<()> {
+        // TODO: do something with filters?
+        if let Some((name, v1, v2)) = self.sketch_params.check_compatibility(&other.sketch_params) {
+            bail!(
+                "First sketch has {} {}, but second sketch has {0} {}",
+                name,
+                v1,
+                v2,
+            );
+        }
+
+        // update my parameters from the remote's
+        self.seq_length += other.seq_length;
+        self.num_valid_kmers += other.num_valid_kmers;
+
+        // HyperLogLog sketches are merged register-by-register (and can't be
+        // clipped to a size)
+        if let SketchParams::HyperLogLog { precision, .. } = self.sketch_params {
+            self.hashes = hyperloglog::merge(&self.hashes, &other.hashes, precision);
+            return Ok(());
+        }
+
+        let sketch1 = &self.hashes;
+        let sketch2 = &other.hashes;
+        let mut new_hashes = Vec::with_capacity(sketch1.len() + sketch2.len());
+        let (mut i, mut j) = (0, 0);
+        while (i < sketch1.len()) && (j < sketch2.len()) {
+            if sketch1[i].hash < sketch2[j].hash {
+                new_hashes.push(sketch1[i].clone());
+                i += 1;
+            } else if sketch2[j].hash < sketch1[i].hash {
+                new_hashes.push(sketch2[j].clone());
+                j += 1;
+            } else {
+                new_hashes.push(KmerCount {
+                    hash: sketch1[i].hash,
+                    kmer: sketch1[i].kmer.clone(),
+                    count: sketch1[i].count.saturating_add(sketch2[j].count),
+                    extra_count: sketch1[i]
+                        .extra_count
+                        .saturating_add(sketch2[j].extra_count),
+                    label: sketch1[i].label.clone(),
+                });
+                i += 1;
+                j += 1;
+            }
+        }
+        // one of the sketches may still have hashes left over
+        new_hashes.extend_from_slice(&sketch1[i..]);
+        new_hashes.extend_from_slice(&sketch2[j..]);
+
+        // now clip to the appropriate size
+        match (size, self.sketch_params.hash_info().3) {
+            (Some(s), Some(sc)) => {
+                // keep hashes <= max/sc (or) the first s, whichever is more
+                let max_hash = scaled_max_hash(sc);
+                let keep = new_hashes
+                    .iter()
+                    .enumerate()
+                    .take_while(|(ix, h)| h.hash <= max_hash || *ix < s)
+                    .count();
+                new_hashes.truncate(keep);
+            }
+            (None, Some(sc)) => {
+                let max_hash = scaled_max_hash(sc);
+                let keep = new_hashes.iter().take_while(|h| h.hash <= max_hash).count();
+                new_hashes.truncate(keep);
+            }
+            (Some(s), None) => new_hashes.truncate(s),
+            (None, None) => {}
+        }
+        self.hashes = new_hashes;
+        Ok(())
+    }
+
+    /// Sketch a bare sequence with this sketch's parameters and merge the
+    /// result in (clipped to the sketch's size).
+    pub fn add_sequence(&mut self, seq: &[u8]) -> Result<()> {
+        let mut sketcher = self.sketch_params.create_sketcher();
+        sketcher.process_seq(seq);
+        let size = match self.sketch_params {
+            SketchParams::Mash { final_size, .. } => Some(final_size),
+            SketchParams::Scaled {
+                kmers_to_sketch, ..
+            } => Some(kmers_to_sketch),
+            _ => None,
+        };
+        self.merge(&sketcher.to_sketch(), size)
+    }
+
+    /// Make a lower-resolution copy of this sketch, e.g. to compare it with
+    /// sketches made with a smaller size or scale.
+    ///
+    /// Both Mash and scaled sketches hold the lowest hashes of their inputs
+    /// so either can be reduced to a smaller size, or to a smaller scale as
+    /// long as they hold every hash below the new cutoff.
+    pub fn downsample(&self, target: Downsample) -> Result<Sketch> {
+        let (kmer_length, hash_seed, hash_function) = match self.sketch_params {
+            SketchParams::Mash {
+                kmer_length,
+                hash_seed,
+                hash_function,
+                ..
+            }
+            | SketchParams::Scaled {
+                kmer_length,
+                hash_seed,
+                hash_function,
+                ..
+            } => (kmer_length, hash_seed, hash_function),
+            _ => bail!("Only Mash and scaled sketches can be downsampled"),
+        };
+
+        let (hashes, sketch_params) = match (&self.sketch_params, target) {
+            (_, Downsample::Size(0)) => bail!("Sketches can't be downsampled to a size of 0"),
+            (SketchParams::Mash { final_size, .. }, Downsample::Size(size))
+                if size > *final_size =>
+            {
+                bail!(
+                    "Sketch {} can't be downsampled to {} hashes; it only has a size of {}",
+                    self.name,
+                    size,
+                    final_size
+                );
+            }
+            (_, Downsample::Size(size)) => {
+                let hashes = self.hashes.iter().take(size).cloned().collect();
+                let no_strict = match self.sketch_params {
+                    SketchParams::Mash { no_strict, .. } => no_strict,
+                    // a scaled sketch may have fewer than `size` hashes
+                    _ => true,
+                };
+                let params = SketchParams::Mash {
+                    kmers_to_sketch: size,
+                    final_size: size,
+                    no_strict,
+                    kmer_length,
+                    hash_seed,
+                    hash_function,
+                };
+                (hashes, params)
+            }
+            (_, Downsample::Scale(scale)) if !(scale > 0. && scale <= 1.) => {
+                bail!("Scale must be between 0 and 1 (not {})", scale);
+            }
+            (
+                SketchParams::Scaled {
+                    kmers_to_sketch,
+                    scale: old_scale,
+                    ..
+                },
+                Downsample::Scale(scale),
+            ) => {
+                if scale > *old_scale {
+                    bail!(
+                        "Sketch {} can't be downsampled to a scale of {}; it only has a scale of {}",
+                        self.name,
+                        scale,
+                        old_scale
+                    );
+                }
+                // like the scaled sketcher, keep up to `kmers_to_sketch` hashes
+                // above the cutoff
+                let max_hash = scaled_max_hash(scale);
+                let hashes = self
+                    .hashes
+                    .iter()
+                    .enumerate()
+                    .filter(|(ix, h)| *ix < *kmers_to_sketch || h.hash <= max_hash)
+                    .map(|(_, h)| h.clone())
+                    .collect();
+                let params = SketchParams::Scaled {
+                    kmers_to_sketch: *kmers_to_sketch,
+                    kmer_length,
+                    scale,
+                    hash_seed,
+                    hash_function,
+                };
+                (hashes, params)
+            }
+            (SketchParams::Mash { final_size, .. }, Downsample::Scale(scale)) => {
+                let max_hash = scaled_max_hash(scale);
+                // if the sketch is full, hashes past its last one are missing
+                // so that has to be past the cutoff
+                let last_hash = self.hashes.last().map(|h| h.hash);
+                if self.hashes.len() >= *final_size && last_hash < Some(max_hash) {
+                    bail!(
+                        "Sketch {} doesn't have enough hashes to be downsampled to a scale of {}",
+                        self.name,
+                        scale
+                    );
+                }
+                let hashes = self
+                    .hashes
+                    .iter()
+                    .filter(|h| h.hash <= max_hash)
+                    .cloned()
+                    .collect();
+                let params = SketchParams::Scaled {
+                    kmers_to_sketch: 0,
+                    kmer_length,
+                    scale,
+                    hash_seed,
+                    hash_function,
+                };
+                (hashes, params)
+            }
+            _ => unreachable!(),
+        };
+
+        Ok(Sketch {
+            hashes,
+            sketch_params,
+            ..self.clone()
+        })
+    }
+}
+
+/// Downsample sketches to the coarsest resolution they have in common (the
+/// smallest size for Mash sketches and the smallest scale for scaled ones) so
+/// that comparisons between them aren't biased by differing resolutions.
+///
+/// Returns how many sketches were downsampled.
+pub fn harmonize_sketches(sketches: &mut [Sketch]) -> Result<usize> {
+    let mut min_size = None;
+    let mut min_scale = None;
+    for sketch in sketches.iter() {
+        match sketch.sketch_params {
+            SketchParams::Mash { final_size, .. } => {
+                min_size = Some(min_size.map_or(final_size, |s| usize::min(s, final_size)));
+            }
+            SketchParams::Scaled { scale, .. } => {
+                min_scale = Some(min_scale.map_or(scale, |s| f64::min(s, scale)));
+            }
+            _ => {}
+        }
+    }
+
+    let mut n_downsampled = 0;
+    for sketch in sketches.iter_mut() {
+        let target = match (&sketch.sketch_params, min_size, min_scale) {
+            (SketchParams::Mash { final_size, .. }, Some(size), _) if *final_size > size => {
+                Downsample::Size(size)
+            }
+            (SketchParams::Scaled { scale, .. }, _, Some(min_scale)) if *scale > min_scale => {
+                Downsample::Scale(min_scale)
+            }
+            _ => continue,
+        };
+        *sketch = sketch.downsample(target)?;
+        n_downsampled += 1;
+    }
+    Ok(n_downsampled)
 }
 
 impl Into<JsonSketch> for Sketch {
     fn into(self) -> JsonSketch {
-        JsonSketch::new(
+        let mut sketch = JsonSketch::new(
             &self.name,
             self.seq_length,
             self.num_valid_kmers,
             self.hashes,
             &self.filter_params.to_serialized(),
-        )
+        );
+        sketch.comment = Some(self.comment);
+        sketch.provenance = self.provenance;
+        sketch
     }
 }
 
@@ -84,11 +503,13 @@ fn set_sketch_params(mut cap_sketch_params: sketch_params::Builder, sketch_param
             no_strict,
             kmer_length,
             hash_seed,
+            hash_function,
         } => {
             cap_sketch_params.set_sketch_method(SketchMethod::MurmurHash3);
             cap_sketch_params.set_kmer_length(kmer_length);
             cap_sketch_params.set_kmers_to_sketch(kmers_to_sketch as u64);
             cap_sketch_params.set_hash_seed(hash_seed);
+            cap_sketch_params.set_hash_function(to_cap_hash_function(hash_function));
             cap_sketch_params.set_final_size(final_size as u64);
             cap_sketch_params.set_no_strict(no_strict);
         }
@@ -97,21 +518,50 @@ fn set_sketch_params(mut cap_sketch_params: sketch_params::Builder, sketch_param
             kmer_length,
             scale,
             hash_seed,
+            hash_function,
         } => {
             cap_sketch_params.set_sketch_method(SketchMethod::MurmurHash3Scaled);
             cap_sketch_params.set_kmer_length(kmer_length);
             cap_sketch_params.set_kmers_to_sketch(kmers_to_sketch as u64);
             cap_sketch_params.set_hash_seed(hash_seed);
+            cap_sketch_params.set_hash_function(to_cap_hash_function(hash_function));
             cap_sketch_params.set_scale(scale);
         }
         SketchParams::AllCounts { kmer_length } => {
             cap_sketch_params.set_sketch_method(SketchMethod::None);
             cap_sketch_params.set_kmer_length(kmer_length);
         }
+        SketchParams::HyperLogLog {
+            kmer_length,
+            precision,
+            hash_seed,
+            hash_function,
+        } => {
+            cap_sketch_params.set_sketch_method(SketchMethod::MurmurHash3HyperLogLog);
+            cap_sketch_params.set_kmer_length(kmer_length);
+            cap_sketch_params.set_hash_seed(hash_seed);
+            cap_sketch_params.set_hash_function(to_cap_hash_function(hash_function));
+            cap_sketch_params.set_hll_precision(precision);
+        }
+    }
+}
+
+// the "sketch methods" are named for MurmurHash3 because that's all finch
+// originally supported, but the hash function is stored separately now
+fn to_cap_hash_function(hash_function: HashFunction) -> CapHashFunction {
+    match hash_function {
+        HashFunction::MurmurHash3 => CapHashFunction::MurmurHash3,
+        HashFunction::XxHash3 => CapHashFunction::XxHash3,
+        HashFunction::NtHash => CapHashFunction::NtHash,
     }
 }
 
 fn get_sketch_params(cap_sketch_params: sketch_params::Reader) -> Result<SketchParams> {
+    let hash_function = match cap_sketch_params.get_hash_function()? {
+        CapHashFunction::MurmurHash3 => HashFunction::MurmurHash3,
+        CapHashFunction::XxHash3 => HashFunction::XxHash3,
+        CapHashFunction::NtHash => HashFunction::NtHash,
+    };
     Ok(match cap_sketch_params.get_sketch_method()? {
         SketchMethod::MurmurHash3 => SketchParams::Mash {
             kmers_to_sketch: cap_sketch_params.get_kmers_to_sketch() as usize,
@@ -119,16 +569,33 @@ fn get_sketch_params(cap_sketch_params: sketch_params::Reader) -> Result<SketchP
             no_strict: cap_sketch_params.get_no_strict(),
             kmer_length: cap_sketch_params.get_kmer_length(),
             hash_seed: cap_sketch_params.get_hash_seed(),
+            hash_function,
         },
         SketchMethod::MurmurHash3Scaled => SketchParams::Scaled {
             kmers_to_sketch: cap_sketch_params.get_kmers_to_sketch() as usize,
             kmer_length: cap_sketch_params.get_kmer_length(),
             scale: cap_sketch_params.get_scale(),
             hash_seed: cap_sketch_params.get_hash_seed(),
+            hash_function,
         },
         SketchMethod::None => SketchParams::AllCounts {
             kmer_length: cap_sketch_params.get_kmer_length(),
         },
+        SketchMethod::MurmurHash3HyperLogLog => {
+            let precision = cap_sketch_params.get_hll_precision();
+            if !hyperloglog::PRECISIONS.contains(&precision) {
+                bail!(
+                    "Sketch has an unsupported HyperLogLog precision ({})",
+                    precision
+                );
+            }
+            SketchParams::HyperLogLog {
+                kmer_length: cap_sketch_params.get_kmer_length(),
+                precision,
+                hash_seed: cap_sketch_params.get_hash_seed(),
+                hash_function,
+            }
+        }
     })
 }
 
@@ -152,7 +619,9 @@ pub fn write_finch_file(mut file: &mut dyn Write, sketches: &[Sketch]) -> Result
         for (j, hash) in sketch.hashes.iter().enumerate() {
             let mut cap_hash = hashes.reborrow().get(j as u32);
             cap_hash.set_hash(hash.hash);
-            cap_hash.set_kmer(&hash.kmer);
+            if !hash.kmer.is_empty() {
+                cap_hash.set_kmer(&hash.kmer);
+            }
             cap_hash.set_count(hash.count);
             cap_hash.set_extra_count(hash.extra_count);
             if let Some(label) = &hash.label {
@@ -172,10 +641,37 @@ pub fn write_finch_file(mut file: &mut dyn Write, sketches: &[Sketch]) -> Result
         );
         cap_filter_params.set_err_filter(sketch.filter_params.err_filter);
         cap_filter_params.set_strand_filter(sketch.filter_params.strand_filter);
+        cap_filter_params.set_min_qual(sketch.filter_params.qual_filter.unwrap_or(0));
+        cap_filter_params.set_max_kmer_errors(sketch.filter_params.kmer_err_filter.unwrap_or(0.));
+        cap_filter_params.set_dust_filter(sketch.filter_params.dust_filter.unwrap_or(-1.));
+        if let Some(exclusion) = &sketch.filter_params.exclusion_filter {
+            cap_filter_params.set_exclusion_set(&exclusion.name);
+        }
+        cap_filter_params
+            .set_bloom_filter_size(sketch.filter_params.bloom_filter_size.unwrap_or(0) as u64);
 
         let sketch_params = &sketch.sketch_params;
         let cap_sketch_params = cap_sketch.reborrow().init_sketch_params();
         set_sketch_params(cap_sketch_params, &sketch_params);
+
+        if let Some(provenance) = &sketch.provenance {
+            let mut cap_provenance = cap_sketch.reborrow().init_provenance();
+            cap_provenance.set_finch_version(&provenance.finch_version);
+            let mut cap_files = cap_provenance
+                .reborrow()
+                .init_input_files(provenance.input_files.len() as u32);
+            for (ix, filename) in provenance.input_files.iter().enumerate() {
+                cap_files.set(ix as u32, filename);
+            }
+            let mut cap_checksums = cap_provenance
+                .reborrow()
+                .init_input_checksums(provenance.input_checksums.len() as u32);
+            for (ix, checksum) in provenance.input_checksums.iter().enumerate() {
+                cap_checksums.set(ix as u32, checksum);
+            }
+            cap_provenance.set_timestamp(provenance.timestamp);
+            cap_provenance.set_command_line(&provenance.command_line);
+        }
     }
 
     capnp_serialize::write_message(&mut file, &message)?;
@@ -224,6 +720,50 @@ pub fn read_finch_file(mut file: &mut dyn BufRead) -> Result<Vec<Sketch>> {
             abun_filter: (low_abun_filter, high_abun_filter),
             err_filter: cap_filter_params.get_err_filter(),
             strand_filter: cap_filter_params.get_strand_filter(),
+            qual_filter: match cap_filter_params.get_min_qual() {
+                0 => None,
+                i => Some(i),
+            },
+            kmer_err_filter: match cap_filter_params.get_max_kmer_errors() {
+                e if e > 0. => Some(e),
+                _ => None,
+            },
+            dust_filter: match cap_filter_params.get_dust_filter() {
+                d if d >= 0. => Some(d),
+                _ => None,
+            },
+            exclusion_filter: if cap_filter_params.has_exclusion_set() {
+                Some(ExclusionSet::from_name(
+                    cap_filter_params.get_exclusion_set()?,
+                ))
+            } else {
+                None
+            },
+            bloom_filter_size: match cap_filter_params.get_bloom_filter_size() {
+                0 => None,
+                i => Some(i as usize),
+            },
+        };
+
+        let provenance = if cap_sketch.has_provenance() {
+            let cap_provenance = cap_sketch.get_provenance()?;
+            Some(Provenance {
+                finch_version: cap_provenance.get_finch_version()?.to_string(),
+                input_files: cap_provenance
+                    .get_input_files()?
+                    .iter()
+                    .map(|f| f.map(|f| f.to_string()))
+                    .collect::<StdResult<_, _>>()?,
+                input_checksums: cap_provenance
+                    .get_input_checksums()?
+                    .iter()
+                    .map(|c| c.map(|c| c.to_string()))
+                    .collect::<StdResult<_, _>>()?,
+                timestamp: cap_provenance.get_timestamp(),
+                command_line: cap_provenance.get_command_line()?.to_string(),
+            })
+        } else {
+            None
         };
 
         sketches.push(Sketch {
@@ -235,7 +775,308 @@ pub fn read_finch_file(mut file: &mut dyn BufRead) -> Result<Vec<Sketch>> {
             hashes,
             sketch_params,
             filter_params,
+            provenance,
         });
     }
     Ok(sketches)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_sketch(hashes: &[u64], sketch_params: SketchParams) -> Sketch {
+        Sketch {
+            name: "test".to_string(),
+            seq_length: 100,
+            num_valid_kmers: 98,
+            comment: "".to_string(),
+            hashes: hashes
+                .iter()
+                .map(|&hash| KmerCount {
+                    hash,
+                    kmer: vec![],
+                    count: 1,
+                    extra_count: 0,
+                    label: None,
+                })
+                .collect(),
+            filter_params: FilterParams::default(),
+            sketch_params,
+            provenance: None,
+        }
+    }
+
+    fn mash_params(size: usize) -> SketchParams {
+        SketchParams::Mash {
+            kmers_to_sketch: size,
+            final_size: size,
+            no_strict: false,
+            kmer_length: 3,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        }
+    }
+
+    fn scaled_params(size: usize, scale: f64) -> SketchParams {
+        SketchParams::Scaled {
+            kmers_to_sketch: size,
+            kmer_length: 3,
+            scale,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        }
+    }
+
+    fn hash_values(sketch: &Sketch) -> Vec<u64> {
+        sketch.hashes.iter().map(|h| h.hash).collect()
+    }
+
+    #[test]
+    fn test_downsample() {
+        let quarter = u64::max_value() / 4;
+        let mash = make_sketch(
+            &[1, quarter - 1, quarter + 1, u64::max_value() - 1],
+            mash_params(4),
+        );
+
+        let smaller = mash.downsample(Downsample::Size(2)).unwrap();
+        assert_eq!(hash_values(&smaller), vec![1, quarter - 1]);
+        assert_eq!(smaller.sketch_params, mash_params(2));
+        assert_eq!(smaller.num_valid_kmers, mash.num_valid_kmers);
+        assert!(mash.downsample(Downsample::Size(5)).is_err());
+
+        let scaled = mash.downsample(Downsample::Scale(0.25)).unwrap();
+        assert_eq!(hash_values(&scaled), vec![1, quarter - 1]);
+        assert_eq!(scaled.sketch_params, scaled_params(0, 0.25));
+        assert!(scaled.validate().is_ok());
+        // the sketch is full and might be missing hashes below the cutoff
+        assert!(smaller.downsample(Downsample::Scale(0.25)).is_err());
+        assert!(mash.downsample(Downsample::Scale(1.5)).is_err());
+
+        // scaled sketches still keep up to `kmers_to_sketch` hashes
+        let scaled = make_sketch(&[1, 2, quarter - 1, quarter + 1], scaled_params(1, 0.5));
+        let downsampled = scaled.downsample(Downsample::Scale(0.25)).unwrap();
+        assert_eq!(hash_values(&downsampled), vec![1, 2, quarter - 1]);
+        assert_eq!(downsampled.sketch_params, scaled_params(1, 0.25));
+        assert!(downsampled.downsample(Downsample::Scale(0.5)).is_err());
+        let downsampled = scaled.downsample(Downsample::Size(2)).unwrap();
+        assert_eq!(hash_values(&downsampled), vec![1, 2]);
+
+        let hll = make_sketch(
+            &[1],
+            SketchParams::HyperLogLog {
+                kmer_length: 3,
+                precision: 8,
+                hash_seed: 0,
+                hash_function: HashFunction::MurmurHash3,
+            },
+        );
+        assert!(hll.downsample(Downsample::Size(1)).is_err());
+    }
+
+    #[test]
+    fn test_harmonize_sketches() {
+        let quarter = u64::max_value() / 4;
+        let mut sketches = vec![
+            make_sketch(&[1, 2, 3], mash_params(3)),
+            make_sketch(&[1, 3], mash_params(2)),
+            make_sketch(&[1, quarter + 1], scaled_params(0, 0.5)),
+            make_sketch(&[2], scaled_params(0, 0.25)),
+        ];
+        assert_eq!(harmonize_sketches(&mut sketches).unwrap(), 2);
+        assert_eq!(hash_values(&sketches[0]), vec![1, 2]);
+        assert_eq!(sketches[0].sketch_params, mash_params(2));
+        assert_eq!(hash_values(&sketches[1]), vec![1, 3]);
+        assert_eq!(hash_values(&sketches[2]), vec![1]);
+        assert_eq!(sketches[2].sketch_params, scaled_params(0, 0.25));
+        assert_eq!(harmonize_sketches(&mut sketches).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_merge_sketches() {
+        let mut sketch = make_sketch(&[1, 3], mash_params(4));
+        sketch
+            .merge(&make_sketch(&[2, 3, 5, 7, 9], mash_params(4)), Some(4))
+            .unwrap();
+        assert_eq!(hash_values(&sketch), vec![1, 2, 3, 5]);
+        assert_eq!(sketch.hashes[2].count, 2);
+        assert_eq!(sketch.seq_length, 200);
+
+        // leftover hashes from either side are kept
+        let mut sketch = make_sketch(&[1, 2, 8, 9], mash_params(10));
+        sketch
+            .merge(&make_sketch(&[3], mash_params(10)), None)
+            .unwrap();
+        assert_eq!(hash_values(&sketch), vec![1, 2, 3, 8, 9]);
+
+        // counts max out instead of overflowing
+        let mut sketch = make_sketch(&[1], mash_params(4));
+        sketch.hashes[0].count = u32::max_value();
+        sketch.hashes[0].extra_count = u32::max_value();
+        let mut other = make_sketch(&[1], mash_params(4));
+        other.hashes[0].extra_count = 1;
+        sketch.merge(&other, None).unwrap();
+        assert_eq!(sketch.hashes[0].count, u32::max_value());
+        assert_eq!(sketch.hashes[0].extra_count, u32::max_value());
+
+        // sketches with different parameters can't be merged
+        let mut other_seed = mash_params(4);
+        if let SketchParams::Mash { hash_seed, .. } = &mut other_seed {
+            *hash_seed = 42;
+        }
+        let mut sketch = make_sketch(&[1], mash_params(4));
+        assert!(sketch.merge(&make_sketch(&[2], other_seed), None).is_err());
+        assert_eq!(hash_values(&sketch), vec![1]);
+        assert_eq!(sketch.seq_length, 100);
+    }
+
+    #[test]
+    fn test_add_sequence() {
+        let mut sketch = make_sketch(&[], mash_params(4));
+        sketch.seq_length = 0;
+        sketch.add_sequence(b"ACGTGCATGCAAT").unwrap();
+        assert_eq!(sketch.len(), 4);
+        assert_eq!(sketch.seq_length, 13);
+        assert!(sketch.validate().is_ok());
+
+        let mut expected = sketch.clone();
+        let mut more = make_sketch(&[], mash_params(4));
+        more.add_sequence(b"TTTAGGCCACCTG").unwrap();
+        expected.merge(&more, Some(4)).unwrap();
+        sketch.add_sequence(b"TTTAGGCCACCTG").unwrap();
+        assert_eq!(hash_values(&sketch), hash_values(&expected));
+        assert_eq!(sketch.len(), 4);
+        assert_eq!(sketch.seq_length, 26);
+    }
+
+    #[test]
+    fn test_strip_kmers() {
+        let mut sketch = make_sketch(&[1, 5, 9], mash_params(3));
+        assert!(!sketch.has_kmers());
+        for hash in &mut sketch.hashes {
+            hash.kmer = b"ACG".to_vec();
+        }
+        assert!(sketch.has_kmers());
+        sketch.strip_kmers();
+        assert!(!sketch.has_kmers());
+        assert_eq!(hash_values(&sketch), vec![1, 5, 9]);
+
+        // kmers are left out of JSON entirely...
+        let multisketch = MultiSketch::from_sketches(&[sketch.clone()]).unwrap();
+        let json = serde_json::to_string(&multisketch).unwrap();
+        assert!(!json.contains("kmers"));
+        let multisketch: MultiSketch = serde_json::from_str(&json).unwrap();
+        assert_eq!(multisketch.to_sketches().unwrap()[0].hashes, sketch.hashes);
+
+        // ...and the binary format
+        let mut buffer = Vec::new();
+        write_finch_file(&mut buffer, &[sketch.clone()]).unwrap();
+        let sketches = read_finch_file(&mut &buffer[..]).unwrap();
+        assert_eq!(sketches[0].hashes, sketch.hashes);
+    }
+
+    #[test]
+    fn test_load_hll_precision() {
+        let hll_params = |precision| SketchParams::HyperLogLog {
+            kmer_length: 3,
+            precision,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        };
+        for &(precision, ok) in &[(4, true), (24, true), (2, false), (30, false)] {
+            let sketch = make_sketch(&[], hll_params(precision));
+
+            let multisketch = MultiSketch::from_sketches(&[sketch.clone()]).unwrap();
+            let json = serde_json::to_string(&multisketch).unwrap();
+            let multisketch: MultiSketch = serde_json::from_str(&json).unwrap();
+            assert_eq!(multisketch.to_sketches().is_ok(), ok);
+
+            let mut buffer = Vec::new();
+            write_finch_file(&mut buffer, &[sketch]).unwrap();
+            assert_eq!(read_finch_file(&mut &buffer[..]).is_ok(), ok);
+        }
+    }
+
+    #[test]
+    fn test_validate_sketch() {
+        let hashes: Vec<KmerCount> = [1, 5, 9]
+            .iter()
+            .map(|&hash| KmerCount {
+                hash,
+                kmer: b"ACG".to_vec(),
+                count: 1,
+                extra_count: 0,
+                label: None,
+            })
+            .collect();
+        let mut sketch = Sketch {
+            name: "test".to_string(),
+            seq_length: 10,
+            num_valid_kmers: 8,
+            comment: "".to_string(),
+            hashes,
+            filter_params: FilterParams::default(),
+            sketch_params: SketchParams::Mash {
+                kmers_to_sketch: 3,
+                final_size: 3,
+                no_strict: true,
+                kmer_length: 3,
+                hash_seed: 0,
+                hash_function: HashFunction::MurmurHash3,
+            },
+            provenance: None,
+        };
+        assert!(sketch.validate().is_ok());
+
+        sketch.hashes[0].hash = 9;
+        sketch.hashes[1].count = 0;
+        sketch.hashes[2].kmer = b"AC".to_vec();
+        let problems = sketch.find_problems();
+        assert_eq!(
+            problems,
+            vec![
+                "1 hash(es) out of order (first at position 1)",
+                "1 hash(es) with a count of 0 (first at position 1, hash 5)",
+                "1 kmer(s) not of length 3 (first at position 2, hash 9)",
+            ]
+        );
+        assert!(sketch.validate().is_err());
+
+        sketch.hashes[0].hash = 5;
+        sketch.hashes[1].count = 1;
+        sketch.hashes[2].kmer = b"ACG".to_vec();
+        assert_eq!(
+            sketch.find_problems(),
+            vec!["1 duplicate hash(es) (first at position 1, hash 5)"]
+        );
+
+        // scaled sketches can only keep hashes above the maximum if they
+        // haven't filled up yet
+        sketch.hashes[0].hash = 1;
+        sketch.hashes[2].hash = u64::max_value() - 1;
+        sketch.sketch_params = SketchParams::Scaled {
+            kmers_to_sketch: 3,
+            kmer_length: 3,
+            scale: 0.5,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        };
+        assert!(sketch.validate().is_ok());
+        sketch.sketch_params = SketchParams::Scaled {
+            kmers_to_sketch: 2,
+            kmer_length: 3,
+            scale: 0.5,
+            hash_seed: 0,
+            hash_function: HashFunction::MurmurHash3,
+        };
+        assert_eq!(
+            sketch.find_problems(),
+            vec![format!(
+                "1 hash(es) above the scaled maximum of {} (first at position 2, hash {})",
+                u64::max_value() / 2,
+                u64::max_value() - 1
+            )]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+/// A record of how a sketch was made (so it can be audited/reproduced later)
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub finch_version: String,
+    pub input_files: Vec<String>,
+    /// SHA-256 checksums (hex-encoded) of the raw contents of `input_files`
+    pub input_checksums: Vec<String>,
+    /// When the sketch was made (in seconds since the Unix epoch)
+    pub timestamp: u64,
+    pub command_line: String,
+}
+
+impl Provenance {
+    /// Provenance for a sketch made now by this version of finch (the input
+    /// files and command line are filled in by the caller)
+    pub fn new(input_checksums: Vec<String>) -> Self {
+        Provenance {
+            finch_version: env!("CARGO_PKG_VERSION").to_string(),
+            input_files: Vec::new(),
+            input_checksums,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            command_line: String::new(),
+        }
+    }
+}
+
+/// Format a Unix timestamp as an ISO 8601 UTC date/time, e.g.
+/// `2020-01-31T12:00:00Z`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    // convert days since the epoch to a date (see Howard Hinnant's
+    // `civil_from_days` algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+/// Wraps a reader and computes the SHA-256 checksum of everything read
+/// through it
+pub struct ChecksumReader<R> {
+    inner: R,
+    sha: Sha256,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R) -> Self {
+        ChecksumReader {
+            inner,
+            sha: Sha256::new(),
+        }
+    }
+
+    /// The hex-encoded checksum of the data read so far
+    pub fn hex_digest(&self) -> String {
+        self.sha
+            .clone()
+            .result()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_read = self.inner.read(buf)?;
+        self.sha.input(&buf[..n_read]);
+        Ok(n_read)
+    }
+}
+
+/// Compute the checksum of an entire stream (e.g. to check it against a
+/// sketch's provenance)
+pub fn checksum_reader<R: Read>(reader: R) -> io::Result<String> {
+    let mut reader = ChecksumReader::new(reader);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.hex_digest())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1_580_472_000), "2020-01-31T12:00:00Z");
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(
+            checksum_reader(&b""[..]).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            checksum_reader(&b"abc"[..]).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // the multi-block NIST test vectors
+        assert_eq!(
+            checksum_reader(&b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"[..])
+                .unwrap(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            checksum_reader(
+                &b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"[..]
+            )
+            .unwrap(),
+            "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1"
+        );
+        // lengths around where the padding spills over into another block
+        for (len, expected) in &[
+            (
+                55,
+                "463eb28e72f82e0a96c0a4cc53690c571281131f672aa229e0d45ae59b598b59",
+            ),
+            (
+                56,
+                "da2ae4d6b36748f2a318f23e7ab1dfdf45acdc9d049bd80e59de82a60895f562",
+            ),
+            (
+                57,
+                "2fe741af801cc238602ac0ec6a7b0c3a8a87c7fc7d7f02a3fe03d1c12eac4d8f",
+            ),
+            (
+                63,
+                "29af2686fd53374a36b0846694cc342177e428d1647515f078784d69cdb9e488",
+            ),
+            (
+                64,
+                "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108",
+            ),
+            (
+                65,
+                "4bfd2c8b6f1eec7a2afeb48b934ee4b2694182027e6d0fc075074f2fabb31781",
+            ),
+        ] {
+            let data: Vec<u8> = (0..*len).collect();
+            assert_eq!(&checksum_reader(&data[..]).unwrap(), expected);
+        }
+        // spans more than one block (and is read in odd-sized pieces)
+        let data = vec![b'a'; 1000];
+        let mut reader = ChecksumReader::new(&data[..]);
+        let mut buf = [0; 37];
+        while reader.read(&mut buf).unwrap() > 0 {}
+        assert_eq!(
+            reader.hex_digest(),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+}
@@ -1,13 +1,23 @@
+use std::any::Any;
+use std::io::Write;
+
 use needletail::bitkmer::{bitmer_to_bytes, reverse_complement};
 use needletail::{Sequence, SequenceRecord};
 
-use crate::sketch_schemes::{KmerCount, SketchParams, SketchScheme};
+use crate::filtering::KmerFilter;
+use crate::sketch_schemes::{
+    same_sketcher, write_checkpoint, CheckpointRef, KmerCount, SketchParams, SketchScheme,
+};
+use crate::Result as FinchResult;
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct AllCountsSketcher {
     counts: Vec<u32>,
+    // the number of distinct (canonical) kmers seen
+    n_kmers: usize,
     total_bases: u64,
     k: u8,
+    pub(crate) kmer_filter: KmerFilter,
 }
 
 impl AllCountsSketcher {
@@ -15,19 +25,53 @@ impl AllCountsSketcher {
         // TODO: should we take a size parameter or the like and clip this?
         AllCountsSketcher {
             counts: vec![0; 4usize.pow(k.into())],
+            n_kmers: 0,
             total_bases: 0,
             k,
+            kmer_filter: KmerFilter::default(),
         }
     }
+
+    /// Add the counts from another sketcher (with the same kmer length) to
+    /// this one
+    pub fn merge(&mut self, other: &AllCountsSketcher) {
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c = c.saturating_add(*o);
+        }
+        self.n_kmers = (0..self.counts.len() as u64)
+            .filter(|ix| {
+                let rc = reverse_complement((*ix, self.k)).0;
+                // only count each kmer once (with its lower strand)
+                *ix <= rc && (self.counts[*ix as usize] > 0 || self.counts[rc as usize] > 0)
+            })
+            .count();
+        self.total_bases += other.total_bases;
+    }
 }
 
 impl SketchScheme for AllCountsSketcher {
     fn process(&mut self, seq: SequenceRecord) {
-        for (_, kmer, _) in seq.normalize(false).bit_kmers(self.k, false) {
+        let norm_seq = seq.normalize(false);
+        let mask = self
+            .kmer_filter
+            .kmer_mask(&norm_seq, seq.qual.as_ref().map(|q| &q[..]), self.k);
+        for (pos, kmer, _) in norm_seq.bit_kmers(self.k, false) {
+            if !mask.passes(pos) {
+                continue;
+            }
+            if self.counts[kmer.0 as usize] == 0
+                && self.counts[reverse_complement((kmer.0, self.k)).0 as usize] == 0
+            {
+                self.n_kmers += 1;
+            }
             self.counts[kmer.0 as usize] = self.counts[kmer.0 as usize].saturating_add(1);
         }
     }
 
+    fn set_kmer_filter(&mut self, kmer_filter: KmerFilter) {
+        self.kmer_filter = kmer_filter;
+    }
+
     fn total_bases_and_kmers(&self) -> (u64, u64) {
         (
             self.total_bases,
@@ -58,9 +102,26 @@ impl SketchScheme for AllCountsSketcher {
         results
     }
 
+    fn sketch_size(&self) -> usize {
+        self.n_kmers
+    }
+
     fn parameters(&self) -> SketchParams {
         SketchParams::AllCounts {
             kmer_length: self.k,
         }
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn merge_from(&mut self, other: &dyn SketchScheme) -> FinchResult<()> {
+        self.merge(same_sketcher(self, other)?);
+        Ok(())
+    }
+
+    fn save_checkpoint(&self, writer: &mut dyn Write) -> FinchResult<()> {
+        write_checkpoint(writer, CheckpointRef::AllCounts(self))
+    }
 }
@@ -2,6 +2,7 @@ use std::cmp::Ordering;
 use std::hash::Hasher;
 
 use murmurhash3::murmurhash3_x64_128;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
 
 // The individual items to store in the BinaryHeap
 pub type ItemHash = u64;
@@ -11,7 +12,171 @@ pub fn hash_f(item: &[u8], seed: u64) -> ItemHash {
     murmurhash3_x64_128(item, seed).0
 }
 
-#[derive(Debug, Clone)]
+/// The function used to hash (canonical) kmers
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum HashFunction {
+    /// The first 64 bits of MurmurHash3_x64_128 (as used by Mash)
+    MurmurHash3,
+    /// The 64-bit variant of XXH3
+    XxHash3,
+    /// The canonical ntHash of the kmer; when sketching sequences this is a
+    /// rolling hash so each kmer's hash is updated from the previous kmer's
+    /// instead of rehashing every base
+    NtHash,
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        HashFunction::MurmurHash3
+    }
+}
+
+impl HashFunction {
+    /// The name of the hash in sketch files (e.g. the JSON `hashType`)
+    pub fn name(self) -> &'static str {
+        match self {
+            HashFunction::MurmurHash3 => "MurmurHash3_x64_128",
+            HashFunction::XxHash3 => "XXH3_64",
+            HashFunction::NtHash => "ntHash",
+        }
+    }
+
+    /// The name of HyperLogLog sketches made with this hash in sketch files
+    pub fn hll_name(self) -> &'static str {
+        match self {
+            HashFunction::MurmurHash3 => "MurmurHash3_x64_128_HyperLogLog",
+            HashFunction::XxHash3 => "XXH3_64_HyperLogLog",
+            HashFunction::NtHash => "ntHash_HyperLogLog",
+        }
+    }
+
+    /// The hash function with this `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            HashFunction::MurmurHash3,
+            HashFunction::XxHash3,
+            HashFunction::NtHash,
+        ]
+        .iter()
+        .find(|f| f.name() == name)
+        .cloned()
+    }
+
+    /// Hash a single kmer (which should be in its canonical form)
+    #[inline]
+    pub fn hash(self, kmer: &[u8], seed: u64) -> ItemHash {
+        match self {
+            HashFunction::MurmurHash3 => hash_f(kmer, seed),
+            HashFunction::XxHash3 => xxh3_64_with_seed(kmer, seed),
+            HashFunction::NtHash => {
+                let (fwd, rev) = nthash_strands(kmer);
+                nthash_finish(fwd, rev, seed)
+            }
+        }
+    }
+}
+
+/// Hashes the kmers of a sequence, rolling the hash along from the previous
+/// kmer where that's possible (i.e. for ntHash)
+#[derive(Clone, Debug)]
+pub struct KmerHasher {
+    hash_function: HashFunction,
+    kmer_length: usize,
+    seed: u64,
+    // the position and strand hashes of the last kmer hashed with ntHash
+    nthash_state: Option<(usize, u64, u64)>,
+}
+
+impl KmerHasher {
+    pub fn new(hash_function: HashFunction, kmer_length: u8, seed: u64) -> Self {
+        KmerHasher {
+            hash_function,
+            kmer_length: usize::from(kmer_length),
+            seed,
+            nthash_state: None,
+        }
+    }
+
+    /// Hash the kmer at `pos` in the (normalized) sequence `seq`; `kmer` is
+    /// its canonical form. Kmers should be hashed in order of position.
+    #[inline]
+    pub fn hash(&mut self, seq: &[u8], pos: usize, kmer: &[u8]) -> ItemHash {
+        if self.hash_function != HashFunction::NtHash {
+            return self.hash_function.hash(kmer, self.seed);
+        }
+        let k = self.kmer_length;
+        let (fwd, rev) = match self.nthash_state {
+            Some((last_pos, mut fwd, mut rev)) if last_pos <= pos && pos - last_pos < k => {
+                for out_pos in last_pos..pos {
+                    let (out_base, in_base) = (seq[out_pos], seq[out_pos + k]);
+                    fwd = fwd.rotate_left(1)
+                        ^ nthash_seed(out_base).rotate_left(k as u32)
+                        ^ nthash_seed(in_base);
+                    rev = rev.rotate_right(1)
+                        ^ nthash_seed(complement(out_base)).rotate_right(1)
+                        ^ nthash_seed(complement(in_base)).rotate_left(k as u32 - 1);
+                }
+                (fwd, rev)
+            }
+            _ => nthash_strands(&seq[pos..pos + k]),
+        };
+        self.nthash_state = Some((pos, fwd, rev));
+        nthash_finish(fwd, rev, self.seed)
+    }
+}
+
+/// The splitmix64 finalizer
+#[inline]
+pub(crate) fn mix(hash: u64) -> u64 {
+    let mut z = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[inline]
+fn nthash_seed(base: u8) -> u64 {
+    match base {
+        b'A' => 0x3c8b_fbb3_95c6_0474,
+        b'C' => 0x3193_c185_62a0_2b4c,
+        b'G' => 0x2032_3ed0_8257_2324,
+        b'T' => 0x2955_49f5_4be2_4456,
+        _ => 0,
+    }
+}
+
+#[inline]
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        n => n,
+    }
+}
+
+/// The forward and reverse complement ntHashes of a kmer
+fn nthash_strands(kmer: &[u8]) -> (u64, u64) {
+    let k = kmer.len();
+    let mut fwd = 0;
+    let mut rev = 0;
+    for (i, base) in kmer.iter().enumerate() {
+        fwd ^= nthash_seed(*base).rotate_left((k - 1 - i) as u32);
+        rev ^= nthash_seed(complement(*base)).rotate_left(i as u32);
+    }
+    (fwd, rev)
+}
+
+/// Combine the strand hashes into the canonical hash; ntHash values aren't
+/// uniformly distributed (the minimum of the two strands is biased low) so
+/// they're also run through a finalizer to make e.g. scaled sketching work
+#[inline]
+fn nthash_finish(fwd: u64, rev: u64, seed: u64) -> ItemHash {
+    mix(u64::min(fwd, rev) ^ seed)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct HashedItem<T> {
     pub(crate) hash: ItemHash,
     pub(crate) item: T,
@@ -37,6 +202,48 @@ impl<T> PartialOrd for HashedItem<T> {
     }
 }
 
+/// A kmer held by a sketcher; sketchers can keep hundreds of thousands of
+/// these around (e.g. while oversketching) so kmers of up to 32 bases are
+/// packed 2 bits per base into a `u64` instead of each getting their own
+/// heap allocation (anything else, e.g. kmers containing non-ACGT bases,
+/// is kept as-is).
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) enum PackedKmer {
+    Bits(u64),
+    Bytes(Box<[u8]>),
+}
+
+impl PackedKmer {
+    pub(crate) fn new(kmer: &[u8]) -> Self {
+        if kmer.len() > 32 {
+            return PackedKmer::Bytes(kmer.into());
+        }
+        let mut bits = 0u64;
+        for base in kmer {
+            let base_bits = match base {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => return PackedKmer::Bytes(kmer.into()),
+            };
+            bits = (bits << 2) | base_bits;
+        }
+        PackedKmer::Bits(bits)
+    }
+
+    /// Unpack the kmer (which must have been `kmer_length` bases long)
+    pub(crate) fn to_bytes(&self, kmer_length: u8) -> Vec<u8> {
+        match self {
+            PackedKmer::Bits(bits) => (0..kmer_length)
+                .rev()
+                .map(|i| b"ACGT"[((bits >> (2 * u32::from(i))) & 3) as usize])
+                .collect(),
+            PackedKmer::Bytes(bytes) => bytes.to_vec(),
+        }
+    }
+}
+
 /// If we're using a `HashMap` where the keys themselves are hashes, it's
 /// a little silly to re-hash them. That's where the `NoHashHasher` comes in.
 pub struct NoHashHasher(u64);
@@ -62,3 +269,89 @@ impl Hasher for NoHashHasher {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_names() {
+        for f in &[
+            HashFunction::MurmurHash3,
+            HashFunction::XxHash3,
+            HashFunction::NtHash,
+        ] {
+            assert_eq!(HashFunction::from_name(f.name()), Some(*f));
+        }
+        assert_eq!(HashFunction::from_name("nope"), None);
+    }
+
+    #[test]
+    fn test_xxhash3() {
+        // reference values from the xxHash test suite
+        assert_eq!(HashFunction::XxHash3.hash(b"", 0), 0x2d06_8005_38d3_94c2);
+        assert_ne!(
+            HashFunction::XxHash3.hash(b"ACGT", 0),
+            HashFunction::XxHash3.hash(b"ACGT", 1)
+        );
+    }
+
+    #[test]
+    fn test_nthash_rolling() {
+        let seq = b"ACGTTGCANNACGGTACCATGACGTACGATTACA";
+        for k in &[1u8, 3, 5, 21] {
+            let k = *k;
+            let mut hasher = KmerHasher::new(HashFunction::NtHash, k, 42);
+            // skip some positions (as happens when kmers have Ns in them)
+            for pos in (0..=seq.len() - usize::from(k)).filter(|p| p % 7 != 3) {
+                let kmer = &seq[pos..pos + usize::from(k)];
+                assert_eq!(
+                    hasher.hash(seq, pos, kmer),
+                    HashFunction::NtHash.hash(kmer, 42),
+                    "k={} pos={}",
+                    k,
+                    pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_nthash_canonical() {
+        // a kmer and its reverse complement hash the same
+        let f = HashFunction::NtHash;
+        assert_eq!(f.hash(b"AACGTG", 0), f.hash(b"CACGTT", 0));
+        assert_ne!(f.hash(b"AACGTG", 0), f.hash(b"AACGTC", 0));
+        assert_ne!(f.hash(b"AACGTG", 0), f.hash(b"AACGTG", 1));
+    }
+
+    #[test]
+    fn test_packed_kmers() {
+        let kmers: &[&[u8]] = &[
+            b"A",
+            b"TTA",
+            b"ACGTACGTACGTACGTACGTACGTACGTACGT",
+            b"TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTT",
+        ];
+        for kmer in kmers {
+            let packed = PackedKmer::new(kmer);
+            match packed {
+                PackedKmer::Bits(_) => {}
+                _ => panic!("{:?} wasn't packed into bits", kmer),
+            }
+            assert_eq!(packed.to_bytes(kmer.len() as u8), kmer.to_vec());
+        }
+        assert_eq!(PackedKmer::new(b"TTA"), PackedKmer::Bits(0b11_11_00));
+
+        // too long or not ACGT
+        let kmers: &[&[u8]] = &[b"ACGTACGTACGTACGTACGTACGTACGTACGTA", b"ACN", b"acg"];
+        for kmer in kmers {
+            let packed = PackedKmer::new(kmer);
+            match packed {
+                PackedKmer::Bytes(_) => {}
+                _ => panic!("{:?} shouldn't have been packed into bits", kmer),
+            }
+            assert_eq!(packed.to_bytes(kmer.len() as u8), kmer.to_vec());
+        }
+    }
+}
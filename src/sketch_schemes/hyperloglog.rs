@@ -0,0 +1,273 @@
+use std::any::Any;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use needletail::{Sequence, SequenceRecord};
+
+use crate::estimators::{hll_estimate, hll_update};
+use crate::filtering::KmerFilter;
+use crate::sketch_schemes::{
+    same_sketcher, write_checkpoint, CheckpointRef, HashFunction, ItemHash, KmerCount, KmerHasher,
+    SketchParams, SketchScheme,
+};
+use crate::Result as FinchResult;
+
+/// The supported precisions (a sketch has `2^precision` registers)
+pub const PRECISIONS: RangeInclusive<u8> = 4..=24;
+
+/// A HyperLogLog "sketch" of all the kmers in a sequence.
+///
+/// This can't be used to compare to normal MinHash sketches, but it's much
+/// cheaper for counting distinct kmers and estimating unions of very large
+/// datasets. When converted into a `Vec<KmerCount>`, each non-empty register
+/// is stored with its index as the `hash` and its value as the `count`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HyperLogLogSketcher {
+    registers: Vec<u8>,
+    precision: u8,
+    kmer_length: u8,
+    total_kmers: u64,
+    total_bases: u64,
+    seed: u64,
+    hash_function: HashFunction,
+    pub(crate) kmer_filter: KmerFilter,
+}
+
+impl HyperLogLogSketcher {
+    pub fn new(precision: u8, kmer_length: u8, seed: u64, hash_function: HashFunction) -> Self {
+        HyperLogLogSketcher {
+            registers: vec![0; 1 << precision],
+            precision,
+            kmer_length,
+            total_kmers: 0,
+            total_bases: 0,
+            seed,
+            hash_function,
+            kmer_filter: KmerFilter::default(),
+        }
+    }
+
+    pub fn push(&mut self, kmer: &[u8]) {
+        let hash = self.hash_function.hash(kmer, self.seed);
+        self.push_hashed(hash);
+    }
+
+    /// Add a kmer that's already been hashed (with this sketcher's hash
+    /// function and seed)
+    pub fn push_hashed(&mut self, hash: ItemHash) {
+        self.total_kmers += 1;
+        if self.kmer_filter.admit_hash(hash) {
+            hll_update(&mut self.registers, self.precision, hash);
+        }
+    }
+
+    /// Merge the registers of another sketcher into this one (the result is
+    /// the sketch of the union of both inputs).
+    pub fn merge(&mut self, other: &HyperLogLogSketcher) {
+        for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+            *r = u8::max(*r, *o);
+        }
+        self.total_kmers += other.total_kmers;
+        self.total_bases += other.total_bases;
+    }
+
+    pub fn estimate(&self) -> f64 {
+        hll_estimate(&self.registers)
+    }
+}
+
+impl SketchScheme for HyperLogLogSketcher {
+    fn process(&mut self, seq: SequenceRecord) {
+        self.total_bases += seq.seq.len() as u64;
+        let rc = seq.reverse_complement();
+        let norm_seq = seq.normalize(false);
+        let mask = self.kmer_filter.kmer_mask(
+            &norm_seq,
+            seq.qual.as_ref().map(|q| &q[..]),
+            self.kmer_length,
+        );
+        let mut hasher = KmerHasher::new(self.hash_function, self.kmer_length, self.seed);
+        for (pos, kmer, _) in norm_seq.canonical_kmers(self.kmer_length, &rc) {
+            if !mask.passes(pos) {
+                continue;
+            }
+            let hash = hasher.hash(&norm_seq, pos, kmer);
+            self.push_hashed(hash);
+        }
+    }
+
+    fn set_kmer_filter(&mut self, kmer_filter: KmerFilter) {
+        self.kmer_filter = kmer_filter;
+    }
+
+    fn total_bases_and_kmers(&self) -> (u64, u64) {
+        (self.total_bases, self.total_kmers)
+    }
+
+    fn to_vec(&self) -> Vec<KmerCount> {
+        to_kmer_counts(&self.registers)
+    }
+
+    fn sketch_size(&self) -> usize {
+        self.registers.iter().filter(|r| **r > 0).count()
+    }
+
+    fn parameters(&self) -> SketchParams {
+        SketchParams::HyperLogLog {
+            kmer_length: self.kmer_length,
+            precision: self.precision,
+            hash_seed: self.seed,
+            hash_function: self.hash_function,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn merge_from(&mut self, other: &dyn SketchScheme) -> FinchResult<()> {
+        self.merge(same_sketcher(self, other)?);
+        Ok(())
+    }
+
+    fn save_checkpoint(&self, writer: &mut dyn Write) -> FinchResult<()> {
+        write_checkpoint(writer, CheckpointRef::HyperLogLog(self))
+    }
+}
+
+/// Convert a set of registers into their sparse `KmerCount` representation
+pub fn to_kmer_counts(registers: &[u8]) -> Vec<KmerCount> {
+    registers
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| **r > 0)
+        .map(|(ix, r)| KmerCount {
+            hash: ix as ItemHash,
+            kmer: vec![],
+            count: u32::from(*r),
+            extra_count: 0,
+            label: None,
+        })
+        .collect()
+}
+
+/// Rebuild the full set of registers from a `KmerCount` representation
+pub fn to_registers(hashes: &[KmerCount], precision: u8) -> Vec<u8> {
+    let mut registers = vec![0u8; 1 << precision];
+    for kmer in hashes {
+        if let Some(r) = registers.get_mut(kmer.hash as usize) {
+            // ranks can't be larger than 65 so the cast is safe
+            *r = u8::max(*r, kmer.count as u8);
+        }
+    }
+    registers
+}
+
+/// Merge two HyperLogLog sketches by taking the maximum of each register
+pub fn merge(hashes: &[KmerCount], other: &[KmerCount], precision: u8) -> Vec<KmerCount> {
+    let mut registers = to_registers(hashes, precision);
+    for (r, o) in registers.iter_mut().zip(to_registers(other, precision)) {
+        *r = u8::max(*r, o);
+    }
+    to_kmer_counts(&registers)
+}
+
+/// Estimate the set statistics between two HyperLogLog sketches.
+///
+/// The intersection is estimated by inclusion-exclusion from the estimated
+/// sizes of each set and of their union (i.e. `|A| + |B| - |A ∪ B|`) so
+/// the estimates are fairly noisy for sets that are very different in size
+/// or that share few kmers.
+///
+/// Returns a tuple of the same form as `distance::raw_distance`:
+/// (containment of the query in the reference, jaccard index, size of
+/// intersection, size of union).
+pub fn raw_distance(
+    query_hashes: &[KmerCount],
+    ref_hashes: &[KmerCount],
+    precision: u8,
+) -> (f64, f64, u64, u64) {
+    let query_size = hll_estimate(&to_registers(query_hashes, precision));
+    let ref_size = hll_estimate(&to_registers(ref_hashes, precision));
+    let union_size = hll_estimate(&to_registers(
+        &merge(query_hashes, ref_hashes, precision),
+        precision,
+    ));
+    let common = f64::max(0., query_size + ref_size - union_size);
+
+    let containment = if ref_size == 0. {
+        0.
+    } else {
+        f64::min(1., common / ref_size)
+    };
+    let jaccard = if union_size == 0. {
+        1.
+    } else {
+        f64::min(1., common / union_size)
+    };
+    (
+        containment,
+        jaccard,
+        common.round() as u64,
+        union_size.round() as u64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kmers(start: usize, end: usize) -> Vec<Vec<u8>> {
+        (start..end)
+            .map(|i| format!("{:021}", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_hll_sketcher() {
+        let mut sketcher = HyperLogLogSketcher::new(12, 21, 0, HashFunction::MurmurHash3);
+        for kmer in kmers(0, 20_000) {
+            sketcher.push(&kmer);
+        }
+        // pushing the same kmers again shouldn't change anything
+        for kmer in kmers(0, 1_000) {
+            sketcher.push(&kmer);
+        }
+        assert!((sketcher.estimate() - 20_000.).abs() / 20_000. < 0.05);
+        assert_eq!(sketcher.total_bases_and_kmers(), (0, 21_000));
+
+        let hashes = sketcher.to_vec();
+        assert!(hashes.iter().all(|h| h.count > 0 && h.hash < 4096));
+        assert_eq!(to_registers(&hashes, 12), sketcher.registers);
+    }
+
+    #[test]
+    fn test_hll_merge_and_distance() {
+        let mut sketcher1 = HyperLogLogSketcher::new(12, 21, 0, HashFunction::MurmurHash3);
+        for kmer in kmers(0, 20_000) {
+            sketcher1.push(&kmer);
+        }
+        let mut sketcher2 = HyperLogLogSketcher::new(12, 21, 0, HashFunction::MurmurHash3);
+        for kmer in kmers(10_000, 30_000) {
+            sketcher2.push(&kmer);
+        }
+
+        let merged = merge(&sketcher1.to_vec(), &sketcher2.to_vec(), 12);
+        let mut merged_sketcher = sketcher1.clone();
+        merged_sketcher.merge(&sketcher2);
+        assert_eq!(merged, merged_sketcher.to_vec());
+        assert!((merged_sketcher.estimate() - 30_000.).abs() / 30_000. < 0.05);
+
+        let (containment, jaccard, common, total) =
+            raw_distance(&sketcher1.to_vec(), &sketcher2.to_vec(), 12);
+        assert!((jaccard - 1. / 3.).abs() < 0.05);
+        assert!((containment - 0.5).abs() < 0.05);
+        assert!((common as f64 - 10_000.).abs() / 10_000. < 0.15);
+        assert!((total as f64 - 30_000.).abs() / 30_000. < 0.05);
+
+        let (containment, jaccard, _, _) =
+            raw_distance(&sketcher1.to_vec(), &sketcher1.to_vec(), 12);
+        assert_eq!(containment, 1.);
+        assert_eq!(jaccard, 1.);
+    }
+}
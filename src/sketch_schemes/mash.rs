@@ -1,25 +1,34 @@
+use std::any::Any;
 use std::collections::{BinaryHeap, HashMap};
 use std::hash::BuildHasherDefault;
+use std::io::Write;
 use std::usize;
 
 use needletail::{Sequence, SequenceRecord};
 
-use crate::sketch_schemes::hashing::{hash_f, HashedItem, NoHashHasher};
-use crate::sketch_schemes::{ItemHash, KmerCount, SketchParams, SketchScheme};
+use crate::filtering::KmerFilter;
+use crate::sketch_schemes::hashing::{HashedItem, NoHashHasher, PackedKmer};
+use crate::sketch_schemes::{
+    same_sketcher, write_checkpoint, CheckpointRef, HashFunction, ItemHash, KmerCount, KmerHasher,
+    SketchParams, SketchScheme,
+};
+use crate::Result as FinchResult;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MashSketcher {
-    hashes: BinaryHeap<HashedItem<Vec<u8>>>,
+    hashes: BinaryHeap<HashedItem<PackedKmer>>,
     counts: HashMap<ItemHash, (u32, u32), BuildHasherDefault<NoHashHasher>>,
     kmer_length: u8,
     total_kmers: u64,
     total_bases: u64,
     size: usize,
     seed: u64,
+    hash_function: HashFunction,
+    pub(crate) kmer_filter: KmerFilter,
 }
 
 impl MashSketcher {
-    pub fn new(size: usize, kmer_length: u8, seed: u64) -> Self {
+    pub fn new(size: usize, kmer_length: u8, seed: u64, hash_function: HashFunction) -> Self {
         MashSketcher {
             hashes: BinaryHeap::with_capacity(size + 1),
             counts: HashMap::with_capacity_and_hasher(size, BuildHasherDefault::default()),
@@ -28,37 +37,70 @@ impl MashSketcher {
             total_bases: 0,
             size,
             seed,
+            hash_function,
+            kmer_filter: KmerFilter::default(),
         }
     }
 
-    #[allow(clippy::map_entry)]
     pub fn push(&mut self, kmer: &[u8], extra_count: u8) {
+        let new_hash = self.hash_function.hash(kmer, self.seed);
+        self.push_hashed(kmer, new_hash, extra_count);
+    }
+
+    /// Add a kmer that's already been hashed (with this sketcher's hash
+    /// function and seed)
+    pub fn push_hashed(&mut self, kmer: &[u8], new_hash: ItemHash, extra_count: u8) {
         self.total_kmers += 1;
-        let new_hash = hash_f(kmer, self.seed);
-        let add_hash = match self.hashes.peek() {
-            None => true,
-            Some(old_max_hash) => {
-                (new_hash <= (*old_max_hash).hash) || (self.hashes.len() < self.size)
+        if self.would_keep(new_hash) && self.kmer_filter.admit_hash(new_hash) {
+            self.add_counts(
+                new_hash,
+                || PackedKmer::new(kmer),
+                1,
+                u32::from(extra_count),
+            );
+        }
+    }
+
+    /// Merge the hashes and counts of another sketcher (with the same
+    /// parameters) into this one; because the sketch only depends on which
+    /// hashes were seen (and not on the order they were seen in), this gives
+    /// the same result as if one sketcher had processed both inputs.
+    pub fn merge(&mut self, other: &MashSketcher) {
+        for item in &other.hashes {
+            if self.would_keep(item.hash) {
+                let (count, extra_count) = other.counts[&item.hash];
+                self.add_counts(item.hash, || item.item.clone(), count, extra_count);
             }
-        };
-
-        if add_hash {
-            if self.counts.contains_key(&new_hash) {
-                let count = self.counts.entry(new_hash).or_insert((0, 0));
-                (*count) = (
-                    (*count).0.saturating_add(1),
-                    (*count).1.saturating_add(u32::from(extra_count)),
-                );
-            } else {
-                self.hashes.push(HashedItem {
-                    hash: new_hash,
-                    item: kmer.to_owned(),
-                });
-                self.counts.insert(new_hash, (1, u32::from(extra_count)));
-                if self.hashes.len() > self.size {
-                    let hash = self.hashes.pop().unwrap();
-                    let _ = self.counts.remove(&hash.hash).unwrap();
-                }
+        }
+        self.total_kmers += other.total_kmers;
+        self.total_bases += other.total_bases;
+    }
+
+    fn would_keep(&self, hash: ItemHash) -> bool {
+        match self.hashes.peek() {
+            None => true,
+            Some(old_max_hash) => (hash <= old_max_hash.hash) || (self.hashes.len() < self.size),
+        }
+    }
+
+    /// Add to the counts for a hash, inserting it (and dropping the largest
+    /// hash if the sketch is full) if it's not already in the sketch
+    fn add_counts(
+        &mut self,
+        hash: ItemHash,
+        kmer: impl FnOnce() -> PackedKmer,
+        count: u32,
+        extra_count: u32,
+    ) {
+        if let Some(counts) = self.counts.get_mut(&hash) {
+            counts.0 = counts.0.saturating_add(count);
+            counts.1 = counts.1.saturating_add(extra_count);
+        } else {
+            self.hashes.push(HashedItem { hash, item: kmer() });
+            self.counts.insert(hash, (count, extra_count));
+            if self.hashes.len() > self.size {
+                let hash = self.hashes.pop().unwrap();
+                let _ = self.counts.remove(&hash.hash).unwrap();
             }
         }
     }
@@ -68,27 +110,43 @@ impl SketchScheme for MashSketcher {
     fn process(&mut self, seq: SequenceRecord) {
         self.total_bases += seq.seq.len() as u64;
         let rc = seq.reverse_complement();
-        for (_, kmer, is_rev_complement) in
-            seq.normalize(false).canonical_kmers(self.kmer_length, &rc)
-        {
+        let norm_seq = seq.normalize(false);
+        let mask = self.kmer_filter.kmer_mask(
+            &norm_seq,
+            seq.qual.as_ref().map(|q| &q[..]),
+            self.kmer_length,
+        );
+        let mut hasher = KmerHasher::new(self.hash_function, self.kmer_length, self.seed);
+        for (pos, kmer, is_rev_complement) in norm_seq.canonical_kmers(self.kmer_length, &rc) {
+            if !mask.passes(pos) {
+                continue;
+            }
             let rc_count = if is_rev_complement { 1u8 } else { 0u8 };
-            self.push(kmer, rc_count);
+            let hash = hasher.hash(&norm_seq, pos, kmer);
+            self.push_hashed(kmer, hash, rc_count);
         }
     }
 
+    fn set_kmer_filter(&mut self, kmer_filter: KmerFilter) {
+        self.kmer_filter = kmer_filter;
+    }
+
     fn total_bases_and_kmers(&self) -> (u64, u64) {
         (self.total_bases, self.total_kmers)
     }
 
     fn to_vec(&self) -> Vec<KmerCount> {
-        let mut vec = self.hashes.clone().into_sorted_vec();
+        // sort references instead of cloning the (potentially very large)
+        // heap; kmers are only unpacked once they're leaving the sketcher
+        let mut items: Vec<_> = self.hashes.iter().collect();
+        items.sort_unstable();
 
-        let mut results = Vec::with_capacity(vec.len());
-        for item in vec.drain(..) {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
             let counts = self.counts[&item.hash];
             let new_item = KmerCount {
                 hash: item.hash,
-                kmer: item.item,
+                kmer: item.item.to_bytes(self.kmer_length),
                 count: counts.0,
                 extra_count: counts.1,
                 label: None,
@@ -98,6 +156,10 @@ impl SketchScheme for MashSketcher {
         results
     }
 
+    fn sketch_size(&self) -> usize {
+        self.hashes.len()
+    }
+
     fn parameters(&self) -> SketchParams {
         SketchParams::Mash {
             kmers_to_sketch: self.size,
@@ -105,13 +167,27 @@ impl SketchScheme for MashSketcher {
             no_strict: false,
             kmer_length: self.kmer_length,
             hash_seed: self.seed,
+            hash_function: self.hash_function,
         }
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn merge_from(&mut self, other: &dyn SketchScheme) -> FinchResult<()> {
+        self.merge(same_sketcher(self, other)?);
+        Ok(())
+    }
+
+    fn save_checkpoint(&self, writer: &mut dyn Write) -> FinchResult<()> {
+        write_checkpoint(writer, CheckpointRef::Mash(self))
+    }
 }
 
 #[test]
 fn test_minhashkmers() {
-    let mut queue = MashSketcher::new(3, 2, 42);
+    let mut queue = MashSketcher::new(3, 2, 42, HashFunction::MurmurHash3);
     queue.push(b"ca", 0);
     queue.push(b"cc", 1);
     queue.push(b"ac", 0);
@@ -130,6 +206,67 @@ fn test_minhashkmers() {
     assert_eq!(array[2].extra_count, 1);
 }
 
+#[test]
+fn test_quality_filtered_kmers() {
+    use crate::filtering::FilterParams;
+
+    let filters = FilterParams {
+        qual_filter: Some(20),
+        ..Default::default()
+    };
+    let mut sketcher = MashSketcher::new(10, 3, 42, HashFunction::MurmurHash3);
+    sketcher.set_kmer_filter(KmerFilter::new(&filters));
+    // the low-quality "T" should knock out every kmer it's in
+    sketcher.process(SequenceRecord::new(
+        b"test".to_vec().into(),
+        b"AAAATAAAA".to_vec().into(),
+        Some(b"IIII#IIII".to_vec().into()),
+    ));
+    let array = sketcher.to_vec();
+    assert_eq!(array.len(), 1);
+    assert_eq!(array[0].kmer, b"AAA");
+    assert_eq!(array[0].count, 4);
+    assert_eq!(sketcher.total_bases_and_kmers(), (9, 4));
+}
+
+#[test]
+fn test_merge() {
+    let seq = b"ACGGTACCATGACGTACGATTACAGGACTTACCGATTAGCATCGACTTAGCACGATCGAATCG";
+    let kmers: Vec<&[u8]> = seq.windows(5).collect();
+    let mut serial = MashSketcher::new(10, 5, 42, HashFunction::MurmurHash3);
+    let mut parts = vec![MashSketcher::new(10, 5, 42, HashFunction::MurmurHash3); 3];
+    for (ix, kmer) in kmers.iter().enumerate() {
+        serial.push(kmer, 0);
+        parts[ix % 3].push(kmer, 0);
+        // see some kmers more than once
+        if ix % 4 == 0 {
+            serial.push(kmer, 1);
+            parts[(ix + 1) % 3].push(kmer, 1);
+        }
+    }
+    let mut merged = parts.pop().unwrap();
+    for part in &parts {
+        merged.merge_from(part).unwrap();
+    }
+    assert_eq!(merged.to_vec(), serial.to_vec());
+    assert_eq!(
+        merged.total_bases_and_kmers(),
+        serial.total_bases_and_kmers()
+    );
+
+    // only sketchers with the same parameters can be merged
+    let other = MashSketcher::new(10, 5, 0, HashFunction::MurmurHash3);
+    assert!(merged.merge_from(&other).is_err());
+    let other = crate::sketch_schemes::scaled::ScaledSketcher::new(
+        10,
+        0.1,
+        5,
+        42,
+        HashFunction::MurmurHash3,
+    );
+    assert!(merged.merge_from(&other).is_err());
+}
+
 //#[test]
 //fn test_longer_sequence() {
 //    let mut queue = MinHashKmers::new(100, 21, 42);
@@ -1,15 +1,20 @@
 pub mod counts;
-mod hashing;
+pub(crate) mod hashing;
+pub mod hyperloglog;
 pub mod mash;
 pub mod scaled;
 
-use failure::bail;
+use std::any::Any;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use failure::{bail, format_err};
 use needletail::SequenceRecord;
 
-use crate::filtering::FilterParams;
+use crate::filtering::{ExclusionSet, FilterParams, FilterReport, KmerFilter};
 use crate::serialization::Sketch;
 use crate::Result as FinchResult;
-pub use hashing::ItemHash;
+pub use hashing::{HashFunction, ItemHash, KmerHasher};
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Serialize)]
 pub struct KmerCount {
@@ -20,11 +25,58 @@ pub struct KmerCount {
     pub label: Option<Vec<u8>>,
 }
 
-pub trait SketchScheme {
+pub trait SketchScheme: Send {
     fn process(&mut self, seq: SequenceRecord);
+
+    /// Sketch a bare sequence (e.g. from a BAM file, a database or a
+    /// string) instead of a parsed FASTA/Q record
+    fn process_seq(&mut self, seq: &[u8]) {
+        self.process(SequenceRecord::new(
+            Cow::Borrowed(b""),
+            Cow::Borrowed(seq),
+            None,
+        ));
+    }
+
+    /// Set the per-kmer (e.g. quality) filters to apply during `process`
+    fn set_kmer_filter(&mut self, kmer_filter: KmerFilter);
     fn total_bases_and_kmers(&self) -> (u64, u64);
     fn to_vec(&self) -> Vec<KmerCount>;
+    /// The number of hashes currently in the sketch (i.e. the length of
+    /// `to_vec`, before any filtering); this should be cheap to call
+    fn sketch_size(&self) -> usize;
     fn parameters(&self) -> SketchParams;
+    fn as_any(&self) -> &dyn Any;
+
+    /// Merge another sketcher of the same type and with the same parameters
+    /// into this one (e.g. to combine sketchers that each processed part of
+    /// a file); the result is the same as if this sketcher had processed
+    /// everything itself.
+    fn merge_from(&mut self, other: &dyn SketchScheme) -> FinchResult<()>;
+
+    /// Save everything about this sketcher (its hashes, counts, totals and
+    /// kmer filters) so sketching can be picked up where it left off with
+    /// `load_checkpoint`
+    fn save_checkpoint(&self, writer: &mut dyn Write) -> FinchResult<()>;
+
+    /// Filter the hashes seen so far into a sketch (e.g. to get snapshots
+    /// of a sketch that's still being added to); `filters` are applied as
+    /// they would be at the end of `sketch_stream` so this fails if there
+    /// aren't enough hashes left for the sketch (unless it's `no_strict`).
+    ///
+    /// `sketch_params` should be what the sketcher was created from (the
+    /// sketcher doesn't know e.g. the final size of a Mash sketch).
+    fn to_filtered_sketch(
+        &self,
+        name: &str,
+        sketch_params: &SketchParams,
+        filters: &FilterParams,
+    ) -> FinchResult<(Sketch, FilterReport)> {
+        if let Some((param, v1, v2)) = sketch_params.check_compatibility(&self.parameters()) {
+            bail!("Sketcher has {} {}, but sketch has {}", param, v2, v1);
+        }
+        crate::finish_sketch(self, name, "", sketch_params, filters.clone())
+    }
 
     fn to_sketch(&self) -> Sketch {
         // TODO: maybe this should be the primary teardown method for
@@ -42,10 +94,89 @@ pub trait SketchScheme {
             hashes,
             filter_params: FilterParams::default(),
             sketch_params: self.parameters(),
+            provenance: None,
+        }
+    }
+}
+
+/// Get at another sketcher as the same type as `sketcher` so they can be
+/// merged (see `SketchScheme::merge_from`)
+pub(crate) fn same_sketcher<'a, S: SketchScheme + 'static>(
+    sketcher: &S,
+    other: &'a dyn SketchScheme,
+) -> FinchResult<&'a S> {
+    match other.as_any().downcast_ref::<S>() {
+        Some(other) if other.parameters() == sketcher.parameters() => Ok(other),
+        _ => bail!("Only sketchers of the same type and with the same parameters can be merged"),
+    }
+}
+
+/// The start of every checkpoint file (the last byte is the format version)
+const CHECKPOINT_MAGIC: &[u8; 8] = b"FINCHCK1";
+
+/// A sketcher being saved as a checkpoint
+#[derive(Serialize)]
+pub(crate) enum CheckpointRef<'a> {
+    Mash(&'a mash::MashSketcher),
+    Scaled(&'a scaled::ScaledSketcher),
+    AllCounts(&'a counts::AllCountsSketcher),
+    HyperLogLog(&'a hyperloglog::HyperLogLogSketcher),
+}
+
+/// A sketcher loaded from a checkpoint (this has to match `CheckpointRef`)
+#[derive(Deserialize)]
+enum Checkpoint {
+    Mash(mash::MashSketcher),
+    Scaled(scaled::ScaledSketcher),
+    AllCounts(counts::AllCountsSketcher),
+    HyperLogLog(hyperloglog::HyperLogLogSketcher),
+}
+
+pub(crate) fn write_checkpoint(writer: &mut dyn Write, sketcher: CheckpointRef) -> FinchResult<()> {
+    writer.write_all(CHECKPOINT_MAGIC)?;
+    bincode::serialize_into(writer, &sketcher)
+        .map_err(|e| format_err!("Could not write checkpoint: {}", e))
+}
+
+impl Checkpoint {
+    fn kmer_filter_mut(&mut self) -> &mut KmerFilter {
+        match self {
+            Checkpoint::Mash(sketcher) => &mut sketcher.kmer_filter,
+            Checkpoint::Scaled(sketcher) => &mut sketcher.kmer_filter,
+            Checkpoint::AllCounts(sketcher) => &mut sketcher.kmer_filter,
+            Checkpoint::HyperLogLog(sketcher) => &mut sketcher.kmer_filter,
         }
     }
 }
 
+/// Load a sketcher saved with `SketchScheme::save_checkpoint` (including
+/// its kmer filters, so `set_kmer_filter` doesn't need to be called again)
+///
+/// Exclusion sets are too big to save in every checkpoint so only their
+/// names are kept; the set the sketcher was using has to be passed back in
+/// as `exclusion` (this fails if it's missing or a different set).
+pub fn load_checkpoint(
+    reader: &mut dyn Read,
+    exclusion: Option<&ExclusionSet>,
+) -> FinchResult<Box<dyn SketchScheme>> {
+    let mut magic = [0; 8];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| format_err!("Could not read checkpoint: {}", e))?;
+    if &magic != CHECKPOINT_MAGIC {
+        bail!("Not a finch checkpoint (or from an incompatible version of finch)");
+    }
+    let mut checkpoint: Checkpoint = bincode::deserialize_from(reader)
+        .map_err(|e| format_err!("Could not read checkpoint: {}", e))?;
+    checkpoint.kmer_filter_mut().restore_exclusion(exclusion)?;
+    Ok(match checkpoint {
+        Checkpoint::Mash(sketcher) => Box::new(sketcher),
+        Checkpoint::Scaled(sketcher) => Box::new(sketcher),
+        Checkpoint::AllCounts(sketcher) => Box::new(sketcher),
+        Checkpoint::HyperLogLog(sketcher) => Box::new(sketcher),
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum SketchParams {
     Mash {
@@ -54,16 +185,24 @@ pub enum SketchParams {
         no_strict: bool,
         kmer_length: u8,
         hash_seed: u64,
+        hash_function: HashFunction,
     },
     Scaled {
         kmers_to_sketch: usize,
         kmer_length: u8,
         scale: f64,
         hash_seed: u64,
+        hash_function: HashFunction,
     },
     AllCounts {
         kmer_length: u8,
     },
+    HyperLogLog {
+        kmer_length: u8,
+        precision: u8,
+        hash_seed: u64,
+        hash_function: HashFunction,
+    },
 }
 
 impl Default for SketchParams {
@@ -74,6 +213,7 @@ impl Default for SketchParams {
             no_strict: false,
             kmer_length: 21,
             hash_seed: 0,
+            hash_function: HashFunction::default(),
         }
     }
 }
@@ -85,26 +225,41 @@ impl SketchParams {
                 kmers_to_sketch,
                 kmer_length,
                 hash_seed,
+                hash_function,
                 ..
             } => Box::new(mash::MashSketcher::new(
                 *kmers_to_sketch,
                 *kmer_length,
                 *hash_seed,
+                *hash_function,
             )),
             SketchParams::Scaled {
                 kmers_to_sketch,
                 kmer_length,
                 scale,
                 hash_seed,
+                hash_function,
             } => Box::new(scaled::ScaledSketcher::new(
                 *kmers_to_sketch,
                 *scale,
                 *kmer_length,
                 *hash_seed,
+                *hash_function,
             )),
             SketchParams::AllCounts { kmer_length } => {
                 Box::new(counts::AllCountsSketcher::new(*kmer_length))
             }
+            SketchParams::HyperLogLog {
+                kmer_length,
+                precision,
+                hash_seed,
+                hash_function,
+            } => Box::new(hyperloglog::HyperLogLogSketcher::new(
+                *precision,
+                *kmer_length,
+                *hash_seed,
+                *hash_function,
+            )),
         }
     }
 
@@ -128,16 +283,39 @@ impl SketchParams {
             SketchParams::Mash { kmer_length, .. } => *kmer_length,
             SketchParams::Scaled { kmer_length, .. } => *kmer_length,
             SketchParams::AllCounts { kmer_length, .. } => *kmer_length,
+            SketchParams::HyperLogLog { kmer_length, .. } => *kmer_length,
         }
     }
 
     pub fn hash_info(&self) -> (&str, u16, u64, Option<f64>) {
         match self {
-            SketchParams::Mash { hash_seed, .. } => ("MurmurHash3_x64_128", 64, *hash_seed, None),
+            SketchParams::Mash {
+                hash_seed,
+                hash_function,
+                ..
+            } => (hash_function.name(), 64, *hash_seed, None),
             SketchParams::Scaled {
-                hash_seed, scale, ..
-            } => ("MurmurHash3_x64_128", 64, *hash_seed, Some(*scale)),
+                hash_seed,
+                scale,
+                hash_function,
+                ..
+            } => (hash_function.name(), 64, *hash_seed, Some(*scale)),
             SketchParams::AllCounts { .. } => ("None", 0, 0, None),
+            SketchParams::HyperLogLog {
+                hash_seed,
+                hash_function,
+                ..
+            } => (hash_function.hll_name(), 64, *hash_seed, None),
+        }
+    }
+
+    /// The function used to hash kmers (`None` for `AllCounts` sketches)
+    pub fn hash_function(&self) -> Option<HashFunction> {
+        match self {
+            SketchParams::Mash { hash_function, .. }
+            | SketchParams::Scaled { hash_function, .. }
+            | SketchParams::HyperLogLog { hash_function, .. } => Some(*hash_function),
+            SketchParams::AllCounts { .. } => None,
         }
     }
 
@@ -148,6 +326,7 @@ impl SketchParams {
                 kmers_to_sketch, ..
             } => *kmers_to_sketch,
             SketchParams::AllCounts { kmer_length, .. } => 4usize.pow(u32::from(*kmer_length)),
+            SketchParams::HyperLogLog { precision, .. } => 1 << precision,
         }
     }
 
@@ -164,8 +343,8 @@ impl SketchParams {
                     v2,
                 );
             }
-            // TODO: harmonize scaled/non-scaled sketches?
-            // TODO: harminize sketch sizes?
+            // sketches with different sizes/scales should be downsampled with
+            // `harmonize_sketches` first if they need to be comparable
             // TODO: do something with no_strict and final_size
         }
 
@@ -203,7 +382,222 @@ impl SketchParams {
                 other.hash_info().2.to_string(),
             ));
         }
+        if let (
+            SketchParams::HyperLogLog { precision: p1, .. },
+            SketchParams::HyperLogLog { precision: p2, .. },
+        ) = (self, other)
+        {
+            if p1 != p2 {
+                return Some(("HyperLogLog precision", p1.to_string(), p2.to_string()));
+            }
+        }
 
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_size() {
+        let all_params = [
+            SketchParams::default(),
+            SketchParams::Scaled {
+                kmers_to_sketch: 1000,
+                kmer_length: 5,
+                scale: 0.5,
+                hash_seed: 0,
+                hash_function: HashFunction::default(),
+            },
+            SketchParams::AllCounts { kmer_length: 5 },
+            SketchParams::HyperLogLog {
+                kmer_length: 5,
+                precision: 8,
+                hash_seed: 0,
+                hash_function: HashFunction::default(),
+            },
+        ];
+        let seqs: [&[u8]; 3] = [
+            b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT",
+            b"TTTTTTTTTTTTTTTTTTTTTTTTTTTT",
+            b"ATGGTCCCTGTAATCGTACGTCATGGTACCGTTGCAACGT",
+        ];
+        for params in &all_params {
+            let mut sketcher = params.create_sketcher();
+            let mut other = params.create_sketcher();
+            assert_eq!(sketcher.sketch_size(), 0);
+            for (ix, seq) in seqs.iter().enumerate() {
+                let record =
+                    SequenceRecord::new(b"test".to_vec().into(), seq.to_vec().into(), None);
+                if ix == 2 {
+                    other.process(record);
+                } else {
+                    sketcher.process(record);
+                }
+                assert_eq!(sketcher.sketch_size(), sketcher.to_vec().len());
+            }
+            // (the last sequence is the reverse complement of the first)
+            sketcher.merge_from(&*other).unwrap();
+            assert_eq!(sketcher.sketch_size(), sketcher.to_vec().len());
+        }
+    }
+
+    #[test]
+    fn test_process_seq() {
+        let seq = b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT";
+        let params = SketchParams::Mash {
+            kmers_to_sketch: 10,
+            final_size: 10,
+            no_strict: false,
+            kmer_length: 5,
+            hash_seed: 0,
+            hash_function: HashFunction::default(),
+        };
+        let mut from_record = params.create_sketcher();
+        from_record.process(SequenceRecord::new(
+            b"seq1 a test".to_vec().into(),
+            seq.to_vec().into(),
+            None,
+        ));
+        let mut from_seq = params.create_sketcher();
+        from_seq.process_seq(seq);
+        assert_eq!(from_seq.to_vec(), from_record.to_vec());
+        assert_eq!(from_seq.total_bases_and_kmers(), (40, 36));
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let all_params = [
+            SketchParams::Mash {
+                kmers_to_sketch: 20,
+                final_size: 10,
+                no_strict: true,
+                kmer_length: 5,
+                hash_seed: 0,
+                hash_function: HashFunction::NtHash,
+            },
+            SketchParams::Scaled {
+                kmers_to_sketch: 1000,
+                kmer_length: 5,
+                scale: 0.5,
+                hash_seed: 42,
+                hash_function: HashFunction::default(),
+            },
+            SketchParams::AllCounts { kmer_length: 3 },
+            SketchParams::HyperLogLog {
+                kmer_length: 5,
+                precision: 8,
+                hash_seed: 0,
+                hash_function: HashFunction::default(),
+            },
+        ];
+        let seqs: [&[u8]; 4] = [
+            b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT",
+            b"TTTTTTTTTTTTTTTTTTTTTTTTTTTT",
+            b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT",
+            b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT",
+        ];
+        let record =
+            |seq: &[u8]| SequenceRecord::new(b"test".to_vec().into(), seq.to_vec().into(), None);
+        // the singleton filter's state has to be saved too (and the
+        // exclusion set passed back in)
+        let mut reference = all_params[0].create_sketcher();
+        reference.process(record(seqs[0]));
+        let mut reference = reference.to_sketch();
+        reference.hashes.truncate(5);
+        let exclusion = ExclusionSet::from_sketches("host", &[reference], &all_params[0]).unwrap();
+        let filters = FilterParams {
+            bloom_filter_size: Some(1024),
+            exclusion_filter: Some(exclusion.clone()),
+            ..Default::default()
+        };
+        for params in &all_params {
+            let mut sketcher = params.create_sketcher();
+            let mut resumed = params.create_sketcher();
+            let mut used_exclusion = None;
+            if let SketchParams::Mash { .. } = params {
+                sketcher.set_kmer_filter(KmerFilter::new(&filters));
+                resumed.set_kmer_filter(KmerFilter::new(&filters));
+                used_exclusion = Some(&exclusion);
+            }
+            resumed.process(record(seqs[0]));
+            let mut checkpoint = Vec::new();
+            resumed.save_checkpoint(&mut checkpoint).unwrap();
+            if used_exclusion.is_some() {
+                assert!(load_checkpoint(&mut &checkpoint[..], None).is_err());
+                let other = ExclusionSet::from_sketches("other", &[], params).unwrap();
+                assert!(load_checkpoint(&mut &checkpoint[..], Some(&other)).is_err());
+            } else {
+                assert!(load_checkpoint(&mut &checkpoint[..], Some(&exclusion)).is_err());
+            }
+            let mut resumed = load_checkpoint(&mut &checkpoint[..], used_exclusion).unwrap();
+            assert_eq!(resumed.parameters(), sketcher.parameters());
+            for seq in &seqs[1..] {
+                resumed.process(record(seq));
+            }
+
+            for seq in &seqs {
+                sketcher.process(record(seq));
+            }
+            assert_eq!(resumed.to_vec(), sketcher.to_vec());
+            assert_eq!(
+                resumed.total_bases_and_kmers(),
+                sketcher.total_bases_and_kmers()
+            );
+        }
+
+        assert!(load_checkpoint(&mut &b"FINCHCK0"[..], None).is_err());
+        assert!(load_checkpoint(&mut &b"FINCH"[..], None).is_err());
+    }
+
+    #[test]
+    fn test_filtered_snapshots() {
+        let params = SketchParams::Mash {
+            kmers_to_sketch: 100,
+            final_size: 10,
+            no_strict: false,
+            kmer_length: 5,
+            hash_seed: 0,
+            hash_function: HashFunction::default(),
+        };
+        let mut sketcher = params.create_sketcher();
+        let filters = FilterParams {
+            filter_on: Some(true),
+            abun_filter: (Some(2), None),
+            ..Default::default()
+        };
+        let seq = b"ACGTTGCAACGGTACCATGACGTACGATTACAGGGACCAT";
+        sketcher.process(SequenceRecord::new(
+            b"test".to_vec().into(),
+            seq.to_vec().into(),
+            None,
+        ));
+        // only 6 of the kmers have been seen twice
+        assert!(sketcher
+            .to_filtered_sketch("test", &params, &filters)
+            .is_err());
+
+        sketcher.process(SequenceRecord::new(
+            b"test".to_vec().into(),
+            seq.to_vec().into(),
+            None,
+        ));
+        let (sketch, report) = sketcher
+            .to_filtered_sketch("test", &params, &filters)
+            .unwrap();
+        assert_eq!(sketch.name, "test");
+        assert_eq!(sketch.hashes.len(), 10);
+        assert!(sketch.hashes.iter().all(|h| h.count >= 2));
+        assert_eq!(report.pre_filter_size, sketcher.sketch_size());
+        assert_eq!(sketch.seq_length, 2 * seq.len() as u64);
+        // taking a snapshot doesn't change the sketcher
+        assert_eq!(sketcher.sketch_size(), report.pre_filter_size);
+
+        let other_params = SketchParams::AllCounts { kmer_length: 5 };
+        assert!(sketcher
+            .to_filtered_sketch("test", &other_params, &filters)
+            .is_err());
+    }
+}
@@ -1,15 +1,22 @@
+use std::any::Any;
 use std::collections::{BinaryHeap, HashMap};
 use std::hash::BuildHasherDefault;
+use std::io::Write;
 use std::usize;
 
 use needletail::{Sequence, SequenceRecord};
 
-use crate::sketch_schemes::hashing::{hash_f, HashedItem, NoHashHasher};
-use crate::sketch_schemes::{ItemHash, KmerCount, SketchParams, SketchScheme};
+use crate::filtering::KmerFilter;
+use crate::sketch_schemes::hashing::{HashedItem, NoHashHasher, PackedKmer};
+use crate::sketch_schemes::{
+    same_sketcher, write_checkpoint, CheckpointRef, HashFunction, ItemHash, KmerCount, KmerHasher,
+    SketchParams, SketchScheme,
+};
+use crate::Result as FinchResult;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ScaledSketcher {
-    hashes: BinaryHeap<HashedItem<Vec<u8>>>,
+    hashes: BinaryHeap<HashedItem<PackedKmer>>,
     counts: HashMap<ItemHash, (u32, u32), BuildHasherDefault<NoHashHasher>>,
     kmer_length: u8,
     total_kmers: u64,
@@ -17,10 +24,18 @@ pub struct ScaledSketcher {
     size: usize,
     max_hash: u64,
     seed: u64,
+    hash_function: HashFunction,
+    pub(crate) kmer_filter: KmerFilter,
 }
 
 impl ScaledSketcher {
-    pub fn new(size: usize, scale: f64, kmer_length: u8, seed: u64) -> Self {
+    pub fn new(
+        size: usize,
+        scale: f64,
+        kmer_length: u8,
+        seed: u64,
+        hash_function: HashFunction,
+    ) -> Self {
         let iscale = (1. / scale) as u64;
         ScaledSketcher {
             hashes: BinaryHeap::with_capacity(size),
@@ -31,33 +46,68 @@ impl ScaledSketcher {
             size,
             max_hash: u64::max_value() / iscale,
             seed,
+            hash_function,
+            kmer_filter: KmerFilter::default(),
         }
     }
 
-    #[allow(clippy::map_entry)]
     pub fn push(&mut self, kmer: &[u8], extra_count: u8) {
+        let new_hash = self.hash_function.hash(kmer, self.seed);
+        self.push_hashed(kmer, new_hash, extra_count);
+    }
+
+    /// Add a kmer that's already been hashed (with this sketcher's hash
+    /// function and seed)
+    pub fn push_hashed(&mut self, kmer: &[u8], new_hash: ItemHash, extra_count: u8) {
         self.total_kmers += 1;
-        let new_hash = hash_f(kmer, self.seed);
-
-        if new_hash <= self.max_hash || (self.hashes.len() <= self.size && self.size != 0) {
-            if self.counts.contains_key(&new_hash) {
-                let count = self.counts.entry(new_hash).or_insert((0, 0));
-                (*count) = (
-                    (*count).0.saturating_add(1),
-                    (*count).1.saturating_add(u32::from(extra_count)),
-                );
-            } else {
-                self.hashes.push(HashedItem {
-                    hash: new_hash,
-                    item: kmer.to_owned(),
-                });
-                self.counts.insert(new_hash, (1, u32::from(extra_count)));
-                if self.hashes.len() > self.size
-                    && (*self.hashes.peek().unwrap()).hash > self.max_hash
-                {
-                    let hash = self.hashes.pop().unwrap();
-                    let _ = self.counts.remove(&hash.hash).unwrap();
-                }
+        if self.would_keep(new_hash) && self.kmer_filter.admit_hash(new_hash) {
+            self.add_counts(
+                new_hash,
+                || PackedKmer::new(kmer),
+                1,
+                u32::from(extra_count),
+            );
+        }
+    }
+
+    /// Merge the hashes and counts of another sketcher (with the same
+    /// parameters) into this one; because the sketch only depends on which
+    /// hashes were seen (and not on the order they were seen in), this gives
+    /// the same result as if one sketcher had processed both inputs.
+    pub fn merge(&mut self, other: &ScaledSketcher) {
+        for item in &other.hashes {
+            if self.would_keep(item.hash) {
+                let (count, extra_count) = other.counts[&item.hash];
+                self.add_counts(item.hash, || item.item.clone(), count, extra_count);
+            }
+        }
+        self.total_kmers += other.total_kmers;
+        self.total_bases += other.total_bases;
+    }
+
+    fn would_keep(&self, hash: ItemHash) -> bool {
+        hash <= self.max_hash || (self.hashes.len() <= self.size && self.size != 0)
+    }
+
+    /// Add to the counts for a hash, inserting it (and dropping the largest
+    /// hash if it's over the maximum and not needed to fill out the sketch)
+    /// if it's not already in the sketch
+    fn add_counts(
+        &mut self,
+        hash: ItemHash,
+        kmer: impl FnOnce() -> PackedKmer,
+        count: u32,
+        extra_count: u32,
+    ) {
+        if let Some(counts) = self.counts.get_mut(&hash) {
+            counts.0 = counts.0.saturating_add(count);
+            counts.1 = counts.1.saturating_add(extra_count);
+        } else {
+            self.hashes.push(HashedItem { hash, item: kmer() });
+            self.counts.insert(hash, (count, extra_count));
+            if self.hashes.len() > self.size && self.hashes.peek().unwrap().hash > self.max_hash {
+                let hash = self.hashes.pop().unwrap();
+                let _ = self.counts.remove(&hash.hash).unwrap();
             }
         }
     }
@@ -67,27 +117,43 @@ impl SketchScheme for ScaledSketcher {
     fn process(&mut self, seq: SequenceRecord) {
         self.total_bases += seq.seq.len() as u64;
         let rc = seq.reverse_complement();
-        for (_, kmer, is_rev_complement) in
-            seq.normalize(false).canonical_kmers(self.kmer_length, &rc)
-        {
+        let norm_seq = seq.normalize(false);
+        let mask = self.kmer_filter.kmer_mask(
+            &norm_seq,
+            seq.qual.as_ref().map(|q| &q[..]),
+            self.kmer_length,
+        );
+        let mut hasher = KmerHasher::new(self.hash_function, self.kmer_length, self.seed);
+        for (pos, kmer, is_rev_complement) in norm_seq.canonical_kmers(self.kmer_length, &rc) {
+            if !mask.passes(pos) {
+                continue;
+            }
             let rc_count = if is_rev_complement { 1u8 } else { 0u8 };
-            self.push(kmer, rc_count);
+            let hash = hasher.hash(&norm_seq, pos, kmer);
+            self.push_hashed(kmer, hash, rc_count);
         }
     }
 
+    fn set_kmer_filter(&mut self, kmer_filter: KmerFilter) {
+        self.kmer_filter = kmer_filter;
+    }
+
     fn total_bases_and_kmers(&self) -> (u64, u64) {
         (self.total_bases, self.total_kmers)
     }
 
     fn to_vec(&self) -> Vec<KmerCount> {
-        let mut vec = self.hashes.clone().into_sorted_vec();
+        // sort references instead of cloning the (potentially very large)
+        // heap; kmers are only unpacked once they're leaving the sketcher
+        let mut items: Vec<_> = self.hashes.iter().collect();
+        items.sort_unstable();
 
-        let mut results = Vec::with_capacity(vec.len());
-        for item in vec.drain(..) {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
             let counts = self.counts[&item.hash];
             let new_item = KmerCount {
                 hash: item.hash,
-                kmer: item.item,
+                kmer: item.item.to_bytes(self.kmer_length),
                 count: counts.0,
                 extra_count: counts.1,
                 label: None,
@@ -97,14 +163,32 @@ impl SketchScheme for ScaledSketcher {
         results
     }
 
+    fn sketch_size(&self) -> usize {
+        self.hashes.len()
+    }
+
     fn parameters(&self) -> SketchParams {
         SketchParams::Scaled {
             kmers_to_sketch: self.size,
             kmer_length: self.kmer_length,
             scale: 1. / (u64::max_value() as f64 / self.max_hash as f64),
             hash_seed: self.seed,
+            hash_function: self.hash_function,
         }
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn merge_from(&mut self, other: &dyn SketchScheme) -> FinchResult<()> {
+        self.merge(same_sketcher(self, other)?);
+        Ok(())
+    }
+
+    fn save_checkpoint(&self, writer: &mut dyn Write) -> FinchResult<()> {
+        write_checkpoint(writer, CheckpointRef::Scaled(self))
+    }
 }
 
 #[cfg(test)]
@@ -116,7 +200,7 @@ mod test {
     #[test]
     fn test_minhashkmers_scaled_1() {
         // Scaled=1 should hold all possible kmers
-        let mut queue = ScaledSketcher::new(3, 1., 2, 42);
+        let mut queue = ScaledSketcher::new(3, 1., 2, 42, HashFunction::MurmurHash3);
         queue.push(b"ca", 0);
         queue.push(b"cc", 1);
         queue.push(b"ac", 0);
@@ -139,7 +223,7 @@ mod test {
     fn test_minhashkmers_scaled_1000() {
         // Scaled=1000 should exclude all these hashes,
         // but since only 3 are added and size==3 they should all be present
-        let mut queue = ScaledSketcher::new(3, 0.001, 2, 42);
+        let mut queue = ScaledSketcher::new(3, 0.001, 2, 42, HashFunction::MurmurHash3);
         queue.push(b"ca", 0);
         queue.push(b"cc", 1);
         queue.push(b"ac", 0);
@@ -161,7 +245,7 @@ mod test {
     #[test]
     fn test_minhashkmers_eviction() {
         // try again, but evict one of the kmers
-        let mut queue = ScaledSketcher::new(1, 0.01, 4, 42);
+        let mut queue = ScaledSketcher::new(1, 0.01, 4, 42, HashFunction::MurmurHash3);
         // random kmer that hashes above max_hash
         queue.push(b"AAAA", 0);
         // now fill with kmers that hash below to evict it
@@ -173,9 +257,32 @@ mod test {
         assert!(array.iter().all(|e| e.kmer != b"AAAA"))
     }
 
+    #[test]
+    fn test_minhashkmers_singleton_filter() {
+        use crate::filtering::FilterParams;
+
+        let filters = FilterParams {
+            bloom_filter_size: Some(1024),
+            ..Default::default()
+        };
+        let mut queue = ScaledSketcher::new(0, 1., 2, 42, HashFunction::MurmurHash3);
+        queue.set_kmer_filter(KmerFilter::new(&filters));
+        queue.push(b"ca", 0);
+        queue.push(b"cc", 1);
+        queue.push(b"ac", 0);
+        queue.push(b"ac", 1);
+        queue.push(b"ac", 1);
+        let array = queue.to_vec();
+        assert_eq!(array.len(), 1);
+        assert_eq!(array[0].kmer, b"ac");
+        // the first sighting only goes into the Bloom filter
+        assert_eq!(array[0].count, 2);
+        assert_eq!(queue.total_bases_and_kmers(), (0, 5));
+    }
+
     #[test]
     fn test_minhashkmers_pure_scaled_empty() {
-        let mut queue = ScaledSketcher::new(0, 0.001, 2, 42);
+        let mut queue = ScaledSketcher::new(0, 0.001, 2, 42, HashFunction::MurmurHash3);
         // all these hashes are out of range for scaled=1000
         queue.push(b"ca", 0);
         queue.push(b"cc", 1);
@@ -187,7 +294,7 @@ mod test {
 
     #[test]
     fn test_minhashkmers_pure_scaled() {
-        let mut queue = ScaledSketcher::new(0, 0.001, 2, 42);
+        let mut queue = ScaledSketcher::new(0, 0.001, 2, 42, HashFunction::MurmurHash3);
         // all these hashes are out of range
         queue.push(b"ca", 0);
         queue.push(b"cc", 1);
@@ -200,7 +307,7 @@ mod test {
     proptest! {
         #[test]
         fn pure_scaled_check(seq in "[ACGT]{500,}") {
-            let mut queue = ScaledSketcher::new(0, 1. / 100., 2, 42);
+            let mut queue = ScaledSketcher::new(0, 1. / 100., 2, 42, HashFunction::MurmurHash3);
             let max_hash = u64::max_value() / 100;
             for kmer in seq.as_bytes().windows(4) {
                 queue.push(kmer, 0);
@@ -208,5 +315,26 @@ mod test {
             let array = queue.to_vec();
             assert!(array.iter().all(|item| item.hash <= max_hash));
         }
+
+        #[test]
+        fn merged_matches_serial(seq in "[ACGT]{50,500}", split in 0usize..500, size in 0usize..20) {
+            let kmers: Vec<&[u8]> = seq.as_bytes().windows(3).collect();
+            let split = usize::min(split, kmers.len());
+            let new_sketcher = || ScaledSketcher::new(size, 0.1, 3, 42, HashFunction::MurmurHash3);
+            let mut serial = new_sketcher();
+            let mut first = new_sketcher();
+            let mut second = new_sketcher();
+            for (ix, kmer) in kmers.iter().enumerate() {
+                serial.push(kmer, (ix % 2) as u8);
+                if ix < split {
+                    first.push(kmer, (ix % 2) as u8);
+                } else {
+                    second.push(kmer, (ix % 2) as u8);
+                }
+            }
+            first.merge_from(&second).unwrap();
+            assert_eq!(first.to_vec(), serial.to_vec());
+            assert_eq!(first.total_bases_and_kmers(), serial.total_bases_and_kmers());
+        }
     }
 }
@@ -1,20 +1,17 @@
-use std::cmp;
+use std::collections::BTreeMap;
+use std::str::FromStr;
 
+use failure::bail;
+
+use crate::estimators::kmv;
 use crate::sketch_schemes::KmerCount;
 
+/// Estimates the number of unique kmers a sketch was drawn from.
+///
+/// This is the unbiased k-minimum value estimate; see the `estimators`
+/// module for other methods and for error bounds.
 pub fn cardinality(sketch: &[KmerCount]) -> Result<u64, &'static str> {
-    // Other (possibly more accurate) possibilities:
-    // "hyper log-log" estimate from lowest value?
-    // multiset distribution applied to total count number?
-    // "AKMV" approach: http://people.mpi-inf.mpg.de/~rgemulla/publications/beyer07distinct.pdf
-
-    // fast and simple k-minimum value estimate
-    // https://research.neustar.biz/2012/07/09/sketch-of-the-day-k-minimum-values/
-    if sketch.is_empty() {
-        return Ok(0u64);
-    }
-    Ok(((sketch.len() - 1) as f32
-        / (sketch.last().unwrap().hash as f32 / usize::max_value() as f32)) as u64)
+    Ok(kmv(sketch).estimate.round() as u64)
 }
 
 /// Generates a Vec of numbers of kmers for each coverage level
@@ -22,18 +19,95 @@ pub fn cardinality(sketch: &[KmerCount]) -> Result<u64, &'static str> {
 /// For example, a size 1000 sketch of the same genome repeated 5 times (e.g. 5x coverage) should
 /// produce a "histogram" like [0, 0, 0, 0, 1000] (assuming no repetative kmers in the genome)
 ///
+/// Note the result is as long as the highest count in the sketch (which can be billions of entries
+/// for very high copy kmers) so `sparse_hist` or `binned_hist` should be used instead. Kmers with a
+/// count of 0 are ignored.
+#[deprecated(
+    note = "the histogram is as long as the highest count; use `sparse_hist` or `binned_hist`"
+)]
 pub fn hist(sketch: &[KmerCount]) -> Vec<u64> {
-    let mut counts = vec![0u64; 65536];
-    let mut max_count: u64 = 0;
+    let max_count = sketch.iter().map(|k| k.count).max().unwrap_or(0);
+    let mut counts = vec![0u64; max_count as usize];
+    for kmer in sketch {
+        if kmer.count > 0 {
+            counts[kmer.count as usize - 1] += 1;
+        }
+    }
+    counts
+}
+
+/// Generates a map of coverage level to the number of kmers at that level
+/// (for only the levels with any kmers).
+pub fn sparse_hist(sketch: &[KmerCount]) -> BTreeMap<u32, u64> {
+    let mut counts = BTreeMap::new();
     for kmer in sketch {
-        max_count = cmp::max(max_count, u64::from(kmer.count));
-        counts[kmer.count as usize - 1] += 1;
+        if kmer.count > 0 {
+            *counts.entry(kmer.count).or_insert(0) += 1;
+        }
     }
-    counts.truncate(max_count as usize);
     counts
 }
 
+/// How to group coverage levels together for `binned_hist`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistBins {
+    /// Bins of a fixed width, i.e. [1, w], [w + 1, 2w], ...
+    Linear(u32),
+    /// Bins that double in width, i.e. [1, 1], [2, 3], [4, 7], ...
+    Log2,
+}
+
+impl FromStr for HistBins {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "log" || s == "log2" {
+            return Ok(HistBins::Log2);
+        }
+        match s.parse::<u32>() {
+            Ok(width) if width > 0 => Ok(HistBins::Linear(width)),
+            _ => bail!("bins must be `log` or a positive bin width"),
+        }
+    }
+}
+
+/// A range of coverage levels (inclusive) and the number of kmers in it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistBin {
+    pub lower: u32,
+    pub upper: u32,
+    pub count: u64,
+}
+
+/// Generates a histogram of the number of kmers in each bin of coverage
+/// levels. Only bins containing kmers are returned.
+pub fn binned_hist(sketch: &[KmerCount], bins: HistBins) -> Vec<HistBin> {
+    let mut binned: Vec<HistBin> = Vec::new();
+    for (level, count) in sparse_hist(sketch) {
+        let (lower, upper) = match bins {
+            HistBins::Linear(width) => {
+                let lower = (level - 1) / width * width + 1;
+                (lower, lower.saturating_add(width - 1))
+            }
+            HistBins::Log2 => {
+                let lower = 1u32 << (31 - level.leading_zeros());
+                (lower, lower.saturating_add(lower - 1))
+            }
+        };
+        match binned.last_mut() {
+            Some(bin) if bin.lower == lower => bin.count += count,
+            _ => binned.push(HistBin {
+                lower,
+                upper,
+                count,
+            }),
+        }
+    }
+    binned
+}
+
 #[test]
+#[allow(deprecated)]
 fn test_hist() {
     let sketch = vec![
         KmerCount {
@@ -101,3 +175,84 @@ fn test_hist() {
     assert_eq!(hist_data[2], 1);
     assert_eq!(hist_data[3], 2);
 }
+
+#[test]
+#[allow(deprecated)]
+fn test_hist_high_counts() {
+    let sketch = vec![
+        KmerCount {
+            hash: 1,
+            kmer: vec![],
+            count: 0,
+            extra_count: 0,
+            label: None,
+        },
+        KmerCount {
+            hash: 2,
+            kmer: vec![],
+            count: 100_000,
+            extra_count: 0,
+            label: None,
+        },
+        KmerCount {
+            hash: 3,
+            kmer: vec![],
+            count: u32::max_value(),
+            extra_count: 0,
+            label: None,
+        },
+    ];
+
+    let hist_data = sparse_hist(&sketch);
+    assert_eq!(hist_data.len(), 2);
+    assert_eq!(hist_data[&100_000], 1);
+    assert_eq!(hist_data[&u32::max_value()], 1);
+
+    let hist_data = hist(&sketch[..2]);
+    assert_eq!(hist_data.len(), 100_000);
+    assert_eq!(hist_data[99_999], 1);
+}
+
+#[test]
+fn test_binned_hist() {
+    let sketch: Vec<KmerCount> = [1, 2, 3, 4, 8, 9, 100, u32::max_value()]
+        .iter()
+        .enumerate()
+        .map(|(ix, count)| KmerCount {
+            hash: ix as u64,
+            kmer: vec![],
+            count: *count,
+            extra_count: 0,
+            label: None,
+        })
+        .collect();
+
+    let bins = binned_hist(&sketch, "log".parse().unwrap());
+    let ranges: Vec<(u32, u32, u64)> = bins.iter().map(|b| (b.lower, b.upper, b.count)).collect();
+    assert_eq!(
+        ranges,
+        vec![
+            (1, 1, 1),
+            (2, 3, 2),
+            (4, 7, 1),
+            (8, 15, 2),
+            (64, 127, 1),
+            (1 << 31, u32::max_value(), 1)
+        ]
+    );
+
+    let bins = binned_hist(&sketch, "5".parse().unwrap());
+    let ranges: Vec<(u32, u32, u64)> = bins.iter().map(|b| (b.lower, b.upper, b.count)).collect();
+    assert_eq!(
+        ranges,
+        vec![
+            (1, 5, 4),
+            (6, 10, 2),
+            (96, 100, 1),
+            (4_294_967_291, u32::max_value(), 1)
+        ]
+    );
+
+    assert!("0".parse::<HistBins>().is_err());
+    assert!("linear".parse::<HistBins>().is_err());
+}
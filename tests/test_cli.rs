@@ -2,7 +2,7 @@ use std::io::{BufReader, Cursor};
 use std::process::Command;
 
 use assert_cmd::prelude::*;
-use predicates::prelude::predicate;
+use predicates::prelude::{predicate, PredicateBooleanExt};
 
 use finch::serialization::{read_finch_file, read_mash_file, Sketch};
 
@@ -56,6 +56,505 @@ fn finch_sketch_bin() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn finch_sketch_dust_filter() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .args(&["--dust-filter", "2"])
+        .arg("-b")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let mut buf_reader = BufReader::new(output);
+    let sketch: Vec<Sketch> = read_finch_file(&mut buf_reader)?;
+    assert_eq!(sketch[0].filter_params.dust_filter, Some(2.));
+    assert_eq!(sketch[0].filter_params.qual_filter, None);
+    assert_eq!(sketch[0].hashes.len(), 10);
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--dust-filter", "20"])
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("dust-filter must be between"));
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_exclude() -> Result<(), Box<dyn std::error::Error>> {
+    // excluding a file's own kmers should leave nothing to sketch
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--exclude", "tests/data/query.fa"])
+        .args(&["--exclude-bloom-size", "1"])
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("too few kmers (0)"));
+
+    // scaled sketches don't need a minimum number of kmers so they're just empty
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&[
+            "--sketch-type",
+            "scaled",
+            "--scale",
+            "0.1",
+            "--n-hashes",
+            "0",
+        ])
+        .args(&["--exclude", "tests/data/query.fa"])
+        .arg("-b")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let mut buf_reader = BufReader::new(output);
+    let sketch: Vec<Sketch> = read_finch_file(&mut buf_reader)?;
+    assert_eq!(sketch[0].hashes.len(), 0);
+    assert_eq!(
+        sketch[0]
+            .filter_params
+            .exclusion_filter
+            .as_ref()
+            .unwrap()
+            .name,
+        "tests/data/query.fa"
+    );
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_filter_report() -> Result<(), Box<dyn std::error::Error>> {
+    let report_path = std::env::temp_dir().join("finch_sketch_filter_report.json");
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--filter")
+        .arg("--filter-report")
+        .arg(&report_path)
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let report: serde_json::Value = serde_json::from_reader(std::fs::File::open(&report_path)?)?;
+    assert_eq!(report[0]["name"], "tests/data/query.fa");
+    assert_eq!(report[0]["postFilterSize"], 10);
+    assert!(report[0]["preFilterSize"].as_u64().unwrap() > 10);
+    assert!(report[0]["errCutoff"]["cutoff"].is_number());
+    assert_eq!(report[0]["undersized"], false);
+    std::fs::remove_file(&report_path)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_samples() -> Result<(), Box<dyn std::error::Error>> {
+    // a "pair" of the same file should give one sketch with doubled counts
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--pair")
+        .arg("-b")
+        .arg("-O")
+        .args(&["tests/data/query.fa", "tests/data/query.fa"]);
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    assert_eq!(sketch.len(), 1);
+    assert_eq!(sketch[0].name, "tests/data/query.fa");
+    assert_eq!(sketch[0].seq_length, 2 * 399);
+    assert!(sketch[0].hashes.iter().all(|h| h.count % 2 == 0));
+
+    let sheet_path = std::env::temp_dir().join("finch_sketch_samples.tsv");
+    std::fs::write(
+        &sheet_path,
+        "# name\tfiles\nsample1\ttests/data/query.fa\ttests/data/query.fa\nsample2\ttests/data/query.fa\n",
+    )?;
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--sample-sheet")
+        .arg(&sheet_path)
+        .arg("-b")
+        .arg("-O");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    assert_eq!(sketch.len(), 2);
+    assert_eq!(sketch[0].name, "sample1");
+    assert_eq!(sketch[0].seq_length, 2 * 399);
+    assert_eq!(sketch[1].name, "sample2");
+    assert_eq!(sketch[1].seq_length, 399);
+    std::fs::remove_file(&sheet_path)?;
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .arg("--pair")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("even number"));
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_naming() -> Result<(), Box<dyn std::error::Error>> {
+    let seq_path = std::env::temp_dir().join("finch_sketch_naming.fna");
+    std::fs::write(
+        &seq_path,
+        ">NC_1.1 Test plasmid\nACGTACGGTACGATCGATCGATCGTAGCTAGCTAGCTAGCT\n",
+    )?;
+
+    let naming_args: [&[&str]; 4] = [
+        &[],
+        &["--name-by", "stem"],
+        &["--name-by", "id"],
+        &["--name-template", "{stem}_{id}"],
+    ];
+    let mut names = Vec::new();
+    for args in naming_args.iter() {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--n-hashes", "5"])
+            .args(*args)
+            .arg("-b")
+            .arg("-O")
+            .arg(&seq_path);
+        cmd.assert().success();
+        let output = Cursor::new(cmd.output().unwrap().stdout);
+        let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+        assert_eq!(sketch[0].comment, "Test plasmid");
+        names.push(sketch[0].name.clone());
+    }
+    assert_eq!(
+        names,
+        vec![
+            seq_path.to_string_lossy().into_owned(),
+            "finch_sketch_naming".to_string(),
+            "NC_1.1".to_string(),
+            "finch_sketch_naming_NC_1.1".to_string(),
+        ]
+    );
+    std::fs::remove_file(&seq_path)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_input_list_and_dirs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_input_dirs");
+    std::fs::create_dir_all(dir.join("sub"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("a.fa"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("sub").join("b.fna"))?;
+    std::fs::write(dir.join("notes.txt"), "not a sequence file")?;
+    // symlinked directories aren't followed (so loops like this are safe)
+    #[cfg(unix)]
+    {
+        let link = dir.join("sub").join("loop");
+        if std::fs::symlink_metadata(&link).is_err() {
+            std::os::unix::fs::symlink(&dir, &link)?;
+        }
+    }
+
+    // directories are searched recursively for sequence/sketch files
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("-b")
+        .arg("-O")
+        .arg(&dir);
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    assert_eq!(sketch.len(), 2);
+    assert!(sketch[0].name.ends_with("a.fa"));
+    assert!(sketch[1].name.ends_with("b.fna"));
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .args(&["--glob", "*.fna"])
+        .arg("-b")
+        .arg("-O")
+        .arg(&dir);
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    assert_eq!(sketch.len(), 1);
+    assert!(sketch[0].name.ends_with("b.fna"));
+
+    let list_path = dir.join("inputs.txt");
+    std::fs::write(
+        &list_path,
+        "# path\tname\ntests/data/query.fa\tquery\ntests/data/query.fa\n",
+    )?;
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--input-list")
+        .arg(&list_path)
+        .arg("-b")
+        .arg("-O");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    assert_eq!(sketch.len(), 2);
+    assert_eq!(sketch[0].name, "query");
+    assert_eq!(sketch[1].name, "tests/data/query.fa");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_dir_twice() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_dir_twice");
+    std::fs::create_dir_all(dir.join("sub"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("a.fa"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("sub").join("b.fna"))?;
+
+    // the sketches written into the directory the first time around
+    // shouldn't be picked up as inputs the second time
+    for extra_args in &[&[][..], &["--incremental"][..]] {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--n-hashes", "10"])
+            .args(*extra_args)
+            .arg(&dir);
+        cmd.assert().success();
+        assert!(dir.join("a.fa.sk").exists());
+        assert!(dir.join("sub").join("b.fna.sk").exists());
+        assert!(!dir.join("a.fa.sk.sk").exists());
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_out_dir_clash() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_out_dir_clash");
+    std::fs::create_dir_all(dir.join("a"))?;
+    std::fs::create_dir_all(dir.join("b"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("a").join("x.fa"))?;
+    std::fs::copy("tests/data/query.fa", dir.join("b").join("x.fa"))?;
+    let out_dir = dir.join("sketches");
+
+    // both files would be written to sketches/x.fa.sk
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .arg(dir.join("a"))
+        .arg(dir.join("b"));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("would both be written to"));
+    assert!(!out_dir.join("x.fa.sk").exists());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_incremental() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_incremental");
+    std::fs::create_dir_all(&dir)?;
+    let seq_path = dir.join("query.fa");
+    std::fs::copy("tests/data/query.fa", &seq_path)?;
+    let out_dir = dir.join("sketches");
+    let report_path = dir.join("report.json");
+
+    // the filter report lists every sample that was (re)sketched
+    let sketched_names = |args: &[&str]| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(args)
+            .arg("--incremental")
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .arg("--filter-report")
+            .arg(&report_path)
+            .arg(&seq_path);
+        cmd.assert().success();
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report_path)?)?;
+        Ok(report
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["name"].as_str().unwrap().to_string())
+            .collect())
+    };
+    assert_eq!(sketched_names(&["--n-hashes", "10"])?.len(), 1);
+    assert!(out_dir.join("query.fa.sk").exists());
+    // nothing's changed so nothing should be resketched
+    assert_eq!(sketched_names(&["--n-hashes", "10"])?.len(), 0);
+    // but adding or dropping `--no-kmers` should
+    let no_kmers = ["--n-hashes", "10", "--no-kmers"];
+    assert_eq!(sketched_names(&no_kmers)?.len(), 1);
+    assert_eq!(sketched_names(&no_kmers)?.len(), 0);
+    assert_eq!(sketched_names(&["--n-hashes", "10"])?.len(), 1);
+    // and so should changing the parameters
+    assert_eq!(sketched_names(&["--n-hashes", "20"])?.len(), 1);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_provenance() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_provenance");
+    std::fs::create_dir_all(&dir)?;
+    let seq_path = dir.join("query.fa");
+    std::fs::copy("tests/data/query.fa", &seq_path)?;
+
+    for (flag, ext) in &[("-b", ".bsk"), ("--no-filter", ".sk")] {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--n-hashes", "10"])
+            .arg(flag)
+            .arg(&seq_path);
+        cmd.assert().success();
+        let sketch_path = dir.join(format!("query.fa{}", ext));
+
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("info").arg(&sketch_path);
+        cmd.assert().success().stdout(predicate::str::contains(
+            // `sha256sum tests/data/query.fa`
+            "3abe42d7ee2fb951799ae03acc3d9804ec3848756cf2d7717961aeeed35b36ed",
+        ));
+
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("validate").arg(&sketch_path);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("query.fa OK"));
+    }
+
+    // inputs given as relative paths can still be found from another
+    // directory
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.current_dir(&dir)
+        .arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("query.fa");
+    cmd.assert().success();
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("validate").arg(dir.join("query.fa.sk"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("query.fa OK"));
+
+    // changing the input should fail validation
+    std::fs::write(&seq_path, ">id1\nACGTACGTACGTACGTACGTACGTACGTACGT\n")?;
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("validate").arg(dir.join("query.fa.sk"));
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("query.fa CHANGED"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_validate_malformed() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_validate_malformed");
+    std::fs::create_dir_all(&dir)?;
+    // hashes are out of order and one has a count of 0
+    let bad_path = dir.join("bad.sk");
+    std::fs::write(
+        &bad_path,
+        r#"{"kmer":3,"alphabet":"ACGT","preserveCase":false,"canonical":true,
+        "sketchSize":3,"hashType":"MurmurHash3_x64_128","hashBits":64,"hashSeed":0,
+        "sketches":[{"name":"bad","seqLength":10,"numValidKmers":8,"comment":"",
+        "filters":{},"hashes":["9","5","7"],"counts":[1,0,1]}]}"#,
+    )?;
+    let corrupt_path = dir.join("corrupt.sk");
+    std::fs::write(&corrupt_path, r#"{"kmer":3,"sketches":["#)?;
+    // the counts don't line up with the hashes
+    let truncated_path = dir.join("truncated.sk");
+    std::fs::write(
+        &truncated_path,
+        r#"{"kmer":3,"alphabet":"ACGT","preserveCase":false,"canonical":true,
+        "sketchSize":3,"hashType":"MurmurHash3_x64_128","hashBits":64,"hashSeed":0,
+        "sketches":[{"name":"short","seqLength":10,"numValidKmers":8,"comment":"",
+        "filters":{},"hashes":["5","7","9"],"counts":[1,1]}]}"#,
+    )?;
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("validate")
+        .arg(&bad_path)
+        .arg(&corrupt_path)
+        .arg(&truncated_path);
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "bad: INVALID (1 hash(es) out of order (first at position 1))",
+        ))
+        .stdout(predicate::str::contains(
+            "bad: INVALID (1 hash(es) with a count of 0 (first at position 1, hash 5))",
+        ))
+        .stdout(predicate::str::contains(
+            "corrupt.sk: UNREADABLE (Error parsing",
+        ))
+        .stdout(predicate::str::contains("EOF while parsing"))
+        .stdout(predicate::str::contains(
+            "truncated.sk: UNREADABLE (Error parsing",
+        ))
+        .stdout(predicate::str::contains(
+            "sketch short has 3 hashes, but 2 counts",
+        ))
+        .stderr(predicate::str::contains("3 sketch(es) failed validation"));
+
+    // and distances can't be calculated from them
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist").arg(&bad_path).arg("tests/data/query.fa");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Sketch bad is invalid"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_individual() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "10"])
+        .arg("--individual")
+        .arg("--no-strict")
+        .arg("-b")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let sketch: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    let names: Vec<&str> = sketch.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["id1", "id2", "id3"]);
+    assert_eq!(sketch.iter().map(|s| s.seq_length).sum::<u64>(), 399);
+
+    // every record would need its own Bloom filter
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .arg("--individual")
+        .args(&["--bloom-filter", "1"])
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "can't be used when sketching records individually",
+    ));
+    Ok(())
+}
+
 #[test]
 fn finch_sketch_msh() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("finch")?;
@@ -98,3 +597,327 @@ fn finch_sketch_scaled() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn finch_sketch_hyperloglog() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--sketch-type", "hyperloglog"])
+        .args(&["--hll-precision", "8"])
+        .arg("-b")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let mut buf_reader = BufReader::new(output);
+    let sketch: Vec<Sketch> = read_finch_file(&mut buf_reader)?;
+    assert_eq!(sketch.len(), 1);
+    assert_eq!(sketch[0].sketch_params.k(), 21);
+    assert_eq!(sketch[0].sketch_params.expected_size(), 256);
+    assert!(sketch[0].hashes.iter().all(|h| h.hash < 256));
+    Ok(())
+}
+
+#[test]
+fn finch_hist_bins() -> Result<(), Box<dyn std::error::Error>> {
+    // by default every depth up to the highest is listed
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("hist")
+        .args(&["--n-hashes", "10"])
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let hist: serde_json::Value = serde_json::from_reader(output)?;
+    let counts = hist["tests/data/query.fa"].as_array().unwrap();
+    assert_eq!(counts.iter().map(|c| c.as_u64().unwrap()).sum::<u64>(), 10);
+
+    // with `--sparse` only the depths with any kmers are listed
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("hist")
+        .args(&["--n-hashes", "10"])
+        .arg("--sparse")
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let hist: serde_json::Value = serde_json::from_reader(output)?;
+    let depths = hist["tests/data/query.fa"].as_object().unwrap();
+    assert_eq!(
+        depths.values().map(|c| c.as_u64().unwrap()).sum::<u64>(),
+        10
+    );
+    assert!(depths.values().all(|c| c.as_u64().unwrap() > 0));
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("hist")
+        .args(&["--n-hashes", "10"])
+        .args(&["--bins", "log"])
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let hist: serde_json::Value = serde_json::from_reader(output)?;
+    let bins = hist["tests/data/query.fa"].as_array().unwrap();
+    assert_eq!(bins[0]["lower"], 1);
+    assert_eq!(bins[0]["upper"], 1);
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("hist")
+        .args(&["--bins", "0"])
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("bins must be"));
+    Ok(())
+}
+
+#[test]
+fn finch_downsample() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_downsample");
+    std::fs::create_dir_all(&dir)?;
+    let big_path = dir.join("big.bsk");
+    let small_path = dir.join("small.bsk");
+    for (n_hashes, path) in &[("100", &big_path), ("50", &small_path)] {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--n-hashes", n_hashes])
+            .arg("--no-filter")
+            .arg("-b")
+            .arg("-o")
+            .arg(path)
+            .arg("tests/data/query.fa");
+        cmd.assert().success();
+    }
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("downsample")
+        .args(&["-n", "50"])
+        .arg("-b")
+        .arg("-O")
+        .arg(&big_path);
+    cmd.assert().success();
+    let output = Cursor::new(cmd.output().unwrap().stdout);
+    let downsampled: Vec<Sketch> = read_finch_file(&mut BufReader::new(output))?;
+    let small: Vec<Sketch> =
+        read_finch_file(&mut BufReader::new(std::fs::File::open(&small_path)?))?;
+    assert_eq!(downsampled[0].hashes, small[0].hashes);
+    assert_eq!(downsampled[0].sketch_params, small[0].sketch_params);
+
+    // sketches can't be upsampled
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("downsample").args(&["-n", "100"]).arg(&small_path);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("only has a size of 50"));
+
+    // dist compares the two at the smaller size
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist").arg(&small_path).arg(&big_path);
+    cmd.assert().success().stdout(predicate::str::contains(
+        "\"commonHashes\":50,\"totalHashes\":50",
+    ));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_dist_mixed_types() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_dist_mixed_types");
+    std::fs::create_dir_all(&dir)?;
+    let scaled_path = dir.join("scaled.sk");
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--sketch-type", "scaled"])
+        .args(&["--scale", "0.5"])
+        .args(&["--n-hashes", "10"])
+        .arg("--no-filter")
+        .arg("-o")
+        .arg(&scaled_path)
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    // the sequence is sketched with the same (scaled) parameters as the
+    // sketch file, so nothing's mixed
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist")
+        .arg("--no-filter")
+        .arg(&scaled_path)
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"jaccard\":1.0"))
+        .stdout(predicate::str::contains("mixedTypes").not());
+
+    let mash_path = dir.join("mash.sk");
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "50"])
+        .arg("--no-filter")
+        .arg("-o")
+        .arg(&mash_path)
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist").arg(&mash_path).arg(&scaled_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"jaccard\":1.0"))
+        .stdout(predicate::str::contains("\"mixedTypes\":true"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_hash_functions() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_hash_functions");
+    std::fs::create_dir_all(&dir)?;
+    for (hash, hash_type) in &[("xxhash3", "XXH3_64"), ("nthash", "ntHash")] {
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--hash", hash])
+            .args(&["--n-hashes", "100"])
+            .arg("--no-filter")
+            .arg("-O")
+            .arg("tests/data/query.fa");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "\"hashType\":\"{}\"",
+                hash_type
+            )));
+
+        // the hash function survives a round trip through the binary format
+        let path = dir.join(format!("{}.bsk", hash));
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("sketch")
+            .args(&["--hash", hash])
+            .args(&["--n-hashes", "100"])
+            .arg("--no-filter")
+            .arg("-b")
+            .arg("-o")
+            .arg(&path)
+            .arg("tests/data/query.fa");
+        cmd.assert().success();
+        let mut cmd = Command::cargo_bin("finch")?;
+        cmd.arg("dist")
+            .args(&["--hash", hash])
+            .arg("--no-filter")
+            .arg(&path)
+            .arg("tests/data/query.fa");
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("\"jaccard\":1.0"));
+    }
+
+    // hashes from different functions can't be compared
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist")
+        .arg(dir.join("xxhash3.bsk"))
+        .arg(dir.join("nthash.bsk"));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("has hash type ntHash"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_sketch_no_kmers() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join("finch_sketch_no_kmers");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("query.bsk");
+    let stripped_path = dir.join("stripped.bsk");
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "100"])
+        .arg("--no-filter")
+        .arg("-b")
+        .arg("-o")
+        .arg(&path)
+        .arg("tests/data/query.fa");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--n-hashes", "100"])
+        .arg("--no-filter")
+        .arg("--no-kmers")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"hashes\""))
+        .stdout(predicate::str::contains("\"kmers\"").not());
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("strip")
+        .arg("-b")
+        .arg("-o")
+        .arg(&stripped_path)
+        .arg(&path);
+    cmd.assert().success();
+    let sketches: Vec<Sketch> = read_finch_file(&mut BufReader::new(std::fs::File::open(&path)?))?;
+    let stripped: Vec<Sketch> =
+        read_finch_file(&mut BufReader::new(std::fs::File::open(&stripped_path)?))?;
+    assert!(sketches[0].has_kmers());
+    assert!(!stripped[0].has_kmers());
+    assert_eq!(stripped[0].len(), sketches[0].len());
+    assert!(std::fs::metadata(&stripped_path)?.len() < std::fs::metadata(&path)?.len());
+
+    // distances don't need kmers...
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("dist").arg(&path).arg(&stripped_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"jaccard\":1.0"));
+
+    // ...but the GC content estimate does
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("info").arg(&stripped_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Estimated % GC: unknown"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn finch_threads_and_quiet() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("sketch")
+        .args(&["--threads", "2"])
+        .arg("--quiet")
+        .args(&["--n-hashes", "100"])
+        .arg("--no-filter")
+        .arg("-O")
+        .arg("tests/data/query.fa");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"hashes\""))
+        .stderr(predicate::str::is_empty());
+
+    // `--threads` can also go before the subcommand
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.args(&["-t", "1"])
+        .arg("dist")
+        .args(&["--n-hashes", "100"])
+        .arg("--no-filter")
+        .arg("tests/data/query.fa")
+        .arg("tests/data/query.fa");
+    cmd.assert().success().stderr(predicate::str::is_empty());
+
+    let mut cmd = Command::cargo_bin("finch")?;
+    cmd.arg("info")
+        .args(&["--threads", "0"])
+        .arg("tests/data/query.fa");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "threads must be a positive integer",
+    ));
+
+    Ok(())
+}